@@ -14,24 +14,24 @@ $loop
             addi t0 t0 #680
             add t1 s0 t0
             sltu t2 t1 s0
-            addi s0 t1 #0
+            mv s0 t1
             add s1 s1 t2
 
-            addi a0 s0 #0
-            addi a1 s1 #0
+            mv a0 s0
+            mv a1 s1
             jal ra delay_time
 
-            addi a0 s1 #0
+            mv a0 s1
             jal ra write_hex_u32
             addi a0 x0 #27
             jal ra write
-            addi a0 s0 #0
+            mv a0 s0
             jal ra write_hex_u32
             jal ra crlf
 
-            jal x0 loop
+            j loop
 
-            jal x0 shutdown
+            j shutdown
 
 
 $shutdown
@@ -61,7 +61,7 @@ $write_hex_u32
             sw ra sp -#8
             sw s0 sp -#4
 
-            addi s0 a0 #0
+            mv s0 a0
 
             srli a0 s0 #10
             jal ra write_hex_u16
@@ -82,7 +82,7 @@ $write_hex_u16
             sw ra sp -#8
             sw s0 sp -#4
 
-            addi s0 a0 #0
+            mv s0 a0
 
             srli a0 s0 #C
             jal ra u4_to_hex
@@ -135,20 +135,20 @@ $write
             fence io i
             lb t2 t0 #5
             and t2 t2 t1
-            beq t2 x0 -#8
+            beqz t2 -#8
             fence i o
             sb a0 t0 #0
             ret
 
 
 $whatever
-            csrrc a0 x0 #C01 ; time
+            csrrc a0 x0 time
             jal ra write_hex_u32
             addi a0 x0 #0D
             jal ra write
             addi a0 x0 #0A
             jal ra write
 
-            jal x0 whatever
+            j whatever
 
             inval
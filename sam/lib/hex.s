@@ -0,0 +1,79 @@
+.global write_hex_u64 write_hex_u32 write_hex_u16
+.extern write
+
+$write_hex_u64
+            addi sp sp #8
+            sw ra sp -#8
+            sw s0 sp -#4
+
+            mv s0 a0
+            mv a0 a1
+            jal ra write_hex_u32
+            addi a0 x0 #27 ; '
+            jal ra write
+            mv a0 s0
+            jal ra write_hex_u32
+
+            lw ra sp -#8
+            lw s0 sp -#4
+            addi sp sp -#8
+            ret
+
+;;;
+$write_hex_u32
+            addi sp sp #8
+            sw ra sp -#8
+            sw s0 sp -#4
+
+            mv s0 a0
+
+            srli a0 s0 #10
+            jal ra write_hex_u16
+            addi a0 x0 #27 ; '
+            jal ra write
+            slli a0 s0 #10
+            srli a0 a0 #10
+            jal ra write_hex_u16
+
+            lw s0 sp -#4
+            lw ra sp -#8
+            addi sp sp -#8
+            ret
+
+;;;
+$write_hex_u16
+            addi sp sp #8
+            sw ra sp -#8
+            sw s0 sp -#4
+
+            mv s0 a0
+
+            srli a0 s0 #C
+            jal ra u4_to_hex
+            jal ra write
+            srli a0 s0 #8
+            andi a0 a0 #F
+            jal ra u4_to_hex
+            jal ra write
+            srli a0 s0 #4
+            andi a0 a0 #F
+            jal ra u4_to_hex
+            jal ra write
+            andi a0 s0 #F
+            jal ra u4_to_hex
+            jal ra write
+
+            lw s0 sp -#4
+            lw ra sp -#8
+            addi sp sp -#8
+            ret
+
+;;;
+$u4_to_hex
+            addi t0 x0 #A
+            blt a0 t0 lt_A
+            addi a0 a0 #37
+            ret
+$lt_A
+            addi a0 a0 #30
+            ret
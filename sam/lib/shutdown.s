@@ -0,0 +1,8 @@
+.global shutdown
+
+$shutdown
+            lui t1 #100
+            li t0 #5555
+            sw t0 t1 #0
+            wfi
+            j -#4
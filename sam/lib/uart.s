@@ -0,0 +1,97 @@
+.global write crlf write_str write_format
+.extern write_hex_u32
+
+$crlf
+            addi sp sp #4
+            sw ra sp -#4
+
+            addi a0 x0 #0D ; CR
+            jal ra write
+            addi a0 x0 #0A ; LF
+            jal ra write
+
+            lw ra sp -#4
+            addi sp sp -#4
+            ret
+
+;;;
+$write
+            lui t0 #1000'0
+            addi t1 x0 #20
+            lb t2 t0 #5
+            and t2 t2 t1
+            beqz t2 -#8
+            sb a0 t0 #0
+            ret
+
+;;;
+$write_str
+            addi sp sp #10
+            sw ra sp -#4
+            sw s0 sp -#8
+            sw s1 sp -#C
+            sw s2 sp -#10
+
+            mv s0 a0 ; len
+            mv s1 a1 ; string
+            add s2 a1 a0 ; string end
+            la ra write_str_loop
+$write_str_loop
+            beq s1 s2 write_str_done
+            lb a0 s1 #0
+            addi s1 s1 #1
+            j write
+
+$write_str_done
+            lw ra sp -#4
+            lw s0 sp -#8
+            lw s1 sp -#C
+            lw s2 sp -#10
+            addi sp sp -#10
+            ret
+
+;;;
+$write_format
+            addi sp sp #30
+            sw ra sp -#4
+            sw s0 sp -#8
+            sw s1 sp -#C
+            sw s2 sp -#10
+            sw s3 sp -#14
+            sw s4 sp -#18
+
+            mv s0 a0 ; len
+            mv s1 a1 ; string
+            add s2 a1 a0 ; string end
+            addi s3 x0 #25 ; %
+
+            addi s4 sp -#1C
+            sw a2 sp -#1C
+            sw a3 sp -#20
+            sw a4 sp -#24
+            sw a5 sp -#28
+            sw a6 sp -#2C
+            sw a7 sp -#30
+
+            la ra write_format_loop
+$write_format_loop
+            beq s1 s2 write_format_done
+            lb a0 s1 #0
+            addi s1 s1 #1
+            beq a0 s3 write_format_placeholder
+            j write
+
+$write_format_placeholder
+            lw a0 s4 #0
+            addi s4 s4 -#4
+            j write_hex_u32
+
+$write_format_done
+            lw ra sp -#4
+            lw s0 sp -#8
+            lw s1 sp -#C
+            lw s2 sp -#10
+            lw s3 sp -#14
+            lw s4 sp -#18
+            addi sp sp -#30
+            ret
@@ -1,3 +1,4 @@
+.extern crlf shutdown write write_hex_u16 write_hex_u32
 ; Run this with:
 ; -device virtio-serial-device
 ; -chardev file,id=out,path=out
@@ -10,50 +11,50 @@ $start
 
             ; set up interrupt stack pointer
             lui t0 #8001'0
-            csrrw x0 t0 #340 ; mscratch
+            csrw t0 mscratch
 
             ; set up mtvec
             lui t0 #8000'0 ; #8000'0030 vectored
             addi t0 t0 #031
-            csrrw x0 t0 #305 ; mtvec
-            csrrs t1 x0 #305
+            csrw t0 mtvec
+            csrr t1 mtvec
             bne t0 t1 bad_mtvec
 
-            jal x0 main
+            j main
 
             nop
             nop
             nop
 
 $vec_table ; #8000'0030
-            jal x0 exception ; no user soft interrupts rn
-            jal x0 supervisor_soft_int
-            jal x0 unknown_int
-            jal x0 machine_soft_int
-
-            jal x0 user_timer_int
-            jal x0 supervisor_timer_int
-            jal x0 unknown_int
-            jal x0 machine_timer_int
-
-            jal x0 user_ext_int
-            jal x0 supervisor_ext_int
-            jal x0 unknown_int
-            jal x0 machine_ext_int
+            j exception ; no user soft interrupts rn
+            j supervisor_soft_int
+            j unknown_int
+            j machine_soft_int
+
+            j user_timer_int
+            j supervisor_timer_int
+            j unknown_int
+            j machine_timer_int
+
+            j user_ext_int
+            j supervisor_ext_int
+            j unknown_int
+            j machine_ext_int
 $vec_table_end
 
 $bad_mtvec
-            addi s0 t1 #0
+            mv s0 t1
 
             addi a0 x0 #40 ; @
             jal ra write
             addi a0 x0 #76 ; v
             jal ra write
-            addi a0 s0 #0
+            mv a0 s0
             jal ra write_hex_u32
             jal ra crlf
 
-            jal x0 shutdown
+            j shutdown
 
 $main
             lui s0 #1000'1
@@ -74,7 +75,7 @@ $loop
 
 $loop_good
             lw a0 s0 #8 ; DeviceID
-            beq a0 x0 loop_next ; no device present
+            beqz a0 loop_next ; no device present
 
             slli a0 s0 #10
             srli a0 a0 #1C
@@ -116,11 +117,11 @@ $loop_next
             add s0 s0 t0
             bltu s0 s1 loop
 
-            jal x0 shutdown
+            j shutdown
 
 ;;;
 $exception
-            csrrw sp sp #340 ; mscratch
+            csrrw sp sp mscratch
             addi sp sp #40
 
             sw ra sp -#40
@@ -141,31 +142,31 @@ $exception
             sw a7 sp -#04
 
             ; branch to mcause handler
-            csrrs t0 x0 #342 ; mcause
+            csrr t0 mcause
             slli t0 t0 #2
             auipc t1 #0
             add t0 t0 t1
             jalr x0 t0 #C
 
-            jal x0 die_from_exception ; instruction address misaligned
-            jal x0 die_from_exception ; instruction access fault
-            jal x0 die_from_exception ; illegal instruction
-            jal x0 die_from_exception ; breakpoint
+            j die_from_exception ; instruction address misaligned
+            j die_from_exception ; instruction access fault
+            j die_from_exception ; illegal instruction
+            j die_from_exception ; breakpoint
 
-            jal x0 die_from_exception ; load address misaligned
-            jal x0 die_from_exception ; load access fault
-            jal x0 die_from_exception ; store/AMO address misaligned
-            jal x0 die_from_exception ; store/AMO access fault
+            j die_from_exception ; load address misaligned
+            j die_from_exception ; load access fault
+            j die_from_exception ; store/AMO address misaligned
+            j die_from_exception ; store/AMO access fault
 
-            jal x0 die_from_exception ; ecall from user mode
-            jal x0 die_from_exception ; ecall from supervisor mode
-            jal x0 die_from_exception ; (reserved)
-            jal x0 die_from_exception ; ecall from machine mode
+            j die_from_exception ; ecall from user mode
+            j die_from_exception ; ecall from supervisor mode
+            j die_from_exception ; (reserved)
+            j die_from_exception ; ecall from machine mode
 
-            jal x0 die_from_exception ; instruction page fault
-            jal x0 die_from_exception ; load page fault
-            jal x0 die_from_exception ; (reserved)
-            jal x0 die_from_exception ; store/AMO page fault
+            j die_from_exception ; instruction page fault
+            j die_from_exception ; load page fault
+            j die_from_exception ; (reserved)
+            j die_from_exception ; store/AMO page fault
 
 $exception_end
             lw ra sp -#40
@@ -186,7 +187,7 @@ $exception_end
             lw a7 sp -#04
 
             addi sp sp -#40
-            csrrw sp sp #340 ; mscratch
+            csrrw sp sp mscratch
             mret
 
 $die_from_exception
@@ -197,10 +198,10 @@ $die_from_exception
             jal ra write
             addi a0 x0 #23 ; #
             jal ra write
-            csrrs a0 x0 #342 ; mcause
+            csrr a0 mcause
             jal ra write_hex_u32
             jal ra crlf
-            jal x0 shutdown
+            j shutdown
 
 ;;;
 $supervisor_soft_int
@@ -218,7 +219,7 @@ $machine_ext_int
 
             jal ra crlf
 
-            jal x0 shutdown
+            j shutdown
 
 ;;;
 $unknown_int
@@ -226,193 +227,8 @@ $unknown_int
             jal ra write
             addi a0 x0 #3F ; ?
             jal ra write
-            csrrs a0 x0 #342 ; mcause
+            csrr a0 mcause
             jal ra write_hex_u32
             jal ra crlf
 
-            jal x0 shutdown
-
-;;;
-$shutdown
-            lui t1 #100
-            lui t0 #5 ; #5555
-            addi t0 t0 #555
-            sw t0 t1 #0
-            wfi
-            jal x0 -#4
-
-;;;
-$write_hex_u64
-            addi sp sp #8
-            sw ra sp -#8
-            sw s0 sp -#4
-
-            addi s0 a0 #0
-            addi a0 a1 #0
-            jal ra write_hex_u32
-            addi a0 x0 #27 ; '
-            jal ra write
-            addi a0 s0 #0
-            jal ra write_hex_u32
-
-            lw ra sp -#8
-            lw s0 sp -#4
-            addi sp sp -#8
-            ret
-
-;;;
-$write_hex_u32
-            addi sp sp #8
-            sw ra sp -#8
-            sw s0 sp -#4
-
-            addi s0 a0 #0
-
-            srli a0 s0 #10
-            jal ra write_hex_u16
-            addi a0 x0 #27 ; '
-            jal ra write
-            slli a0 s0 #10
-            srli a0 a0 #10
-            jal ra write_hex_u16
-
-            lw s0 sp -#4
-            lw ra sp -#8
-            addi sp sp -#8
-            ret
-
-;;;
-$write_hex_u16
-            addi sp sp #8
-            sw ra sp -#8
-            sw s0 sp -#4
-
-            addi s0 a0 #0
-
-            srli a0 s0 #C
-            jal ra u4_to_hex
-            jal ra write
-            srli a0 s0 #8
-            andi a0 a0 #F
-            jal ra u4_to_hex
-            jal ra write
-            srli a0 s0 #4
-            andi a0 a0 #F
-            jal ra u4_to_hex
-            jal ra write
-            andi a0 s0 #F
-            jal ra u4_to_hex
-            jal ra write
-
-            lw s0 sp -#4
-            lw ra sp -#8
-            addi sp sp -#8
-            ret
-
-;;;
-$u4_to_hex
-            addi t0 x0 #A
-            blt a0 t0 lt_A
-            addi a0 a0 #37
-            ret
-$lt_A
-            addi a0 a0 #30
-            ret
-
-;;;
-$crlf
-            addi sp sp #4
-            sw ra sp -#4
-
-            addi a0 x0 #0D ; CR
-            jal ra write
-            addi a0 x0 #0A ; LF
-            jal ra write
-
-            lw ra sp -#4
-            addi sp sp -#4
-            ret
-
-;;;
-$write
-            lui t0 #1000'0
-            addi t1 x0 #20
-            lb t2 t0 #5
-            and t2 t2 t1
-            beq t2 x0 -#8
-            sb a0 t0 #0
-            ret
-
-;;;
-$write_str
-            addi sp sp #10
-            sw ra sp -#4
-            sw s0 sp -#8
-            sw s1 sp -#C
-            sw s2 sp -#10
-
-            addi s0 a0 #0 ; len
-            addi s1 a1 #0 ; string
-            add s2 a1 a0 ; string end
-            auipc ra #0
-            addi ra ra #8
-$write_str_loop
-            beq s1 s2 write_str_done
-            lb a0 s1 #0
-            addi s1 s1 #1
-            jal x0 write
-
-$write_str_done
-            lw ra sp -#4
-            lw s0 sp -#8
-            lw s1 sp -#C
-            lw s2 sp -#10
-            addi sp sp -#10
-            ret
-
-;;;
-$write_format
-            addi sp sp #30
-            sw ra sp -#4
-            sw s0 sp -#8
-            sw s1 sp -#C
-            sw s2 sp -#10
-            sw s3 sp -#14
-            sw s4 sp -#18
-
-            addi s0 a0 #0 ; len
-            addi s1 a1 #0 ; string
-            add s2 a1 a0 ; string end
-            addi s3 x0 #25 ; %
-
-            addi s4 sp -#1C
-            sw a2 sp -#1C
-            sw a3 sp -#20
-            sw a4 sp -#24
-            sw a5 sp -#28
-            sw a6 sp -#2C
-            sw a7 sp -#30
-
-            auipc ra #0
-            addi ra ra #8
-$write_format_loop
-            beq s1 s2 write_format_done
-            lb a0 s1 #0
-            addi s1 s1 #1
-            beq a0 s3 write_format_placeholder
-            jal x0 write
-
-$write_format_placeholder
-            lw a0 s4 #0
-            addi s4 s4 -#4
-            jal x0 write_hex_u32
-
-$write_format_done
-            lw ra sp -#4
-            lw s0 sp -#8
-            lw s1 sp -#C
-            lw s2 sp -#10
-            lw s3 sp -#14
-            lw s4 sp -#18
-            addi sp sp -#30
-            ret
+            j shutdown
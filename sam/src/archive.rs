@@ -0,0 +1,109 @@
+//! Archives of sam objects, which sar makes and slink takes members from. See
+//! docs/archive-format.txt.
+
+use crate::{read_len_prefixed_str, read_u32, read_u8, write_len_prefixed_str};
+use crate::{DeserializationError, Object};
+use std::io::{self, prelude::*, Cursor};
+
+// d42864c8-4d0b-4494-94cd-c4b6c5d6149d
+pub const ARCHIVE_MAGIC: [u8; 16] = [
+    0xd4, 0x28, 0x64, 0xc8, 0x4d, 0x0b, 0x44, 0x94,
+    0x94, 0xcd, 0xc4, 0xb6, 0xc5, 0xd6, 0x14, 0x9d,
+];
+
+/// A whole archive, read into memory. Each member is an object with the name it was added as.
+#[derive(Default)]
+pub struct Archive {
+    pub members: Vec<(String, Object)>,
+}
+
+impl Archive {
+    pub fn deserialize(mut reader: impl Read) -> Result<Self, DeserializationError> {
+        let mut magic = [0; 16];
+        reader.read_exact(&mut magic)?;
+        if magic != ARCHIVE_MAGIC {
+            return Err(DeserializationError::BadArchiveMagic);
+        }
+        match read_u8(&mut reader)? {
+            0 => (),
+            n => return Err(DeserializationError::ReservedValue(
+                format!("can't understand archive version {}", n)
+            )),
+        }
+        reader.read_exact(&mut [0; 3])?;
+
+        let count = read_u32(&mut reader)?;
+        let mut members = Vec::new();
+        for _ in 0..count {
+            let name = read_len_prefixed_str(&mut reader)?;
+            let len = read_u32(&mut reader)?;
+            let mut buf = vec![0; len as usize];
+            reader.read_exact(&mut buf)?;
+            reader.read_exact(&mut vec![0; len.wrapping_neg() as usize & 0b11])?;
+            members.push((name, Object::deserialize(Cursor::new(buf))?));
+        }
+        Ok(Archive { members })
+    }
+
+    pub fn serialize(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        writer.write_all(&[0; 4])?; // version 0, then reserved
+        writer.write_all(&(self.members.len() as u32).to_le_bytes())?;
+        for (name, object) in &self.members {
+            let mut buf = Vec::new();
+            object.serialize(&mut buf)?;
+            write_len_prefixed_str(&mut writer, name)?;
+            writer.write_all(&(buf.len() as u32).to_le_bytes())?;
+            writer.write_all(&buf)?;
+            writer.write_all(&vec![0; buf.len().wrapping_neg() & 0b11])?;
+        }
+        Ok(())
+    }
+
+    /// Whether a file starts like an archive rather than an object. Leaves the reader wherever
+    /// reading the magic left it.
+    pub fn is_archive(mut reader: impl Read) -> io::Result<bool> {
+        let mut magic = [0; 16];
+        reader.read_exact(&mut magic)?;
+        Ok(magic == ARCHIVE_MAGIC)
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    use crate::{StringTable, SymbolTable, SymbolValue};
+
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut symbols: SymbolTable = Default::default();
+    let name_index = strings.get_index_or_insert("write");
+    let type_index = strings.get_index_or_insert("");
+    symbols.insert(name_index, SymbolValue::Code { external: true, type_index, offset: Some(4) });
+    let object = Object {
+        load_address: 0x8000_0000,
+        extensions: 0,
+        code_and_data: vec![0x13, 0, 0, 0, 0x82, 0x80],
+        strings,
+        symbols,
+        relocations: Default::default(),
+    };
+    let archive = Archive { members: vec![("uart.sam".to_owned(), object)] };
+
+    let mut buf = Vec::new();
+    archive.serialize(&mut buf).unwrap();
+    assert!(Archive::is_archive(&buf[..]).unwrap());
+    let archive = Archive::deserialize(&buf[..]).unwrap();
+    assert_eq!(archive.members.len(), 1);
+    let (name, object) = &archive.members[0];
+    assert_eq!(name, "uart.sam");
+    // Objects pad their code and data out to a word.
+    assert_eq!(object.code_and_data, [0x13, 0, 0, 0, 0x82, 0x80, 0, 0]);
+    let sym = &object.symbols.symbols[0];
+    assert_eq!(sym.name(&object.strings), "write");
+    assert!(sym.is_external() && sym.is_defined());
+
+    assert!(matches!(
+        Archive::deserialize(&[0; 24][..]),
+        Err(DeserializationError::BadArchiveMagic),
+    ));
+}
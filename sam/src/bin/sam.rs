@@ -1,17 +1,23 @@
 use sam::{
+    elf,
     from_hex,
     ouch,
+    EXT_A,
+    EXT_C,
+    EXT_M,
+    Object,
+    parse_csr,
     parse_reg,
+    read_u16,
     read_u32,
     Relocation,
     RelocationTable,
     RelocationValue,
     StringTable,
-    Symbol,
     SymbolTable,
     SymbolValue,
+    rvc,
     u32_to_hex,
-    upper_imm20_to_hex,
 };
 use std::collections::{HashMap};
 use std::error::Error;
@@ -69,10 +75,24 @@ enum InsnType {
     J,
     Sxli,
     F, // fence
+    Rvc, // c.*, with operands that depend on the mnemonic
+    A, // amo*.w and sc.w: rd rs2 (rs1)
+    Lr, // lr.w: rd (rs1)
     C, // csr??
     Ci, // csr??i
 }
 
+/// Splits an atomic instruction's .aq/.rl/.aqrl suffix off its mnemonic and returns the aq and rl
+/// bits it sets.
+fn split_ordering(mnemonic: &str) -> (&str, u32) {
+    for &(suffix, bits) in &[(".aqrl", 0b11), (".aq", 0b10), (".rl", 0b01)] {
+        if let Some(base) = mnemonic.strip_suffix(suffix) {
+            return (base, bits << 25);
+        }
+    }
+    (mnemonic, 0)
+}
+
 fn parse_pred_succ(s: &str) -> Result<u32, String> {
     let mut n = 0;
     for c in s.chars() {
@@ -107,20 +127,26 @@ enum AssemblerError {
         line_num: usize, // 0-based
         inner: io::Error,
     },
+    UndefinedLabel {
+        label: String,
+    },
 }
 
 impl Display for AssemblerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
-        match self {
-            &AssemblerError::Syntax {
+        match *self {
+            AssemblerError::Syntax {
                 line_num, col_num, ref msg
             } => write!(f, "{}:{}: {}", line_num + 1, col_num + 1, msg),
-            &AssemblerError::DuplicateLabel {
+            AssemblerError::DuplicateLabel {
                 line_num, col_num, ref label
             } => write!(f, "{}:{}: duplicate definition of label {:?}", line_num + 1, col_num + 1, label),
-            &AssemblerError::Write {
+            AssemblerError::Write {
                 line_num, ref inner
             } => write!(f, "{}: {}", line_num + 1, inner),
+            AssemblerError::UndefinedLabel {
+                ref label
+            } => write!(f, "undefined label {:?} (.extern it if another object defines it)", label),
         }
     }
 }
@@ -132,7 +158,7 @@ fn skip_while<I: Iterator, F: Fn(&I::Item) -> bool>(
     f: F,
 ) -> usize {
     let mut count = 0;
-    while iter.peek().map_or(false, &f) {
+    while iter.peek().is_some_and(&f) {
         iter.next();
         count += 1;
     }
@@ -153,7 +179,7 @@ fn collect_while<I: Iterator, F: Fn(&I::Item) -> Option<char>>(
         iter.next();
         count += 1;
     }
-    return (count, s);
+    (count, s)
 }
 
 fn skip_whitespace<I: Iterator<Item = (usize, char)>>(
@@ -190,6 +216,63 @@ fn is_identifier(s: &str) -> bool {
     has_non_numeric
 }
 
+/// Splits a branch or jump target into a label, if it has one, and a number that has to fit in
+/// width bits. The target is a number, a label, or a label plus or minus a number like `loop+#8`,
+/// where the number goes in the instruction for the relocation to add to.
+fn split_target(s: &str, width: u32) -> Result<(Option<&str>, u32), String> {
+    if !is_identifier(s) {
+        return Ok((None, from_hex(s, width)?));
+    }
+    match s.rfind(['+', '-']) {
+        Some(i) if s[i + 1..].starts_with('#') && is_identifier(&s[..i]) => {
+            let addend = s[i..].strip_prefix('+').unwrap_or(&s[i..]);
+            Ok((Some(&s[..i]), from_hex(addend, width)?))
+        },
+        _ => Ok((Some(s), 0)),
+    }
+}
+
+/// Splits an operand like `%hi(label)` into the operator and the label.
+fn split_operator(s: &str) -> Option<(&str, &str)> {
+    let (operator, rest) = s.strip_prefix('%')?.split_once('(')?;
+    Some((operator, rest.strip_suffix(')')?))
+}
+
+/// Makes the labels at offset data labels, since a data directive comes right after them. Every
+/// label starts out as a code label because instructions usually follow.
+fn mark_data(symbols: &mut SymbolTable, offset: u32) {
+    for sym in &mut symbols.symbols {
+        if let SymbolValue::Code { external, type_index, offset: Some(at) } = sym.value {
+            if at == offset {
+                sym.value = SymbolValue::Data { external, type_index, offset: Some(at) };
+            }
+        }
+    }
+}
+
+/// Writes a 32-bit instruction, or its 16-bit form if compress is set and it has one. Returns how
+/// many bytes it wrote.
+fn write_insn(
+    code_and_data: &mut impl Write,
+    insn: u32,
+    compress: bool,
+    extensions: &mut u32,
+    line_num: usize,
+) -> Result<u32, AssemblerError> {
+    let result = match rvc::compress(insn) {
+        Some(half) if compress => {
+            *extensions |= EXT_C;
+            code_and_data.write_all(&(half as u16).to_le_bytes()).map(|()| 2)
+        },
+        _ => code_and_data.write_all(&insn.to_le_bytes()).map(|()| 4),
+    };
+    result.map_err(|e| AssemblerError::Write {
+        line_num,
+        inner: e,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 fn assemble_line2(
     mnemonics: &HashMap<&str, (InsnType, u32)>,
     line_num: usize,
@@ -198,6 +281,8 @@ fn assemble_line2(
     strings: &mut StringTable,
     symbols: &mut SymbolTable,
     relocations: &mut RelocationTable,
+    compress: bool, // emit 16-bit encodings where they'd do the same thing
+    extensions: &mut u32,
     code_and_data: &mut impl Write,
 ) -> Result<u32, AssemblerError> {
     let mut chars = Peekable::new(line.char_indices());
@@ -215,6 +300,7 @@ fn assemble_line2(
     if word.starts_with('$') {
         let name = &word["$".len()..];
         let name_index = strings.get_index_or_insert(name);
+        let mut external = false;
         if let Some(sym) = symbols.get(name_index) {
             if sym.is_defined() {
                 return Err(AssemblerError::DuplicateLabel {
@@ -223,22 +309,41 @@ fn assemble_line2(
                     label: name.to_owned(),
                 });
             }
+            external = sym.is_external(); // from .global
         }
-        // TODO: we don't know it's code. add it to a pending set then choose symbol type based on insn/directive that follows
+        // Code until a data directive right after it says otherwise (see mark_data).
         symbols.insert(name_index, SymbolValue::Code {
-            external: false,
+            external,
             type_index: 0, // none
             offset: Some(insn_offset),
         });
         return Ok(0);
     }
     let (mnemonic, mnemonic_pos) = (word, word_pos);
-    let &(insn_type, template) = mnemonics.get(&mnemonic as &str)
-        .ok_or_else(|| AssemblerError::Syntax {
+    let (base, ordering) = split_ordering(&mnemonic);
+    // jal with just a target and jalr with just a register are pseudo-instructions that link to ra.
+    let operand_count = line.split_whitespace().count() - 1;
+    let &(insn_type, template) = match (base, operand_count) {
+        ("jal" | "jalr", 1) => Some(&(InsnType::P, 0)),
+        _ => match mnemonics.get(base) {
+            Some(&(InsnType::A, _)) | Some(&(InsnType::Lr, _)) => mnemonics.get(base),
+            _ => mnemonics.get(&mnemonic as &str),
+        },
+    }.ok_or_else(|| AssemblerError::Syntax {
+        line_num,
+        col_num: word_pos,
+        msg: format!("unknown mnemonic '{}'", &mnemonic),
+    })?;
+    // Nothing could ever jump to an instruction at an odd offset, even with the C extension.
+    if !mnemonic.starts_with('.') && insn_offset & 1 != 0 {
+        return Err(AssemblerError::Syntax {
             line_num,
-            col_num: word_pos,
-            msg: format!("unknown mnemonic '{}'", &mnemonic),
-        })?;
+            col_num: mnemonic_pos,
+            msg: "instruction isn't 2-byte aligned (.align after the data before it)".to_owned(),
+        });
+    }
+    let template = template | ordering;
+    *extensions |= extension(insn_type, template);
 
     skip_whitespace(&mut chars);
 
@@ -256,6 +361,28 @@ fn assemble_line2(
             |e| AssemblerError::Syntax { line_num, col_num: pos, msg: e })
     };
 
+    // A register in parentheses, for the address operand of atomic instructions.
+    let parse_addr_reg_here = |field, chars: &mut Peekable<_>| {
+        let pos = chars.pos;
+        if chars.peek().is_none() {
+            return Err(AssemblerError::Syntax {
+                line_num,
+                col_num: pos,
+                msg: format!("missing {}", field),
+            });
+        }
+        let (_, word) = collect_word(chars);
+        let reg = word.strip_prefix('(')
+            .and_then(|w| w.strip_suffix(')'))
+            .ok_or_else(|| AssemblerError::Syntax {
+                line_num,
+                col_num: pos,
+                msg: format!("{} must be in parentheses", field),
+            })?;
+        parse_reg(reg).map_err(
+            |e| AssemblerError::Syntax { line_num, col_num: pos + 1, msg: e })
+    };
+
     let parse_imm_here = |width, chars: &mut Peekable<_>| {
         let pos = chars.pos;
         if chars.peek().is_none() {
@@ -270,14 +397,24 @@ fn assemble_line2(
             |e| AssemblerError::Syntax {line_num, col_num: pos, msg: e })
     };
 
+    // A CSR by number or by name.
+    let parse_csr_here = |chars: &mut Peekable<_>| {
+        let pos = chars.pos;
+        if chars.peek().is_none() {
+            return Err(AssemblerError::Syntax {
+                line_num,
+                col_num: pos,
+                msg: "missing csr".to_owned(),
+            });
+        }
+        let (_, csr) = collect_word(chars);
+        let csr = if is_identifier(&csr) { parse_csr(&csr) } else { from_hex(&csr, 12) };
+        csr.map_err(|e| AssemblerError::Syntax { line_num, col_num: pos, msg: e })
+    };
+
     let insn_len = match insn_type {
         InsnType::X => {
-            code_and_data.write_all(&template.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            write_insn(code_and_data, template, compress, extensions, line_num)?
         },
         InsnType::R => {
             let rd = parse_reg_here("rd", &mut chars)?;
@@ -286,12 +423,7 @@ fn assemble_line2(
             skip_whitespace(&mut chars);
             let rs2 = parse_reg_here("rs2", &mut chars)?;
             let insn = template + (rd << 7) + (rs1 << 15) + (rs2 << 20);
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
         },
         InsnType::Sxli => {
             let rd = parse_reg_here("rd", &mut chars)?;
@@ -300,12 +432,7 @@ fn assemble_line2(
             skip_whitespace(&mut chars);
             let imm5 = parse_imm_here(5, &mut chars)?;
             let insn = template + (rd << 7) + (rs1 << 15) + (imm5 << 20);
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
         },
         InsnType::I => {
             let rd = parse_reg_here("rd", &mut chars)?;
@@ -322,7 +449,30 @@ fn assemble_line2(
             }
             let imm12_pos = chars.pos;
             let (_, imm12) = collect_word(&mut chars);
-            if is_identifier(&imm12) {
+            let absolute = split_operator(&imm12);
+            // The offset isn't known yet, so it might not fit a compressed instruction.
+            let relocated = is_identifier(&imm12) || absolute.is_some();
+            if let Some((operator, label)) = absolute {
+                if operator != "lo" || !is_identifier(label) {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: imm12_pos,
+                        msg: "I-type instructions only take %lo(label)".to_owned(),
+                    });
+                }
+                relocations.relocations.push(Relocation {
+                    offset: insn_offset,
+                    symbol_index: symbols.get_index_or_insert(
+                        strings.get_index_or_insert(label),
+                        SymbolValue::Code {
+                            external: false,
+                            type_index: 0, // none
+                            offset: None,
+                        },
+                    ),
+                    value: RelocationValue::AbsIType,
+                });
+            } else if relocated {
                 if !(mnemonic == "addi" || mnemonic == "jalr") {
                     return Err(AssemblerError::Syntax {
                         line_num,
@@ -348,29 +498,46 @@ fn assemble_line2(
                     |e| AssemblerError::Syntax {line_num, col_num: imm12_pos, msg: e })?;
                 insn += imm12 << 20;
             }
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            write_insn(code_and_data, insn, compress && !relocated, extensions, line_num)?
         },
         InsnType::S => {
             let rs2 = parse_reg_here("rs2", &mut chars)?;
             skip_whitespace(&mut chars);
             let rs1 = parse_reg_here("rs1", &mut chars)?;
             skip_whitespace(&mut chars);
-            let imm12 = parse_imm_here(12, &mut chars)?;
             let mut insn = template;
             insn += (rs1 << 15) + (rs2 << 20);
-            insn += imm12 << (31-11) >> (31-11+5) << 25;
-            insn += imm12 << (31-4) >> (31-4+0) << 7;
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            let relocated = chars.peek().is_some_and(|&(_, c)| c == '%');
+            if relocated {
+                let imm12_pos = chars.pos;
+                let (_, imm12) = collect_word(&mut chars);
+                match split_operator(&imm12) {
+                    Some(("lo", label)) if is_identifier(label) => {
+                        relocations.relocations.push(Relocation {
+                            offset: insn_offset,
+                            symbol_index: symbols.get_index_or_insert(
+                                strings.get_index_or_insert(label),
+                                SymbolValue::Code {
+                                    external: false,
+                                    type_index: 0, // none
+                                    offset: None,
+                                },
+                            ),
+                            value: RelocationValue::AbsSType,
+                        });
+                    },
+                    _ => return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: imm12_pos,
+                        msg: "S-type instructions only take %lo(label)".to_owned(),
+                    }),
+                }
+            } else {
+                let imm12 = parse_imm_here(12, &mut chars)?;
+                insn += imm12 << (31-11) >> (31-11+5) << 25;
+                insn += imm12 << (31-4) >> (31-4) << 7;
+            }
+            write_insn(code_and_data, insn, compress && !relocated, extensions, line_num)?
         },
         InsnType::B => {
             let rs1 = parse_reg_here("rs1", &mut chars)?;
@@ -387,13 +554,14 @@ fn assemble_line2(
             }
             let target_pos = chars.pos;
             let (_, target) = collect_word(&mut chars);
-            if is_identifier(&target) {
-                // DANGER: Currently, initial immediate must be zero or it might not overflow
-                // correctly. Fix this in Relocation::apply().
+            let (label, imm13) = split_target(&target, 13).map_err(
+                |e| AssemblerError::Syntax {line_num, col_num: target_pos, msg: e })?;
+            let relocated = label.is_some(); // see the I-type case
+            if let Some(label) = label {
                 relocations.relocations.push(Relocation {
                     offset: insn_offset,
                     symbol_index: symbols.get_index_or_insert(
-                        strings.get_index_or_insert(&target),
+                        strings.get_index_or_insert(label),
                         SymbolValue::Code {
                             external: false,
                             type_index: 0, // none
@@ -402,41 +570,64 @@ fn assemble_line2(
                     ),
                     value: RelocationValue::RelCodeBType,
                 });
-            } else {
-                let imm13 = from_hex(&target, 13).map_err(
-                    |e| AssemblerError::Syntax {line_num, col_num: target_pos, msg: e })?;
-
-                if imm13 & 0x3 != 0 {
-                    return Err(AssemblerError::Syntax {
-                        line_num,
-                        col_num: target_pos,
-                        msg: "low two bits of imm13 must be 0".to_owned(),
-                    });
-                }
-                insn += imm13 << (31-12) >> (31-12+12) << 31;
-                insn += imm13 << (31-10) >> (31-10+5) << 25;
-                insn += imm13 << (31-4) >> (31-4+1) << 8;
-                insn += imm13 << (31-11) >> (31-11+11) << 7;
             }
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
+            // Targets that aren't 4-byte aligned are only an error at run time (and not even
+            // then with the C extension), so they're encodable.
+            if imm13 & 0x1 != 0 {
+                return Err(AssemblerError::Syntax {
                     line_num,
-                    inner: e,
-                })?;
-            4
+                    col_num: target_pos,
+                    msg: "low bit of imm13 must be 0".to_owned(),
+                });
+            }
+            insn += imm13 << (31-12) >> (31-12+12) << 31;
+            insn += imm13 << (31-10) >> (31-10+5) << 25;
+            insn += imm13 << (31-4) >> (31-4+1) << 8;
+            insn += imm13 << (31-11) >> (31-11+11) << 7;
+            write_insn(code_and_data, insn, compress && !relocated, extensions, line_num)?
         },
         InsnType::U => {
             let rd = parse_reg_here("rd", &mut chars)?;
             skip_whitespace(&mut chars);
-            // TODO: Handle other kinds of immediates.
-            let imm20 = parse_imm_here(20, &mut chars)?;
-            let insn = template + (rd << 7) + (imm20 << 12);
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
+            if chars.peek().is_none() {
+                return Err(AssemblerError::Syntax {
                     line_num,
-                    inner: e,
-                })?;
-            4
+                    col_num: chars.pos,
+                    msg: "missing imm20".to_owned(),
+                });
+            }
+            let imm20_pos = chars.pos;
+            let (_, imm20) = collect_word(&mut chars);
+            let relocated = imm20.starts_with('%');
+            let mut insn = template + (rd << 7);
+            if relocated {
+                match split_operator(&imm20) {
+                    Some(("hi", label)) if mnemonic == "lui" && is_identifier(label) => {
+                        relocations.relocations.push(Relocation {
+                            offset: insn_offset,
+                            symbol_index: symbols.get_index_or_insert(
+                                strings.get_index_or_insert(label),
+                                SymbolValue::Code {
+                                    external: false,
+                                    type_index: 0, // none
+                                    offset: None,
+                                },
+                            ),
+                            value: RelocationValue::AbsUType,
+                        });
+                    },
+                    _ => return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: imm20_pos,
+                        msg: "only lui takes %hi(label)".to_owned(),
+                    }),
+                }
+            } else {
+                let imm20 = from_hex(&imm20, 20).map_err(
+                    |e| AssemblerError::Syntax { line_num, col_num: imm20_pos, msg: e })?;
+                insn += imm20 << 12;
+            }
+            write_insn(code_and_data, insn, compress && !relocated, extensions, line_num)?
         },
         InsnType::J => {
             let rd = parse_reg_here("rd", &mut chars)?;
@@ -451,13 +642,14 @@ fn assemble_line2(
             }
             let target_pos = chars.pos;
             let (_, target) = collect_word(&mut chars);
-            if is_identifier(&target) {
-                // DANGER: Currently, initial immediate must be zero or it might not overflow
-                // correctly. Fix this in Relocation::apply().
+            let (label, imm21) = split_target(&target, 21).map_err(
+                |e| AssemblerError::Syntax {line_num, col_num: target_pos, msg: e })?;
+            let relocated = label.is_some(); // see the I-type case
+            if let Some(label) = label {
                 relocations.relocations.push(Relocation {
                     offset: insn_offset,
                     symbol_index: symbols.get_index_or_insert(
-                        strings.get_index_or_insert(&target),
+                        strings.get_index_or_insert(label),
                         SymbolValue::Code {
                             external: false,
                             type_index: 0, // none
@@ -466,73 +658,253 @@ fn assemble_line2(
                     ),
                     value: RelocationValue::RelCodeJType,
                 });
-            } else {
-                let imm21 = from_hex(&target, 21).map_err(
-                    |e| AssemblerError::Syntax {line_num, col_num: target_pos, msg: e })?;
-
-                if imm21 & 0x3 != 0 {
+            }
+            // See the B-type case about alignment.
+            if imm21 & 0x1 != 0 {
+                return Err(AssemblerError::Syntax {
+                    line_num,
+                    col_num: target_pos,
+                    msg: "low bit of imm21 must be 0".to_owned(),
+                });
+            }
+            insn += imm21 << (31-20) >> (31-20+20) << 31;
+            insn += imm21 << (31-10) >> (31-10+1) << 21;
+            insn += imm21 << (31-11) >> (31-11+11) << 20;
+            insn += imm21 << (31-19) >> (31-19+12) << 12;
+            write_insn(code_and_data, insn, compress && !relocated, extensions, line_num)?
+        },
+        InsnType::F => {
+            let parse_pred_succ_here = |field, chars: &mut Peekable<_>| {
+                let pos = chars.pos;
+                if chars.peek().is_none() {
                     return Err(AssemblerError::Syntax {
                         line_num,
-                        col_num: target_pos,
-                        msg: "low two bits of imm21 must be 0".to_owned(),
+                        col_num: pos,
+                        msg: format!("missing {}", field),
                     });
                 }
-                insn += imm21 << (31-20) >> (31-20+20) << 31;
-                insn += imm21 << (31-10) >> (31-10+1) << 21;
-                insn += imm21 << (31-11) >> (31-11+11) << 20;
-                insn += imm21 << (31-19) >> (31-19+12) << 12;
-            }
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+                let (_, bits) = collect_word(chars);
+                parse_pred_succ(&bits).map_err(
+                    |e| AssemblerError::Syntax { line_num, col_num: pos, msg: e })
+            };
+            let pred = parse_pred_succ_here("pred", &mut chars)?;
+            skip_whitespace(&mut chars);
+            let succ = parse_pred_succ_here("succ", &mut chars)?;
+            let insn = template + (pred << 24) + (succ << 20);
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
+        },
+        InsnType::A => {
+            let rd = parse_reg_here("rd", &mut chars)?;
+            skip_whitespace(&mut chars);
+            let rs2 = parse_reg_here("rs2", &mut chars)?;
+            skip_whitespace(&mut chars);
+            let rs1 = parse_addr_reg_here("rs1", &mut chars)?;
+            let insn = template + (rd << 7) + (rs1 << 15) + (rs2 << 20);
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
+        },
+        InsnType::Lr => {
+            let rd = parse_reg_here("rd", &mut chars)?;
+            skip_whitespace(&mut chars);
+            let rs1 = parse_addr_reg_here("rs1", &mut chars)?;
+            let insn = template + (rd << 7) + (rs1 << 15);
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
         },
-        // InsnType::F => {
-        //     insns.push(template);
-        //     let pred = parts.next().expect("missing pred");
-        //     let pred = parse_pred_succ(pred).unwrap();
-        //     let succ = parts.next().expect("missing succ");
-        //     let succ = parse_pred_succ(succ).unwrap();
-        //     insns[0] += (pred << 24) + (succ << 20);
-        //     print_insns
-        // },
         InsnType::C => {
             let rd = parse_reg_here("rd", &mut chars)?;
             skip_whitespace(&mut chars);
             let rs1 = parse_reg_here("rs1", &mut chars)?;
             skip_whitespace(&mut chars);
-            let csr = parse_imm_here(12, &mut chars)?;
+            let csr = parse_csr_here(&mut chars)?;
             let insn = template + (rd << 7) + (rs1 << 15) + (csr << 20);
-            code_and_data.write_all(&insn.to_le_bytes())
-                .map_err(|e| AssemblerError::Write {
-                    line_num,
-                    inner: e,
-                })?;
-            4
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
         },
         InsnType::Ci => {
             let rd = parse_reg_here("rd", &mut chars)?;
             skip_whitespace(&mut chars);
             let imm5 = parse_imm_here(5, &mut chars)?;
             skip_whitespace(&mut chars);
-            let csr = parse_imm_here(12, &mut chars)?;
+            let csr = parse_csr_here(&mut chars)?;
             let insn = template + (rd << 7) + (imm5 << 15) + (csr << 20);
+            write_insn(code_and_data, insn, compress, extensions, line_num)?
+        },
+        InsnType::Rvc => {
+            let syntax = |col_num, msg: String| AssemblerError::Syntax { line_num, col_num, msg };
+            // One of x8-x15, which are all the 3-bit register fields can hold. Returns the field.
+            let creg_here = |field, chars: &mut Peekable<_>| {
+                let pos = chars.pos;
+                let reg = parse_reg_here(field, chars)?;
+                if !rvc::is_creg(reg) {
+                    return Err(syntax(pos, format!("{} must be one of s0, s1, a0-a5", field)));
+                }
+                Ok(reg - 8)
+            };
+            let nonzero_reg_here = |field, chars: &mut Peekable<_>| {
+                let pos = chars.pos;
+                let reg = parse_reg_here(field, chars)?;
+                if reg == 0 {
+                    return Err(syntax(pos, format!("{} can't be zero", field)));
+                }
+                Ok(reg)
+            };
+            // An immediate whose low bits aren't encoded because they have to be 0.
+            let scaled_imm_here = |width, scale: u32, chars: &mut Peekable<_>| {
+                let pos = chars.pos;
+                let imm = parse_imm_here(width, chars)?;
+                if imm & (scale - 1) != 0 {
+                    let low_bits = scale.trailing_zeros();
+                    let msg = format!("low {} bits of imm{} must be 0", low_bits, width);
+                    return Err(syntax(pos, msg));
+                }
+                Ok(imm)
+            };
+            let nonzero = |imm, width, pos| match imm {
+                0 => Err(syntax(pos, format!("imm{} can't be 0", width))),
+                _ => Ok(imm),
+            };
+            let mut target_here = |width, kind, chars: &mut Peekable<_>| {
+                if chars.peek().is_none() {
+                    return Err(syntax(chars.pos, "missing branch target".to_owned()));
+                }
+                let target_pos = chars.pos;
+                let (_, target) = collect_word(chars);
+                let (label, imm) = split_target(&target, width).map_err(|e| syntax(target_pos, e))?;
+                if let Some(label) = label {
+                    relocations.relocations.push(Relocation {
+                        offset: insn_offset,
+                        symbol_index: symbols.get_index_or_insert(
+                            strings.get_index_or_insert(label),
+                            SymbolValue::Code {
+                                external: false,
+                                type_index: 0, // none
+                                offset: None,
+                            },
+                        ),
+                        value: kind,
+                    });
+                }
+                if imm & 0x1 != 0 {
+                    return Err(syntax(target_pos, format!("low bit of imm{} must be 0", width)));
+                }
+                Ok(imm)
+            };
+
+            let fields = match &mnemonic as &str {
+                "c.nop" | "c.ebreak" => 0,
+                "c.addi4spn" => {
+                    let rd = creg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let pos = chars.pos;
+                    let imm10 = nonzero(scaled_imm_here(10, 4, &mut chars)?, 10, pos)?;
+                    (rd << 2) | rvc::put_imm(imm10, &rvc::CIW)
+                },
+                "c.lw" | "c.sw" => {
+                    let field = if mnemonic == "c.lw" { "rd" } else { "rs2" };
+                    let rd = creg_here(field, &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let rs1 = creg_here("rs1", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm7 = scaled_imm_here(7, 4, &mut chars)?;
+                    (rs1 << 7) | (rd << 2) | rvc::put_imm(imm7, &rvc::CL)
+                },
+                "c.addi" | "c.li" => {
+                    let rd = parse_reg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm6 = parse_imm_here(6, &mut chars)?;
+                    (rd << 7) | rvc::put_imm(imm6, &rvc::CI)
+                },
+                "c.addi16sp" => {
+                    let pos = chars.pos;
+                    let imm10 = nonzero(scaled_imm_here(10, 16, &mut chars)?, 10, pos)?;
+                    rvc::put_imm(imm10, &rvc::ADDI16SP)
+                },
+                "c.lui" => {
+                    let pos = chars.pos;
+                    let rd = parse_reg_here("rd", &mut chars)?;
+                    if rd == 2 {
+                        return Err(syntax(pos, "rd can't be sp (that's c.addi16sp)".to_owned()));
+                    }
+                    skip_whitespace(&mut chars);
+                    let pos = chars.pos;
+                    let imm20 = nonzero(parse_imm_here(20, &mut chars)?, 20, pos)?;
+                    // Only the low 6 bits are encoded, and the rest are copies of bit 5.
+                    if rvc::sext(imm20, 6) & 0xF_FFFF != imm20 {
+                        let msg = "imm20 must be at most #1F or at least #FFFE'0".to_owned();
+                        return Err(syntax(pos, msg));
+                    }
+                    (rd << 7) | rvc::put_imm(imm20, &rvc::CI)
+                },
+                "c.srli" | "c.srai" | "c.andi" => {
+                    let rd = creg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm = parse_imm_here(if mnemonic == "c.andi" { 6 } else { 5 }, &mut chars)?;
+                    (rd << 7) | rvc::put_imm(imm, &rvc::CI)
+                },
+                "c.sub" | "c.xor" | "c.or" | "c.and" => {
+                    let rd = creg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let rs2 = creg_here("rs2", &mut chars)?;
+                    (rd << 7) | (rs2 << 2)
+                },
+                "c.j" | "c.jal" => {
+                    let imm12 = target_here(12, RelocationValue::RelCodeCjType, &mut chars)?;
+                    rvc::put_imm(imm12, &rvc::CJ)
+                },
+                "c.beqz" | "c.bnez" => {
+                    let rs1 = creg_here("rs1", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm9 = target_here(9, RelocationValue::RelCodeCbType, &mut chars)?;
+                    (rs1 << 7) | rvc::put_imm(imm9, &rvc::CB)
+                },
+                "c.slli" => {
+                    let rd = parse_reg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm5 = parse_imm_here(5, &mut chars)?;
+                    (rd << 7) | rvc::put_imm(imm5, &rvc::CI)
+                },
+                "c.lwsp" => {
+                    let rd = nonzero_reg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm8 = scaled_imm_here(8, 4, &mut chars)?;
+                    (rd << 7) | rvc::put_imm(imm8, &rvc::LWSP)
+                },
+                "c.swsp" => {
+                    let rs2 = parse_reg_here("rs2", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let imm8 = scaled_imm_here(8, 4, &mut chars)?;
+                    (rs2 << 2) | rvc::put_imm(imm8, &rvc::SWSP)
+                },
+                "c.jr" | "c.jalr" => nonzero_reg_here("rs1", &mut chars)? << 7,
+                "c.mv" | "c.add" => {
+                    let rd = parse_reg_here("rd", &mut chars)?;
+                    skip_whitespace(&mut chars);
+                    let rs2 = nonzero_reg_here("rs2", &mut chars)?;
+                    (rd << 7) | (rs2 << 2)
+                },
+                _ => unreachable!("{} isn't compressed", mnemonic),
+            };
+            let insn = template as u16 | fields as u16;
             code_and_data.write_all(&insn.to_le_bytes())
                 .map_err(|e| AssemblerError::Write {
                     line_num,
                     inner: e,
                 })?;
-            4
+            2
         },
         InsnType::P => {
-            if mnemonic == "li" {
+            // la is li, but only for addresses.
+            if mnemonic == "li" || mnemonic == "la" || mnemonic == "lla" {
                 let rd = parse_reg_here("rd", &mut chars)?;
                 skip_whitespace(&mut chars);
                 let imm32_pos = chars.pos;
                 let (_, imm32) = collect_word(&mut chars);
                 let is_ident = is_identifier(&imm32);
+                if !is_ident && mnemonic != "li" {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: imm32_pos,
+                        msg: format!("{} needs a label", mnemonic),
+                    });
+                }
                 let mut imm20 = 0;
                 let mut imm12 = 0;
                 if !is_ident {
@@ -563,6 +935,8 @@ fn assemble_line2(
                         &mut *strings,
                         &mut *symbols,
                         &mut *relocations,
+                        compress && !is_ident,
+                        &mut *extensions,
                         &mut *code_and_data,
                     )?;
                 } else {
@@ -574,6 +948,8 @@ fn assemble_line2(
                         &mut *strings,
                         &mut *symbols,
                         &mut *relocations,
+                        compress && !is_ident,
+                        &mut *extensions,
                         &mut *code_and_data,
                     )?;
                 }
@@ -602,11 +978,12 @@ fn assemble_line2(
                     &mut *strings,
                     &mut *symbols,
                     &mut *relocations,
+                    compress && !is_ident,
+                    &mut *extensions,
                     &mut *code_and_data,
                 )?;
                 extra_insn_offset
             } else if mnemonic == ".utf8" {
-                let opening_quote_pos = chars.pos;
                 if let Some((pos, c)) = chars.next() {
                     if c != '"' {
                         return Err(AssemblerError::Syntax {
@@ -640,9 +1017,12 @@ fn assemble_line2(
                         s.push(c);
                     }
                 }
-                for _ in 0..(s.len().wrapping_neg() & 0b11) {
+                // Pad so whatever comes next is word-aligned again, even if the string isn't.
+                let end = insn_offset.wrapping_add(s.len() as u32);
+                for _ in 0..(end.wrapping_neg() & 0b11) {
                     s.push('\0'); // conveniently 1 byte
                 }
+                mark_data(symbols, insn_offset);
                 code_and_data.write_all(s.as_bytes())
                     .map_err(|e| AssemblerError::Write {
                         line_num,
@@ -658,15 +1038,311 @@ fn assemble_line2(
                     }
                 }
                 s.len() as u32
+            } else if mnemonic == ".word" || mnemonic == ".half" || mnemonic == ".byte" {
+                let width = match &mnemonic as &str {
+                    ".word" => 32,
+                    ".half" => 16,
+                    _ => 8,
+                };
+                if chars.peek().is_none() {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: chars.pos,
+                        msg: "missing value".to_owned(),
+                    });
+                }
+                let mut bytes = Vec::new();
+                while chars.peek().is_some() {
+                    let value_pos = chars.pos;
+                    let (_, value) = collect_word(&mut chars);
+                    let value = if is_identifier(&value) {
+                        if width != 32 {
+                            return Err(AssemblerError::Syntax {
+                                line_num,
+                                col_num: value_pos,
+                                msg: "only .word can hold a label's address".to_owned(),
+                            });
+                        }
+                        relocations.relocations.push(Relocation {
+                            offset: insn_offset + bytes.len() as u32,
+                            symbol_index: symbols.get_index_or_insert(
+                                strings.get_index_or_insert(&value),
+                                SymbolValue::Code {
+                                    external: false,
+                                    type_index: 0, // none
+                                    offset: None,
+                                },
+                            ),
+                            value: RelocationValue::Abs32,
+                        });
+                        0
+                    } else {
+                        from_hex(&value, width).map_err(
+                            |e| AssemblerError::Syntax { line_num, col_num: value_pos, msg: e })?
+                    };
+                    bytes.extend_from_slice(&value.to_le_bytes()[..width as usize / 8]);
+                    skip_whitespace(&mut chars);
+                }
+                mark_data(symbols, insn_offset);
+                code_and_data.write_all(&bytes)
+                    .map_err(|e| AssemblerError::Write {
+                        line_num,
+                        inner: e,
+                    })?;
+                bytes.len() as u32
+            } else if mnemonic == ".zero" || mnemonic == ".space" {
+                let len_pos = chars.pos;
+                let (_, len) = collect_word(&mut chars);
+                if len.is_empty() || len.starts_with('-') {
+                    let msg = if len.is_empty() { "missing length" } else { "negative length" };
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: len_pos,
+                        msg: msg.to_owned(),
+                    });
+                }
+                let len = from_hex(&len, 32).map_err(
+                    |e| AssemblerError::Syntax { line_num, col_num: len_pos, msg: e })?;
+                if insn_offset.checked_add(len).is_none() {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: len_pos,
+                        msg: "length goes past the end of the object".to_owned(),
+                    });
+                }
+                mark_data(symbols, insn_offset);
+                // Copied a little at a time, since the length can be up to 4 GiB.
+                io::copy(&mut io::repeat(0).take(len as u64), code_and_data)
+                    .map_err(|e| AssemblerError::Write {
+                        line_num,
+                        inner: e,
+                    })?;
+                len
+            } else if mnemonic == ".align" || mnemonic == ".balign" {
+                // .align takes a power of two, like GNU as for RISC-V, and .balign takes bytes.
+                // Objects are only word-aligned once they're linked, so a word is as far as either
+                // can go.
+                let align_pos = chars.pos;
+                let align = parse_imm_here(32, &mut chars)?;
+                let align = match (&mnemonic as &str, align) {
+                    (".align", 0..=2) => 1 << align,
+                    (".balign", 1 | 2 | 4) => align,
+                    _ => return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: align_pos,
+                        msg: "alignment must be 1, 2 or 4 bytes".to_owned(),
+                    }),
+                };
+                let padding_len = insn_offset.wrapping_neg() & (align - 1);
+                code_and_data.write_all(&vec![0; padding_len as usize])
+                    .map_err(|e| AssemblerError::Write {
+                        line_num,
+                        inner: e,
+                    })?;
+                padding_len
+            } else if mnemonic == ".extern" || mnemonic == ".global" {
+                // These do the same thing, which is make labels visible to other objects. The
+                // names just say whether they're meant to be defined here (.global) or somewhere
+                // else (.extern). References to ones that never get defined are left for the
+                // linker.
+                if chars.peek().is_none() {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: chars.pos,
+                        msg: "missing label".to_owned(),
+                    });
+                }
+                while chars.peek().is_some() {
+                    let name_pos = chars.pos;
+                    let (_, name) = collect_word(&mut chars);
+                    if !is_identifier(&name) {
+                        return Err(AssemblerError::Syntax {
+                            line_num,
+                            col_num: name_pos,
+                            msg: format!("{:?} isn't a label", name),
+                        });
+                    }
+                    let name_index = strings.get_index_or_insert(&name);
+                    let (external, type_index) = (true, 0); // type none
+                    let value = match symbols.get(name_index).map(|sym| sym.value) {
+                        Some(SymbolValue::Data { offset, .. }) => {
+                            SymbolValue::Data { external, type_index, offset }
+                        },
+                        Some(SymbolValue::Code { offset, .. }) => {
+                            SymbolValue::Code { external, type_index, offset }
+                        },
+                        _ => SymbolValue::Code { external, type_index, offset: None },
+                    };
+                    symbols.insert(name_index, value);
+                    skip_whitespace(&mut chars);
+                }
+                0
+            } else if mnemonic == "call" || mnemonic == "tail" {
+                // auipc+jalr, through ra for call, or through t1 for tail so ra is left alone.
+                let (link, via) = if mnemonic == "call" { (1, 1) } else { (0, 6) };
+                if chars.peek().is_none() {
+                    return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: chars.pos,
+                        msg: "missing jump target".to_owned(),
+                    });
+                }
+                let target_pos = chars.pos;
+                let (_, target) = collect_word(&mut chars);
+                let is_ident = is_identifier(&target);
+                let (imm20, imm12) = if is_ident {
+                    for (offset, value) in [
+                        (insn_offset, RelocationValue::RelUType),
+                        (insn_offset + 4, RelocationValue::RelIType),
+                    ] {
+                        relocations.relocations.push(Relocation {
+                            offset,
+                            symbol_index: symbols.get_index_or_insert(
+                                strings.get_index_or_insert(&target),
+                                SymbolValue::Code {
+                                    external: false,
+                                    type_index: 0, // none
+                                    offset: None,
+                                },
+                            ),
+                            value,
+                        });
+                    }
+                    // jalr's relocation is relative to jalr, which is 4 bytes after auipc, like
+                    // in li.
+                    (0, 4)
+                } else {
+                    let imm32 = from_hex(&target, 32).map_err(
+                        |e| AssemblerError::Syntax {line_num, col_num: target_pos, msg: e })?;
+                    ((imm32 >> 12).wrapping_add((imm32 >> 11) & 1) & 0xF_FFFF, imm32 & 0xFFF)
+                };
+                let mut len = 0;
+                for insn in [
+                    format!("auipc x{} {}", via, u32_to_hex(imm20)),
+                    format!("jalr x{} x{} {}", link, via, u32_to_hex(imm12)),
+                ] {
+                    len += assemble_line2(
+                        mnemonics,
+                        line_num,
+                        &insn,
+                        insn_offset + len,
+                        &mut *strings,
+                        &mut *symbols,
+                        &mut *relocations,
+                        compress && !is_ident,
+                        &mut *extensions,
+                        &mut *code_and_data,
+                    )?;
+                }
+                len
             } else {
-                return Err(AssemblerError::Syntax {
+                // The rest are shorthand for one other instruction. Registers are checked here,
+                // but anything else is passed through, so errors about it point at it.
+                let mut passed_pos = mnemonic_pos;
+                let mut pass = |field, chars: &mut Peekable<_>| {
+                    passed_pos = chars.pos;
+                    if chars.peek().is_none() {
+                        return Err(AssemblerError::Syntax {
+                            line_num,
+                            col_num: passed_pos,
+                            msg: format!("missing {}", field),
+                        });
+                    }
+                    Ok(collect_word(chars).1)
+                };
+                let reg = |field, chars: &mut Peekable<_>| {
+                    let reg = parse_reg_here(field, chars)?;
+                    skip_whitespace(chars);
+                    Ok(reg)
+                };
+                let insn = match &mnemonic as &str {
+                    "mv" | "not" | "neg" | "seqz" | "snez" | "sltz" | "sgtz" => {
+                        let rd = reg("rd", &mut chars)?;
+                        let rs = reg("rs", &mut chars)?;
+                        match &mnemonic as &str {
+                            "mv" => format!("addi x{} x{} #0", rd, rs),
+                            "not" => format!("xori x{} x{} -#1", rd, rs),
+                            "neg" => format!("sub x{} x0 x{}", rd, rs),
+                            "seqz" => format!("sltiu x{} x{} #1", rd, rs),
+                            "snez" => format!("sltu x{} x0 x{}", rd, rs),
+                            "sltz" => format!("slt x{} x{} x0", rd, rs),
+                            _ => format!("slt x{} x0 x{}", rd, rs),
+                        }
+                    },
+                    "beqz" | "bnez" | "blez" | "bgez" | "bltz" | "bgtz" => {
+                        let rs = reg("rs", &mut chars)?;
+                        let target = pass("branch target", &mut chars)?;
+                        match &mnemonic as &str {
+                            "beqz" => format!("beq x{} x0 {}", rs, target),
+                            "bnez" => format!("bne x{} x0 {}", rs, target),
+                            "blez" => format!("bge x0 x{} {}", rs, target),
+                            "bgez" => format!("bge x{} x0 {}", rs, target),
+                            "bltz" => format!("blt x{} x0 {}", rs, target),
+                            _ => format!("blt x0 x{} {}", rs, target),
+                        }
+                    },
+                    "bgt" | "ble" | "bgtu" | "bleu" => {
+                        // The same comparison with the operands swapped.
+                        let rs = reg("rs", &mut chars)?;
+                        let rt = reg("rt", &mut chars)?;
+                        let target = pass("branch target", &mut chars)?;
+                        let swapped = match &mnemonic as &str {
+                            "bgt" => "blt",
+                            "ble" => "bge",
+                            "bgtu" => "bltu",
+                            _ => "bgeu",
+                        };
+                        format!("{} x{} x{} {}", swapped, rt, rs, target)
+                    },
+                    "j" => format!("jal x0 {}", pass("jump target", &mut chars)?),
+                    "jal" => format!("jal x1 {}", pass("jump target", &mut chars)?),
+                    "jr" => format!("jalr x0 x{} #0", reg("rs", &mut chars)?),
+                    "jalr" => format!("jalr x1 x{} #0", reg("rs", &mut chars)?),
+                    "rdinstret" | "rdinstreth" | "rdcycle" | "rdcycleh" | "rdtime" | "rdtimeh" => {
+                        let csr = &mnemonic["rd".len()..];
+                        format!("csrrs x{} x0 {}", reg("rd", &mut chars)?, csr)
+                    },
+                    // Like the instructions they stand for, these take the CSR last.
+                    "csrr" => {
+                        let rd = reg("rd", &mut chars)?;
+                        format!("csrrs x{} x0 {}", rd, pass("csr", &mut chars)?)
+                    },
+                    "csrw" | "csrs" | "csrc" => {
+                        let rs = reg("rs", &mut chars)?;
+                        let csr = pass("csr", &mut chars)?;
+                        format!("csrr{} x0 x{} {}", &mnemonic[3..], rs, csr)
+                    },
+                    "csrwi" | "csrsi" | "csrci" => {
+                        let imm5 = pass("imm5", &mut chars)?;
+                        skip_whitespace(&mut chars);
+                        let csr = pass("csr", &mut chars)?;
+                        format!("csrr{} x0 {} {}", &mnemonic[3..], imm5, csr)
+                    },
+                    _ => return Err(AssemblerError::Syntax {
+                        line_num,
+                        col_num: mnemonic_pos,
+                        msg: format!("unknown mnemonic {:?}", mnemonic),
+                    }),
+                };
+                assemble_line2(
+                    mnemonics,
                     line_num,
-                    col_num: mnemonic_pos,
-                    msg: format!("unknown mnemonic {:?}", mnemonic),
-                });
+                    &insn,
+                    insn_offset,
+                    &mut *strings,
+                    &mut *symbols,
+                    &mut *relocations,
+                    compress,
+                    &mut *extensions,
+                    &mut *code_and_data,
+                ).map_err(|e| match e {
+                    AssemblerError::Syntax { msg, .. } => {
+                        AssemblerError::Syntax { line_num, col_num: passed_pos, msg }
+                    },
+                    e => e,
+                })?
             }
         },
-        x => todo!("insn_type {:?}", x),
     };
 
     skip_whitespace(&mut chars);
@@ -681,55 +1357,187 @@ fn assemble_line2(
     Ok(insn_len)
 }
 
-fn main() {
+fn mnemonics() -> HashMap<&'static str, (InsnType, u32)> {
     let mut mnemonics = HashMap::new();
-    mnemonics.insert( "inval", (InsnType::X,    0x0000_0000));
-    mnemonics.insert(   "lui", (InsnType::U,    0x0000_0037));
-    mnemonics.insert( "auipc", (InsnType::U,    0x0000_0017));
-    mnemonics.insert(   "jal", (InsnType::J,    0x0000_006F));
-    mnemonics.insert(  "jalr", (InsnType::I,    0x0000_0067));
-    mnemonics.insert(   "ret", (InsnType::X,    0x0000_8067));
-    mnemonics.insert(   "beq", (InsnType::B,    0x0000_0063));
-    mnemonics.insert(   "bne", (InsnType::B,    0x0000_1063));
-    mnemonics.insert(   "blt", (InsnType::B,    0x0000_4063));
-    mnemonics.insert(   "bge", (InsnType::B,    0x0000_5063));
-    mnemonics.insert(  "bltu", (InsnType::B,    0x0000_6063));
-    mnemonics.insert(    "lb", (InsnType::I,    0x0000_0003));
-    mnemonics.insert(    "lh", (InsnType::I,    0x0000_1003));
-    mnemonics.insert(    "lw", (InsnType::I,    0x0000_2003));
-    mnemonics.insert(    "sb", (InsnType::S,    0x0000_0023));
-    mnemonics.insert(    "sh", (InsnType::S,    0x0000_1023));
-    mnemonics.insert(    "sw", (InsnType::S,    0x0000_2023));
-    mnemonics.insert(  "addi", (InsnType::I,    0x0000_0013));
-    mnemonics.insert(   "nop", (InsnType::X,    0x0000_0013));
-    mnemonics.insert(   "ori", (InsnType::I,    0x0000_6013));
-    mnemonics.insert(  "andi", (InsnType::I,    0x0000_7013));
-    mnemonics.insert(  "slli", (InsnType::Sxli, 0x0000_1013));
-    mnemonics.insert(  "srli", (InsnType::Sxli, 0x0000_5013));
-    mnemonics.insert(   "add", (InsnType::R,    0x0000_0033));
-    mnemonics.insert(   "sub", (InsnType::R,    0x4000_0033));
-    mnemonics.insert(  "sltu", (InsnType::R,    0x0000_3033));
-    mnemonics.insert(   "xor", (InsnType::R,    0x0000_4033));
-    mnemonics.insert(    "or", (InsnType::R,    0x0000_6033));
-    mnemonics.insert(   "and", (InsnType::R,    0x0000_7033));
-    mnemonics.insert( "fence", (InsnType::F,    0x0000_000F));
-    mnemonics.insert( "ecall", (InsnType::X,    0x0000_0073));
-    mnemonics.insert("ebreak", (InsnType::X,    0x0010_0073));
-    mnemonics.insert( "csrrw", (InsnType::C,    0x0000_1073));
-    mnemonics.insert( "csrrs", (InsnType::C,    0x0000_2073));
-    mnemonics.insert( "csrrc", (InsnType::C,    0x0000_3073));
-    mnemonics.insert("csrrsi", (InsnType::Ci,   0x0000_6073));
-    mnemonics.insert(  "mret", (InsnType::X,    0x3020_0073));
-    mnemonics.insert(   "wfi", (InsnType::X,    0x1050_0073));
-    mnemonics.insert(    "li", (InsnType::P,              0));
-    mnemonics.insert( ".utf8", (InsnType::P,              0));
-    // TODO: .extern
-    let mnemonics = mnemonics;
-
-    let args: Vec<_> = env::args_os().collect();
+    mnemonics.insert(     "inval", (InsnType::X,    0x0000_0000));
+    mnemonics.insert(       "lui", (InsnType::U,    0x0000_0037));
+    mnemonics.insert(     "auipc", (InsnType::U,    0x0000_0017));
+    mnemonics.insert(       "jal", (InsnType::J,    0x0000_006F));
+    mnemonics.insert(      "jalr", (InsnType::I,    0x0000_0067));
+    mnemonics.insert(       "ret", (InsnType::X,    0x0000_8067));
+    mnemonics.insert(       "beq", (InsnType::B,    0x0000_0063));
+    mnemonics.insert(       "bne", (InsnType::B,    0x0000_1063));
+    mnemonics.insert(       "blt", (InsnType::B,    0x0000_4063));
+    mnemonics.insert(       "bge", (InsnType::B,    0x0000_5063));
+    mnemonics.insert(      "bltu", (InsnType::B,    0x0000_6063));
+    mnemonics.insert(      "bgeu", (InsnType::B,    0x0000_7063));
+    mnemonics.insert(        "lb", (InsnType::I,    0x0000_0003));
+    mnemonics.insert(        "lh", (InsnType::I,    0x0000_1003));
+    mnemonics.insert(        "lw", (InsnType::I,    0x0000_2003));
+    mnemonics.insert(       "lbu", (InsnType::I,    0x0000_4003));
+    mnemonics.insert(       "lhu", (InsnType::I,    0x0000_5003));
+    mnemonics.insert(        "sb", (InsnType::S,    0x0000_0023));
+    mnemonics.insert(        "sh", (InsnType::S,    0x0000_1023));
+    mnemonics.insert(        "sw", (InsnType::S,    0x0000_2023));
+    mnemonics.insert(      "addi", (InsnType::I,    0x0000_0013));
+    mnemonics.insert(       "nop", (InsnType::X,    0x0000_0013));
+    mnemonics.insert(      "slti", (InsnType::I,    0x0000_2013));
+    mnemonics.insert(     "sltiu", (InsnType::I,    0x0000_3013));
+    mnemonics.insert(      "xori", (InsnType::I,    0x0000_4013));
+    mnemonics.insert(       "ori", (InsnType::I,    0x0000_6013));
+    mnemonics.insert(      "andi", (InsnType::I,    0x0000_7013));
+    mnemonics.insert(      "slli", (InsnType::Sxli, 0x0000_1013));
+    mnemonics.insert(      "srli", (InsnType::Sxli, 0x0000_5013));
+    mnemonics.insert(      "srai", (InsnType::Sxli, 0x4000_5013));
+    mnemonics.insert(       "add", (InsnType::R,    0x0000_0033));
+    mnemonics.insert(       "sub", (InsnType::R,    0x4000_0033));
+    mnemonics.insert(       "sll", (InsnType::R,    0x0000_1033));
+    mnemonics.insert(       "slt", (InsnType::R,    0x0000_2033));
+    mnemonics.insert(      "sltu", (InsnType::R,    0x0000_3033));
+    mnemonics.insert(       "xor", (InsnType::R,    0x0000_4033));
+    mnemonics.insert(       "srl", (InsnType::R,    0x0000_5033));
+    mnemonics.insert(       "sra", (InsnType::R,    0x4000_5033));
+    mnemonics.insert(        "or", (InsnType::R,    0x0000_6033));
+    mnemonics.insert(       "and", (InsnType::R,    0x0000_7033));
+    mnemonics.insert(       "mul", (InsnType::R,    0x0200_0033));
+    mnemonics.insert(      "mulh", (InsnType::R,    0x0200_1033));
+    mnemonics.insert(    "mulhsu", (InsnType::R,    0x0200_2033));
+    mnemonics.insert(     "mulhu", (InsnType::R,    0x0200_3033));
+    mnemonics.insert(       "div", (InsnType::R,    0x0200_4033));
+    mnemonics.insert(      "divu", (InsnType::R,    0x0200_5033));
+    mnemonics.insert(       "rem", (InsnType::R,    0x0200_6033));
+    mnemonics.insert(      "remu", (InsnType::R,    0x0200_7033));
+    mnemonics.insert(      "lr.w", (InsnType::Lr,   0x1000_202F));
+    mnemonics.insert(      "sc.w", (InsnType::A,    0x1800_202F));
+    mnemonics.insert( "amoswap.w", (InsnType::A,    0x0800_202F));
+    mnemonics.insert(  "amoadd.w", (InsnType::A,    0x0000_202F));
+    mnemonics.insert(  "amoxor.w", (InsnType::A,    0x2000_202F));
+    mnemonics.insert(  "amoand.w", (InsnType::A,    0x6000_202F));
+    mnemonics.insert(   "amoor.w", (InsnType::A,    0x4000_202F));
+    mnemonics.insert(  "amomin.w", (InsnType::A,    0x8000_202F));
+    mnemonics.insert(  "amomax.w", (InsnType::A,    0xA000_202F));
+    mnemonics.insert( "amominu.w", (InsnType::A,    0xC000_202F));
+    mnemonics.insert( "amomaxu.w", (InsnType::A,    0xE000_202F));
+    mnemonics.insert(     "fence", (InsnType::F,    0x0000_000F));
+    mnemonics.insert(   "fence.i", (InsnType::X,    0x0000_100F));
+    mnemonics.insert(     "ecall", (InsnType::X,    0x0000_0073));
+    mnemonics.insert(    "ebreak", (InsnType::X,    0x0010_0073));
+    mnemonics.insert(     "csrrw", (InsnType::C,    0x0000_1073));
+    mnemonics.insert(     "csrrs", (InsnType::C,    0x0000_2073));
+    mnemonics.insert(     "csrrc", (InsnType::C,    0x0000_3073));
+    mnemonics.insert(    "csrrwi", (InsnType::Ci,   0x0000_5073));
+    mnemonics.insert(    "csrrsi", (InsnType::Ci,   0x0000_6073));
+    mnemonics.insert(    "csrrci", (InsnType::Ci,   0x0000_7073));
+    mnemonics.insert(      "mret", (InsnType::X,    0x3020_0073));
+    mnemonics.insert(       "wfi", (InsnType::X,    0x1050_0073));
+    mnemonics.insert("c.addi4spn", (InsnType::Rvc,       0x0000));
+    mnemonics.insert(      "c.lw", (InsnType::Rvc,       0x4000));
+    mnemonics.insert(      "c.sw", (InsnType::Rvc,       0xC000));
+    mnemonics.insert(     "c.nop", (InsnType::Rvc,       0x0001));
+    mnemonics.insert(    "c.addi", (InsnType::Rvc,       0x0001));
+    mnemonics.insert(     "c.jal", (InsnType::Rvc,       0x2001));
+    mnemonics.insert(      "c.li", (InsnType::Rvc,       0x4001));
+    mnemonics.insert("c.addi16sp", (InsnType::Rvc,       0x6101));
+    mnemonics.insert(     "c.lui", (InsnType::Rvc,       0x6001));
+    mnemonics.insert(    "c.srli", (InsnType::Rvc,       0x8001));
+    mnemonics.insert(    "c.srai", (InsnType::Rvc,       0x8401));
+    mnemonics.insert(    "c.andi", (InsnType::Rvc,       0x8801));
+    mnemonics.insert(     "c.sub", (InsnType::Rvc,       0x8C01));
+    mnemonics.insert(     "c.xor", (InsnType::Rvc,       0x8C21));
+    mnemonics.insert(      "c.or", (InsnType::Rvc,       0x8C41));
+    mnemonics.insert(     "c.and", (InsnType::Rvc,       0x8C61));
+    mnemonics.insert(       "c.j", (InsnType::Rvc,       0xA001));
+    mnemonics.insert(    "c.beqz", (InsnType::Rvc,       0xC001));
+    mnemonics.insert(    "c.bnez", (InsnType::Rvc,       0xE001));
+    mnemonics.insert(    "c.slli", (InsnType::Rvc,       0x0002));
+    mnemonics.insert(    "c.lwsp", (InsnType::Rvc,       0x4002));
+    mnemonics.insert(      "c.jr", (InsnType::Rvc,       0x8002));
+    mnemonics.insert(      "c.mv", (InsnType::Rvc,       0x8002));
+    mnemonics.insert(  "c.ebreak", (InsnType::Rvc,       0x9002));
+    mnemonics.insert(    "c.jalr", (InsnType::Rvc,       0x9002));
+    mnemonics.insert(     "c.add", (InsnType::Rvc,       0x9002));
+    mnemonics.insert(    "c.swsp", (InsnType::Rvc,       0xC002));
+    mnemonics.insert(        "li", (InsnType::P,              0));
+    mnemonics.insert(        "la", (InsnType::P,              0));
+    mnemonics.insert(       "lla", (InsnType::P,              0));
+    mnemonics.insert(        "mv", (InsnType::P,              0));
+    mnemonics.insert(       "not", (InsnType::P,              0));
+    mnemonics.insert(       "neg", (InsnType::P,              0));
+    mnemonics.insert(      "seqz", (InsnType::P,              0));
+    mnemonics.insert(      "snez", (InsnType::P,              0));
+    mnemonics.insert(      "sltz", (InsnType::P,              0));
+    mnemonics.insert(      "sgtz", (InsnType::P,              0));
+    mnemonics.insert(      "beqz", (InsnType::P,              0));
+    mnemonics.insert(      "bnez", (InsnType::P,              0));
+    mnemonics.insert(      "blez", (InsnType::P,              0));
+    mnemonics.insert(      "bgez", (InsnType::P,              0));
+    mnemonics.insert(      "bltz", (InsnType::P,              0));
+    mnemonics.insert(      "bgtz", (InsnType::P,              0));
+    mnemonics.insert(       "bgt", (InsnType::P,              0));
+    mnemonics.insert(       "ble", (InsnType::P,              0));
+    mnemonics.insert(      "bgtu", (InsnType::P,              0));
+    mnemonics.insert(      "bleu", (InsnType::P,              0));
+    mnemonics.insert(         "j", (InsnType::P,              0));
+    mnemonics.insert(        "jr", (InsnType::P,              0));
+    mnemonics.insert(      "call", (InsnType::P,              0));
+    mnemonics.insert(      "tail", (InsnType::P,              0));
+    mnemonics.insert( "rdinstret", (InsnType::P,              0));
+    mnemonics.insert("rdinstreth", (InsnType::P,              0));
+    mnemonics.insert(   "rdcycle", (InsnType::P,              0));
+    mnemonics.insert(  "rdcycleh", (InsnType::P,              0));
+    mnemonics.insert(    "rdtime", (InsnType::P,              0));
+    mnemonics.insert(   "rdtimeh", (InsnType::P,              0));
+    mnemonics.insert(      "csrr", (InsnType::P,              0));
+    mnemonics.insert(      "csrw", (InsnType::P,              0));
+    mnemonics.insert(      "csrs", (InsnType::P,              0));
+    mnemonics.insert(      "csrc", (InsnType::P,              0));
+    mnemonics.insert(     "csrwi", (InsnType::P,              0));
+    mnemonics.insert(     "csrsi", (InsnType::P,              0));
+    mnemonics.insert(     "csrci", (InsnType::P,              0));
+    mnemonics.insert(     ".utf8", (InsnType::P,              0));
+    mnemonics.insert(     ".word", (InsnType::P,              0));
+    mnemonics.insert(     ".half", (InsnType::P,              0));
+    mnemonics.insert(     ".byte", (InsnType::P,              0));
+    mnemonics.insert(     ".zero", (InsnType::P,              0));
+    mnemonics.insert(    ".space", (InsnType::P,              0));
+    mnemonics.insert(    ".align", (InsnType::P,              0));
+    mnemonics.insert(   ".balign", (InsnType::P,              0));
+    mnemonics.insert(   ".extern", (InsnType::P,              0));
+    mnemonics.insert(   ".global", (InsnType::P,              0));
+    mnemonics
+}
+
+/// The extension (as an object's extensions bit) that an instruction from the mnemonic table
+/// needs, or 0 if it's in RV32I.
+fn extension(insn_type: InsnType, template: u32) -> u32 {
+    match insn_type {
+        InsnType::R if template >> 25 == 0x01 => EXT_M,
+        InsnType::A | InsnType::Lr => EXT_A,
+        InsnType::Rvc => EXT_C,
+        _ => 0,
+    }
+}
+
+fn main() {
+    let mnemonics = mnemonics();
+
+    let mut args: Vec<_> = env::args_os().collect();
+    // With --compress, instructions that have a 16-bit encoding use it, except ones whose
+    // immediate is a label, because that isn't known until the end. With --elf, the output is a
+    // relocatable ELF file instead of a sam object.
+    let mut compress = false;
+    let mut elf = false;
+    while args.len() > 1 {
+        match args[1].to_str() {
+            Some("--compress") => compress = true,
+            Some("--elf") => elf = true,
+            _ => break,
+        }
+        args.remove(1);
+    }
     assert_eq!(args.len(), 4);
     let load_address: u32 = from_hex(args[1].to_str().expect("load address must be valid Unicode"), 32).unwrap_or_else(ouch);
-    let mut input = fs::File::open(&args[2]).unwrap();
+    let input = fs::File::open(&args[2]).unwrap();
     let mut output = fs::OpenOptions::new()
         .read(true)
         .write(true)
@@ -751,7 +1559,8 @@ fn main() {
         0x00, 0x00, 0x00, 0x00, // symbol-table-offset
         0x00, 0x00, 0x00, 0x00, // relocation-table-offset
         0x01, 0x00, // arch = risc-v
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // reserved
+        0x00, 0x00, // reserved
+        0x00, 0x00, 0x00, 0x00, // extensions
     ]).unwrap_or_else(ouch);
 
     let load_address_offset = output.stream_position().unwrap_or_else(ouch) as u32;
@@ -764,6 +1573,7 @@ fn main() {
     let mut symbols: SymbolTable = Default::default();
     let mut relocations: RelocationTable = Default::default();
     let mut insn_offset: u32 = 0;
+    let mut extensions = 0;
 
     for (line_num, line) in io::BufReader::new(&input).lines().enumerate() {
         let mut line = line.unwrap();
@@ -778,27 +1588,43 @@ fn main() {
             &mut strings,
             &mut symbols,
             &mut relocations,
+            compress,
+            &mut extensions,
             &mut output,
         ).unwrap_or_else(ouch);
         insn_offset += byte_count as u32;
     }
+    // The string table has to be word-aligned, but compressed instructions or data might have left
+    // code and data short of a word.
+    let padding_len = insn_offset.wrapping_neg() & 0b11;
+    output.write_all(&vec![0; padding_len as usize]).unwrap_or_else(ouch);
 
     let string_table_offset = output.stream_position().unwrap_or_else(ouch) as u32;
 
     for reloc in &mut relocations.relocations {
         let sym = reloc.symbol(&symbols);
-        if !sym.is_external() {
-            print!("Applying relocation {:?} at offset {}\n", &reloc, u32_to_hex(reloc.offset));
-            // Apply it.
-            output.seek(SeekFrom::Start(
-                code_and_data_offset as u64 + reloc.offset as u64
-            )).unwrap_or_else(ouch);
-            let insn = read_u32(&mut output).unwrap_or_else(ouch);
-            let insn = reloc.apply(insn, &symbols).unwrap_or_else(ouch);
-            output.seek(SeekFrom::Start(
-                code_and_data_offset as u64 + reloc.offset as u64
-            )).unwrap_or_else(ouch);
-            output.write_all(&insn.to_le_bytes()).unwrap_or_else(ouch);
+        if !sym.is_defined() {
+            if !sym.is_external() {
+                ouch(AssemblerError::UndefinedLabel { label: sym.name(&strings).to_owned() })
+            }
+            // It's in another object, so leave the relocation for the linker.
+            continue;
+        }
+        // Apply it.
+        output.seek(SeekFrom::Start(
+            code_and_data_offset as u64 + reloc.offset as u64
+        )).unwrap_or_else(ouch);
+        let size = reloc.value.insn_size() as usize;
+        let insn = match size {
+            2 => read_u16(&mut output).unwrap_or_else(ouch) as u32,
+            _ => read_u32(&mut output).unwrap_or_else(ouch),
+        };
+        let insn = reloc.apply(insn, &symbols, load_address).unwrap_or_else(ouch);
+        output.seek(SeekFrom::Start(
+            code_and_data_offset as u64 + reloc.offset as u64
+        )).unwrap_or_else(ouch);
+        output.write_all(&insn.to_le_bytes()[..size]).unwrap_or_else(ouch);
+        if !reloc.value.is_absolute() {
             reloc.value = RelocationValue::UnusedEntry;
         }
     }
@@ -818,6 +1644,279 @@ fn main() {
     output.write_all(&string_table_offset.to_le_bytes()).unwrap_or_else(ouch);
     output.write_all(&symbol_table_offset.to_le_bytes()).unwrap_or_else(ouch);
     output.write_all(&relocation_table_offset.to_le_bytes()).unwrap_or_else(ouch);
+    output.seek(SeekFrom::Start(0x2C)).unwrap_or_else(ouch);
+    output.write_all(&extensions.to_le_bytes()).unwrap_or_else(ouch);
+
+    if elf {
+        output.seek(SeekFrom::Start(0)).unwrap_or_else(ouch);
+        let object = Object::deserialize(&mut output).unwrap_or_else(ouch);
+        output.seek(SeekFrom::Start(0)).unwrap_or_else(ouch);
+        output.set_len(0).unwrap_or_else(ouch);
+        elf::write_rel(&object, &mut output).unwrap_or_else(ouch);
+    }
 
     output.sync_data().unwrap();
 }
+
+/// Assembles lines the way main does, starting at offset 0, but leaves the relocations unapplied
+/// and the tables unwritten.
+#[cfg(test)]
+fn assemble_lines(lines: &[&str], compress: bool)
+    -> Result<(Vec<u8>, SymbolTable, RelocationTable, StringTable), AssemblerError>
+{
+    // The round-trip tests call this once per instruction, so only build the table once.
+    static MNEMONICS: std::sync::OnceLock<HashMap<&str, (InsnType, u32)>> =
+        std::sync::OnceLock::new();
+    let mnemonics = MNEMONICS.get_or_init(mnemonics);
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut symbols: SymbolTable = Default::default();
+    let mut relocations: RelocationTable = Default::default();
+    let mut code = Vec::new();
+    let mut offset = 0;
+    for (line_num, line) in lines.iter().enumerate() {
+        offset += assemble_line2(
+            mnemonics,
+            line_num,
+            line,
+            offset,
+            &mut strings,
+            &mut symbols,
+            &mut relocations,
+            compress,
+            &mut 0,
+            &mut code,
+        )?;
+    }
+    Ok((code, symbols, relocations, strings))
+}
+
+/// Disassembling any valid instruction word and assembling the result should give back the same
+/// word.
+#[test]
+fn test_round_trip() {
+    use sam::disasm::disassemble;
+
+    const OPCODES: [u32; 12] = [
+        0x37, 0x17, 0x6F, 0x67, 0x63, 0x03, 0x23, 0x13, 0x33, 0x2F, 0x0F, 0x73,
+    ];
+    const SPECIAL: [u32; 7] = [
+        0x0000_0000, // inval
+        0x0000_0013, // nop
+        0x0000_8067, // ret
+        0x0000_0073, // ecall
+        0x0010_0073, // ebreak
+        0x3020_0073, // mret
+        0x1050_0073, // wfi
+    ];
+
+    let mut state: u32 = 0x2545_F491;
+    let mut xorshift = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    let random = (0..0x4_0000).map(|_| {
+        let opcode = OPCODES[xorshift() as usize % OPCODES.len()];
+        (xorshift() & !0x7F) | opcode
+    });
+
+    let mut checked = 0;
+    for insn in SPECIAL.iter().copied().chain(random) {
+        let text = match disassemble(insn) {
+            Some(text) => text,
+            None => continue,
+        };
+        let (code, ..) = assemble_lines(&[&text], false)
+            .unwrap_or_else(|e| panic!("{:?} ({}): {}", text, u32_to_hex(insn), e));
+        assert_eq!(code, insn.to_le_bytes(), "{:?} ({})", text, u32_to_hex(insn));
+        checked += 1;
+    }
+    assert!(checked > 0x1_0000, "only checked {} words", checked);
+}
+
+#[test]
+fn test_round_trip_compressed() {
+    use sam::disasm::disassemble_compressed;
+
+    let mut checked = 0;
+    for half in 0..0x1_0000 {
+        let text = match disassemble_compressed(half) {
+            Some(text) => text,
+            None => continue,
+        };
+        let (code, ..) = assemble_lines(&[&text], false)
+            .unwrap_or_else(|e| panic!("{:?} (#{:04X}): {}", text, half, e));
+        assert_eq!(code, (half as u16).to_le_bytes(), "{:?} (#{:04X})", text, half);
+        checked += 1;
+    }
+    assert!(checked > 0x6000, "only checked {} halves", checked);
+}
+
+#[test]
+fn test_pseudo_instructions() {
+    let assemble = |text: &str| {
+        let (code, _, relocations, _) = assemble_lines(&[text], false)
+            .unwrap_or_else(|e| panic!("{:?}: {}", text, e));
+        (code, relocations.relocations.iter().map(|r| (r.offset, r.value)).collect::<Vec<_>>())
+    };
+
+    for &(pseudo, real) in &[
+        ("mv s0 a0", "addi s0 a0 #0"),
+        ("not a0 a1", "xori a0 a1 -#1"),
+        ("neg a0 a1", "sub a0 zero a1"),
+        ("seqz a0 a1", "sltiu a0 a1 #1"),
+        ("snez a0 a1", "sltu a0 zero a1"),
+        ("sltz a0 a1", "slt a0 a1 zero"),
+        ("sgtz a0 a1", "slt a0 zero a1"),
+        ("beqz a0 -#8", "beq a0 zero -#8"),
+        ("bnez a0 -#8", "bne a0 zero -#8"),
+        ("blez a0 -#8", "bge zero a0 -#8"),
+        ("bgez a0 -#8", "bge a0 zero -#8"),
+        ("bltz a0 -#8", "blt a0 zero -#8"),
+        ("bgtz a0 -#8", "blt zero a0 -#8"),
+        ("bgt a0 a1 #10", "blt a1 a0 #10"),
+        ("ble a0 a1 #10", "bge a1 a0 #10"),
+        ("bgtu a0 a1 #10", "bltu a1 a0 #10"),
+        ("bleu a0 a1 #10", "bgeu a1 a0 #10"),
+        ("j -#4", "jal zero -#4"),
+        ("jal -#4", "jal ra -#4"),
+        ("jr t0", "jalr zero t0 #0"),
+        ("jalr t0", "jalr ra t0 #0"),
+        ("rdcycle a0", "csrrs a0 zero #C00"),
+        ("rdtimeh a0", "csrrs a0 zero #C81"),
+        ("rdinstret a0", "csrrs a0 zero #C02"),
+        ("csrr t0 mcause", "csrrs t0 zero #342"),
+        ("csrw t0 mscratch", "csrrw zero t0 #340"),
+        ("csrs t0 mie", "csrrs zero t0 #304"),
+        ("csrc t0 mie", "csrrc zero t0 #304"),
+        ("csrwi #1 mie", "csrrwi zero #1 #304"),
+        ("csrsi #8 mstatus", "csrrsi zero #8 #300"),
+        ("csrci #8 mstatus", "csrrci zero #8 #300"),
+        ("call #1234'5678", "auipc ra #1234'5\njalr ra ra #678"),
+        ("tail -#4", "auipc t1 #0\njalr zero t1 -#4"),
+        ("call loop", "auipc ra #0\njalr ra ra #4"),
+        ("tail loop", "auipc t1 #0\njalr zero t1 #4"),
+        ("la a0 loop", "auipc a0 #0\naddi a0 a0 #4"),
+    ] {
+        let mut expected = Vec::new();
+        for line in real.lines() {
+            expected.extend(assemble(line).0);
+        }
+        let (code, relocations) = assemble(pseudo);
+        assert_eq!(code, expected, "{:?}", pseudo);
+        if pseudo.ends_with("loop") {
+            assert!(matches!(
+                relocations[..],
+                [(0, RelocationValue::RelUType), (4, RelocationValue::RelIType)],
+            ), "{:?}", pseudo);
+        }
+    }
+}
+
+#[test]
+fn test_extern_and_global() {
+    let lines = [".global early", "$early", "jal ra puts", ".extern puts", "$late", ".global late"];
+    let (_, symbols, _, mut strings) = assemble_lines(&lines, false)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let mut symbol = |name| symbols.get(strings.get_index_or_insert(name)).unwrap();
+    for (name, defined) in [("early", true), ("late", true), ("puts", false)] {
+        let sym = symbol(name);
+        assert!(sym.is_external(), "{}", name);
+        assert_eq!(sym.is_defined(), defined, "{}", name);
+    }
+}
+
+#[test]
+fn test_data_directives() {
+    let lines = [
+        "$code", "ebreak",
+        "$table", ".word code -#1", ".half #1234", ".byte #56 -#1", ".align #2",
+        "$buf", ".zero #3", ".balign #2", ".global buf",
+    ];
+    let (code, symbols, relocations, mut strings) = assemble_lines(&lines, false)
+        .unwrap_or_else(|e| panic!("{}", e));
+    assert_eq!(code[4..], [
+        0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0x34, 0x12, 0x56, 0xFF, // table
+        0, 0, 0, 0, // buf
+    ]);
+    assert!(matches!(relocations.relocations[..], [Relocation {
+        offset: 4,
+        value: RelocationValue::Abs32,
+        ..
+    }]));
+    let mut symbol = |name| symbols.get(strings.get_index_or_insert(name)).unwrap().value;
+    assert!(matches!(symbol("code"), SymbolValue::Code { offset: Some(0), .. }));
+    assert!(matches!(symbol("table"), SymbolValue::Data { offset: Some(4), .. }));
+    assert!(matches!(symbol("buf"), SymbolValue::Data { external: true, offset: Some(16), .. }));
+
+    for line in [".half code", ".byte #100", ".align #3", ".balign #3", ".zero -#1", ".word"] {
+        assert!(assemble_lines(&[line], false).is_err(), "{:?}", line);
+    }
+
+    // An instruction right after an odd amount of data could never run.
+    assert!(assemble_lines(&[".byte #1", "ebreak"], false).is_err());
+    assert!(assemble_lines(&[".byte #1", "$here", "j here"], true).is_err());
+    assert!(assemble_lines(&[".byte #1", ".align #1", "ebreak"], false).is_ok());
+    assert!(assemble_lines(&[".half #1", "c.ebreak"], true).is_ok());
+    assert!(assemble_lines(&[".byte #1", ".zero #FFFF'FFFF"], false).is_err());
+}
+
+#[test]
+fn test_hi_and_lo() {
+    let assemble = |line: &str| {
+        assemble_lines(&[line], true).map(|(code, _, relocations, _)| {
+            (code, relocations.relocations.last().map(|r| r.value))
+        })
+    };
+
+    // Not compressed, since the relocation needs the 32-bit form.
+    let (code, reloc) = assemble("lui a0 %hi(value)").unwrap();
+    assert_eq!((code, reloc.unwrap().insn_size()), (vec![0x37, 0x05, 0, 0], 4));
+    assert!(matches!(reloc, Some(RelocationValue::AbsUType)));
+    for line in ["addi a0 a0 %lo(value)", "lw a1 a0 %lo(value)"] {
+        assert!(matches!(assemble(line), Ok((_, Some(RelocationValue::AbsIType)))), "{:?}", line);
+    }
+    // Stores split the immediate, so they get their own kind, and stay 32-bit too.
+    let (code, reloc) = assemble("sw a0 t0 %lo(value)").unwrap();
+    assert_eq!(code, vec![0x23, 0xA0, 0xA2, 0x00]);
+    assert!(matches!(reloc, Some(RelocationValue::AbsSType)));
+    for line in [
+        "auipc a0 %hi(value)",
+        "lui a0 %lo(value)",
+        "addi a0 a0 %hi(value)",
+        "sw a0 t0 %hi(value)",
+        "lui a0",
+    ] {
+        assert!(assemble(line).is_err(), "{:?}", line);
+    }
+}
+
+#[test]
+fn test_branch_addends() {
+    assert_eq!(split_target("loop+#8", 13), Ok((Some("loop"), 8)));
+    assert_eq!(split_target("loop-#8", 13), Ok((Some("loop"), 0x1FF8)));
+    assert_eq!(split_target("-#8", 13), Ok((None, 0x1FF8)));
+    assert_eq!(split_target("ptr+0x4", 13), Ok((Some("ptr+0x4"), 0)));
+    assert!(split_target("loop+#2000", 13).is_err());
+
+    let assemble = |line: &str| {
+        assemble_lines(&[line], false).map(|(code, _, relocations, _)| {
+            (code, relocations.relocations.last().map(|r| r.value))
+        })
+    };
+
+    for (line, insn) in [
+        ("beq a0 a1 #8", "beq a0 a1 loop+#8"),
+        ("jal zero #10", "jal x0 table+#10"),
+        ("bne a0 zero -#4", "bnez a0 far-#4"),
+        ("c.j -#2", "c.j far-#2"),
+    ] {
+        let (expected, _) = assemble(line).unwrap();
+        let (code, reloc) = assemble(insn).unwrap();
+        assert_eq!(code, expected, "{:?}", insn);
+        assert!(reloc.is_some(), "{:?}", insn);
+    }
+    assert!(assemble("beq a0 a1 loop+#3").is_err());
+}
@@ -0,0 +1,60 @@
+use sam::archive::Archive;
+use sam::{ouch, Object};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+usage: sar ARCHIVE OBJECT...
+       sar --list ARCHIVE
+
+Bundles the objects into ARCHIVE, replacing it if it exists, for slink to take members from. Each
+member is named after its object's file name. With --list, prints each member's name and the
+labels it defines for other objects.";
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    match args.first().and_then(|arg| arg.to_str()) {
+        Some("--list") if args.len() == 2 => list(&args[1]),
+        Some(_) if args.len() >= 2 => create(&args[0], &args[1..]),
+        _ => usage_error(),
+    }
+}
+
+fn create(archive_path: &OsString, object_paths: &[OsString]) {
+    let mut archive = Archive::default();
+    for path in object_paths {
+        let input = fs::File::open(path).unwrap_or_else(ouch);
+        let object = Object::deserialize(io::BufReader::new(input)).unwrap_or_else(ouch);
+        let name = Path::new(path).file_name().unwrap_or(path).to_string_lossy().into_owned();
+        if archive.members.iter().any(|(other, _)| *other == name) {
+            eprintln!("sar: more than one object is named {}", name);
+            process::exit(1);
+        }
+        archive.members.push((name, object));
+    }
+
+    let mut output = io::BufWriter::new(fs::File::create(archive_path).unwrap_or_else(ouch));
+    archive.serialize(&mut output).unwrap_or_else(ouch);
+    output.into_inner().unwrap_or_else(ouch).sync_data().unwrap_or_else(ouch);
+}
+
+fn list(archive_path: &OsString) {
+    let input = fs::File::open(archive_path).unwrap_or_else(ouch);
+    let archive = Archive::deserialize(io::BufReader::new(input)).unwrap_or_else(ouch);
+    for (name, object) in &archive.members {
+        let labels: Vec<_> = object.symbols.symbols.iter()
+            .filter(|sym| sym.is_external() && sym.is_defined())
+            .map(|sym| sym.name(&object.strings))
+            .collect();
+        println!("{}: {}", name, labels.join(" "));
+    }
+}
@@ -0,0 +1,32 @@
+use sam::{elf, from_hex, ouch};
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+const USAGE: &str = "\
+usage: sconv LOAD_ADDRESS ELF OBJECT
+
+Converts a relocatable ELF file (ET_REL), e.g. from GCC or LLVM, into a sam object for slink.
+LOAD_ADDRESS only matters if the object comes first when it's linked.";
+
+fn main() {
+    let args: Vec<_> = env::args_os().skip(1).collect();
+    if args.len() != 3 {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    let load_address = args[0].to_str()
+        .ok_or_else(|| "load address must be valid Unicode".to_owned())
+        .and_then(|s| from_hex(s, 32))
+        .unwrap_or_else(ouch);
+    let data = fs::read(&args[1]).unwrap_or_else(ouch);
+    let object = elf::read_rel(&data, load_address).unwrap_or_else(|e| {
+        eprintln!("sconv: {}: {}", args[1].to_string_lossy(), e);
+        process::exit(1);
+    });
+
+    let mut output = io::BufWriter::new(fs::File::create(&args[2]).unwrap_or_else(ouch));
+    object.serialize(&mut output).unwrap_or_else(ouch);
+    output.into_inner().unwrap_or_else(ouch).sync_data().unwrap_or_else(ouch);
+}
@@ -1,30 +1,58 @@
-use sam::{ouch, read_u32_at};
+use sam::records::{write_ihex, write_srec};
+use sam::{ouch, Object, SymbolValue};
 use std::env;
 use std::fs;
-use std::io::{self, prelude::*, SeekFrom};
+use std::io::{self, prelude::*};
+use std::process;
 
-fn main() {
-    let args: Vec<_> = env::args_os().collect();
-    assert!(args.len() == 3);
+const USAGE: &str = "\
+usage: scopy [--ihex | --srec] OBJECT OUTPUT
+
+Copies the object's code and data to OUTPUT, e.g. for qemu's -bios. With --ihex or --srec, OUTPUT
+is Intel HEX or Motorola S-records instead, with the code and data at the load address, for
+flashing tools, and the start address at $start if the object has it.";
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
 
-    let mut input = fs::File::open(&args[1]).unwrap_or_else(ouch);
-    let mut output = fs::File::create(&args[2]).unwrap_or_else(ouch);
+enum Format {
+    Raw,
+    Ihex,
+    Srec,
+}
 
-    // check magic
-    let mut buf = Vec::with_capacity(16);
-    buf.resize(16, 0);
-    input.read_exact(&mut buf).unwrap_or_else(ouch);
-    assert_eq!(&buf, &[
-        // magic = dc867b72-87f7-47da-a770-752af3299a3c
-        0xdc, 0x86, 0x7b, 0x72, 0x87, 0xf7, 0x47, 0xda,
-        0xa7, 0x70, 0x75, 0x2a, 0xf3, 0x29, 0x9a, 0x3c,
-    ]);
+fn main() {
+    let mut format = Format::Raw;
+    let mut paths = Vec::new();
+    for arg in env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("--ihex") => format = Format::Ihex,
+            Some("--srec") => format = Format::Srec,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() != 2 {
+        usage_error();
+    }
 
-    let code_and_data_offset = read_u32_at(&mut input, 0x18).unwrap_or_else(ouch);
-    let string_table_offset = read_u32_at(&mut input, 0x1C).unwrap_or_else(ouch);
-    let len = string_table_offset - code_and_data_offset;
-    input.seek(SeekFrom::Start(code_and_data_offset as u64)).unwrap_or_else(ouch);
-    io::copy(&mut input.take(len as u64), &mut output).unwrap_or_else(ouch);
+    let input = fs::File::open(&paths[0]).unwrap_or_else(ouch);
+    let object = Object::deserialize(io::BufReader::new(input)).unwrap_or_else(ouch);
+    let start = object.symbols.symbols.iter()
+        .find(|sym| sym.name(&object.strings) == "start")
+        .and_then(|sym| match sym.value {
+            SymbolValue::Code { offset, .. } => offset,
+            _ => None,
+        })
+        .map(|offset| object.load_address.wrapping_add(offset));
 
-    output.sync_data().unwrap();
+    let mut output = io::BufWriter::new(fs::File::create(&paths[1]).unwrap_or_else(ouch));
+    let (address, data) = (object.load_address, &object.code_and_data);
+    match format {
+        Format::Raw => output.write_all(data),
+        Format::Ihex => write_ihex(&mut output, address, data, start),
+        Format::Srec => write_srec(&mut output, address, data, start),
+    }.unwrap_or_else(ouch);
+    output.into_inner().unwrap_or_else(ouch).sync_data().unwrap_or_else(ouch);
 }
@@ -0,0 +1,363 @@
+use sam::{
+    disasm::{disassemble, disassemble_compressed, fetched_hex, REG_NAMES},
+    extension_letters,
+    insn::{funct3, imm_b, imm_i, imm_j, opcode, rd, rs1},
+    ouch,
+    rvc,
+    u32_to_hex,
+    Labels,
+    Object,
+    RelocationValue,
+    SymbolValue,
+    EXT_C,
+};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::process;
+
+const USAGE: &str = "\
+usage: sdis [--reassemblable] OBJECT
+
+With --reassemblable, the output assembles back into the same code and data, or sdis fails if it
+can't.";
+
+fn relocation_kind(value: RelocationValue) -> &'static str {
+    match value {
+        RelocationValue::UnusedEntry => "unused",
+        RelocationValue::RelCodeBType => "rel-code-b-type",
+        RelocationValue::RelCodeJType => "rel-code-j-type",
+        RelocationValue::RelUType => "rel-u-type",
+        RelocationValue::RelIType => "rel-i-type",
+        RelocationValue::RelCodeCbType => "rel-code-cb-type",
+        RelocationValue::RelCodeCjType => "rel-code-cj-type",
+        RelocationValue::Abs32 => "abs-32",
+        RelocationValue::AbsUType => "abs-u-type",
+        RelocationValue::AbsIType => "abs-i-type",
+        RelocationValue::AbsSType => "abs-s-type",
+    }
+}
+
+/// Where a branch or jump goes, if insn is one. A compressed one goes wherever its expansion does.
+fn target(addr: u32, insn: u32, size: u32) -> Option<u32> {
+    let insn = if size == 2 { rvc::expand(insn)? } else { insn };
+    match opcode(insn) {
+        0x63 => Some(addr.wrapping_add(imm_b(insn))),
+        0x6F => Some(addr.wrapping_add(imm_j(insn))),
+        _ => None,
+    }
+}
+
+/// The pseudo-instruction that the assembler turns into auipc and then second, with relocations
+/// against name for both, if there is one.
+fn auipc_pseudo(auipc: u32, second: u32, name: &str) -> Option<String> {
+    // The second instruction's relocation is relative to the auipc, which is 4 bytes back.
+    if opcode(auipc) != 0x17 || auipc >> 12 != 0 || imm_i(second) != 4 || rs1(second) != rd(auipc) {
+        return None;
+    }
+    match (opcode(second), funct3(second), rd(second), rd(auipc)) {
+        (0x13, 0b000, rd, _) if rd == rs1(second) => {
+            Some(format!("la {} {}", REG_NAMES[rd as usize], name))
+        },
+        (0x67, 0b000, 1, 1) => Some(format!("call {}", name)),
+        (0x67, 0b000, 0, 6) => Some(format!("tail {}", name)),
+        _ => None,
+    }
+}
+
+/// Bytes that aren't instructions as a .utf8 directive, if the assembler would turn it back into
+/// exactly those bytes. It pads with NULs up to the next word boundary, so given bytes that end on
+/// one, trailing NULs are fine but no others are.
+fn utf8_directive(bytes: &[u8]) -> Option<String> {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    if len == 0 || bytes.len() - len >= 4 {
+        return None;
+    }
+    let s = std::str::from_utf8(&bytes[..len]).ok()?;
+    let mut directive = ".utf8 \"".to_owned();
+    for c in s.chars() {
+        match c {
+            // The assembler strips comments before parsing, and a line can't contain a line break.
+            '\0' | ';' | '\n' | '\r' => return None,
+            '"' | '\\' => {
+                directive.push('\\');
+                directive.push(c);
+            },
+            _ => directive.push(c),
+        }
+    }
+    directive.push('"');
+    Some(directive)
+}
+
+/// Bytes that aren't instructions or a string as a .word directive, if they're whole words.
+fn word_directive(bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    let words: Vec<_> = bytes.chunks(4)
+        .map(|b| u32_to_hex(u32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+        .collect();
+    Some(format!(".word {}", words.join(" ")))
+}
+
+/// A string's worth of bytes for a comment, if they look like one.
+fn string_hint(bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|&b| b == 0 || b.is_ascii_graphic() || b == b' ') {
+        let s: String = bytes.iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect();
+        Some(format!("{:?}", s))
+    } else {
+        None
+    }
+}
+
+fn word_at(object: &Object, offset: u32) -> u32 {
+    let i = offset as usize;
+    let b = &object.code_and_data;
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+fn half_at(object: &Object, offset: u32) -> u32 {
+    let i = offset as usize;
+    let b = &object.code_and_data;
+    u16::from_le_bytes([b[i], b[i + 1]]) as u32
+}
+
+/// The instruction at offset and how many bytes it takes, or None if there aren't enough bytes
+/// left for one. Halves only count as compressed instructions if the object needs the C extension,
+/// since they're more likely to be data otherwise, and a zero word is inval rather than two
+/// illegal halves.
+fn fetch(object: &Object, offset: u32) -> Option<(u32, u32)> {
+    let rest = object.code_and_data.len() as u32 - offset;
+    let inval = rest >= 4 && word_at(object, offset) == 0;
+    if object.extensions & EXT_C != 0
+        && rest >= 2
+        && half_at(object, offset) & 0b11 != 0b11
+        && !inval
+    {
+        Some((half_at(object, offset), 2))
+    } else if rest >= 4 {
+        Some((word_at(object, offset), 4))
+    } else {
+        None
+    }
+}
+
+fn disassemble_sized(insn: u32, size: u32) -> Option<String> {
+    if size == 2 {
+        disassemble_compressed(insn)
+    } else {
+        disassemble(insn)
+    }
+}
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut reassemblable = false;
+    let mut path = None;
+    for arg in env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("--reassemblable") => reassemblable = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => usage_error(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage_error());
+
+    let input = fs::File::open(&path).unwrap_or_else(ouch);
+    let object = Object::deserialize(io::BufReader::new(input)).unwrap_or_else(ouch);
+    let labels = Labels::new(&object);
+
+    // offset -> (kind, symbol name)
+    let relocations: HashMap<u32, (RelocationValue, &str)> = object.relocations.relocations.iter()
+        .filter(|reloc| !matches!(reloc.value, RelocationValue::UnusedEntry))
+        .map(|reloc| {
+            let name = reloc.symbol(&object.symbols).name(&object.strings);
+            (reloc.offset, (reloc.value, name))
+        })
+        .collect();
+
+    let fail = |offset: u32, msg: &str| -> ! {
+        let addr = object.load_address.wrapping_add(offset);
+        eprintln!("sdis: can't reassemble {}: {}", u32_to_hex(addr), msg);
+        process::exit(1);
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "; load address {}", u32_to_hex(object.load_address)).unwrap_or_else(ouch);
+    if object.extensions != 0 {
+        writeln!(out, "; extensions {}", extension_letters(object.extensions)).unwrap_or_else(ouch);
+    }
+    for sym in object.symbols.symbols.iter().filter(|sym| sym.is_external()) {
+        let directive = if sym.is_defined() { ".global" } else { ".extern" };
+        writeln!(out, "{} {}", directive, sym.name(&object.strings)).unwrap_or_else(ouch);
+    }
+
+    let len = object.code_and_data.len() as u32;
+    let compressed = object.extensions & EXT_C != 0;
+    // Where data labels are. What follows one is data up to the next code label, even if it looks
+    // like instructions.
+    let data_labels: HashSet<u32> = object.symbols.symbols.iter()
+        .filter_map(|sym| match sym.value {
+            SymbolValue::Data { offset, .. } => offset,
+            _ => None,
+        })
+        .collect();
+    let mut in_data = false;
+    let mut offset = 0;
+    while offset < len {
+        let addr = object.load_address.wrapping_add(offset);
+        for name in labels.names_at(addr) {
+            writeln!(out, "${}", name).unwrap_or_else(ouch);
+            in_data = data_labels.contains(&offset);
+        }
+
+        // The assembler pads code that ends with a compressed instruction to a whole word.
+        if reassemblable && compressed && len - offset == 2 && half_at(&object, offset) == 0 {
+            break;
+        }
+        let (insn, size) = fetch(&object, offset).unwrap_or((0, 0));
+        if size == 0 {
+            if reassemblable {
+                fail(offset, "code and data don't end on a word boundary");
+            }
+            let bytes: Vec<_> = object.code_and_data[offset as usize..].iter()
+                .map(|b| format!("#{:02X}", b))
+                .collect();
+            writeln!(out, "{:<40}; {}  {}", "", u32_to_hex(addr), bytes.join(" "))
+                .unwrap_or_else(ouch);
+            break;
+        }
+        // A word holding a label's address, from .word.
+        if let Some(&(RelocationValue::Abs32, name)) = relocations.get(&offset)
+            .filter(|_| len - offset >= 4)
+        {
+            let text = if reassemblable {
+                format!(".word {}", name)
+            } else {
+                format!(".word {}", u32_to_hex(word_at(&object, offset)))
+            };
+            writeln!(out, "{:<40}; {}  abs-32 {}", text, u32_to_hex(addr), name)
+                .unwrap_or_else(ouch);
+            offset += 4;
+            continue;
+        }
+        // Only the pseudo-instruction can reproduce a pair of relocations against a label from
+        // another object.
+        if let Some(&(RelocationValue::RelUType, name)) = relocations.get(&offset)
+            .filter(|_| reassemblable && size == 4)
+        {
+            let pseudo = match relocations.get(&(offset + 4)) {
+                Some(&(RelocationValue::RelIType, second_name))
+                    if second_name == name
+                        && len - offset >= 8
+                        && labels.names_at(addr.wrapping_add(4)).next().is_none() =>
+                {
+                    auipc_pseudo(insn, word_at(&object, offset + 4), name)
+                },
+                _ => None,
+            };
+            let pseudo = pseudo.unwrap_or_else(|| fail(offset, "rel-u-type relocation"));
+            writeln!(out, "            {}", pseudo).unwrap_or_else(ouch);
+            offset += 8;
+            continue;
+        }
+        let hex = if compressed { fetched_hex(insn) } else { u32_to_hex(insn) };
+        let mut comment = format!("{}  {}", u32_to_hex(addr), hex);
+
+        let text = match disassemble_sized(insn, size).filter(|_| !(reassemblable && in_data)) {
+            Some(text) => match relocations.get(&offset) {
+                Some(&(kind, name)) => {
+                    comment += &format!("  {} {}", relocation_kind(kind), name);
+                    match kind {
+                        _ if !reassemblable => text,
+                        RelocationValue::RelCodeBType
+                        | RelocationValue::RelCodeJType
+                        | RelocationValue::RelCodeCbType
+                        | RelocationValue::RelCodeCjType => {
+                            // The offset is only the addend until the relocation is applied, so
+                            // name the target instead.
+                            let operands: Vec<_> = text.split(' ').collect();
+                            let operands = operands[..operands.len() - 1].join(" ");
+                            match kind.split_addend(insn).1 as i32 {
+                                0 => format!("{} {}", operands, name),
+                                a if a < 0 => format!("{} {}-#{:X}", operands, name, -a),
+                                a => format!("{} {}+#{:X}", operands, name, a),
+                            }
+                        },
+                        RelocationValue::AbsUType
+                        | RelocationValue::AbsIType
+                        | RelocationValue::AbsSType => {
+                            let operands: Vec<_> = text.split(' ').collect();
+                            let op = if let RelocationValue::AbsUType = kind { "hi" } else { "lo" };
+                            let operands = operands[..operands.len() - 1].join(" ");
+                            format!("{} %{}({})", operands, op, name)
+                        },
+                        _ => fail(offset, &format!("{} relocation", relocation_kind(kind))),
+                    }
+                },
+                None => {
+                    if let Some(target) = target(addr, insn, size) {
+                        let label = labels.symbolize(target).unwrap_or_else(|| u32_to_hex(target));
+                        comment += &format!("  -> {}", label);
+                    }
+                    text
+                },
+            },
+            None if reassemblable => {
+                // Take every following word that isn't an instruction either, up to the next
+                // label, so a string comes out as one directive. It has to end on a word boundary
+                // because that's where the assembler's padding ends.
+                let mut end = ((offset + 4) & !0b11).min(len);
+                while len - end >= 4
+                    && (in_data || fetch(&object, end)
+                        .is_none_or(|(insn, size)| disassemble_sized(insn, size).is_none()))
+                    && labels.names_at(object.load_address.wrapping_add(end)).next().is_none()
+                    && !relocations.contains_key(&end)
+                {
+                    end += 4;
+                }
+                let bytes = &object.code_and_data[offset as usize..end as usize];
+                let directive = utf8_directive(bytes)
+                    .or_else(|| word_directive(bytes))
+                    .unwrap_or_else(|| fail(offset, "not an instruction or a string"));
+                writeln!(out, "{}", directive).unwrap_or_else(ouch);
+                offset = end;
+                continue;
+            },
+            None => {
+                if let Some(hint) = string_hint(&insn.to_le_bytes()[..size as usize]) {
+                    comment += &format!("  {}", hint);
+                }
+                "???".to_owned()
+            },
+        };
+        writeln!(out, "            {:<28}; {}", text, comment).unwrap_or_else(ouch);
+        offset += size;
+    }
+}
+
+#[test]
+fn test_utf8_directive() {
+    assert_eq!(utf8_directive(b"hi there!\0\0\0").unwrap(), ".utf8 \"hi there!\"");
+    assert_eq!(utf8_directive(b"\"\\\"\0").unwrap(), ".utf8 \"\\\"\\\\\\\"\"");
+    assert!(utf8_directive(b"a;b\0").is_none());
+    assert!(utf8_directive(b"a\0b\0").is_none());
+    assert!(utf8_directive(b"\0\0\0\0").is_none());
+}
+
+#[test]
+fn test_word_directive() {
+    assert_eq!(word_directive(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap(),
+        ".word #0000'0000 #FFFF'FFFF");
+    assert!(word_directive(&[0, 0]).is_none());
+}
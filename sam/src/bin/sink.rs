@@ -1,21 +1,15 @@
 use sam::{
     ouch,
-    Relocation,
-    read_len_prefixed_str,
     read_u8,
-    read_u16,
-    read_u32,
     read_u32_at,
     RelocationTable,
     StringTable,
-    Symbol,
     SymbolTable,
     u32_to_hex,
 };
-use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{self, prelude::*, SeekFrom};
+use std::io::{prelude::*, SeekFrom};
 
 fn main() {
     let args: Vec<_> = env::args_os().collect();
@@ -25,8 +19,7 @@ fn main() {
         let mut input = fs::File::open(arg).unwrap_or_else(ouch);
 
         // check magic
-        let mut buf = Vec::with_capacity(16);
-        buf.resize(16, 0);
+        let mut buf = vec![0; 16];
         input.read_exact(&mut buf).unwrap_or_else(ouch);
         assert_eq!(&buf, &[
             // magic = dc867b72-87f7-47da-a770-752af3299a3c
@@ -0,0 +1,80 @@
+use sam::archive::Archive;
+use sam::link::{link, pull_members};
+use sam::{elf, ouch, Object};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, prelude::*, SeekFrom};
+use std::process;
+
+const USAGE: &str = "\
+usage: slink [--bios | --elf] INPUT... OUTPUT
+
+Links the objects into one, in order, starting at the first one's load address. Labels from
+.global and .extern are shared between objects, and the rest stay private. An INPUT can also be an
+archive from sar, whose members only go in, after the objects, if they define a label that's
+still needed. With --bios, OUTPUT is just the code and data, like scopy makes, for qemu's -bios,
+and every label has to be defined. With --elf, OUTPUT is an ELF executable if every label is
+defined and a relocatable ELF file otherwise.";
+
+fn usage_error() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let mut bios = false;
+    let mut elf = false;
+    let mut paths: Vec<OsString> = Vec::new();
+    for arg in env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("--bios") => bios = true,
+            Some("--elf") => elf = true,
+            _ => paths.push(arg),
+        }
+    }
+    let output_path = paths.pop().unwrap_or_else(|| usage_error());
+    if paths.is_empty() || bios && elf {
+        usage_error();
+    }
+
+    let mut objects = Vec::new();
+    let mut archives = Vec::new();
+    for path in &paths {
+        let name = path.to_string_lossy().into_owned();
+        let mut input = io::BufReader::new(fs::File::open(path).unwrap_or_else(ouch));
+        let is_archive = Archive::is_archive(&mut input).unwrap_or_else(ouch);
+        input.seek(SeekFrom::Start(0)).unwrap_or_else(ouch);
+        if is_archive {
+            archives.push((name, Archive::deserialize(input).unwrap_or_else(ouch)));
+        } else {
+            objects.push((name, Object::deserialize(input).unwrap_or_else(ouch)));
+        }
+    }
+    if objects.is_empty() {
+        usage_error();
+    }
+    pull_members(&mut objects, archives);
+    let linked = link(&objects).unwrap_or_else(|e| {
+        eprintln!("slink: {}", e);
+        process::exit(1);
+    });
+
+    let unresolved = linked.unresolved();
+    if bios && !unresolved.is_empty() {
+        eprintln!("slink: no object defines {}", unresolved.join(" "));
+        process::exit(1);
+    }
+
+    let mut output = io::BufWriter::new(fs::File::create(&output_path).unwrap_or_else(ouch));
+    if bios {
+        output.write_all(&linked.code_and_data).unwrap_or_else(ouch);
+    } else if elf && unresolved.is_empty() {
+        elf::write_exec(&linked, &mut output).unwrap_or_else(ouch);
+    } else if elf {
+        elf::write_rel(&linked, &mut output).unwrap_or_else(ouch);
+    } else {
+        linked.serialize(&mut output).unwrap_or_else(ouch);
+    }
+    output.into_inner().unwrap_or_else(ouch).sync_data().unwrap_or_else(ouch);
+}
@@ -0,0 +1,394 @@
+use sam::{
+    elf,
+    emu::{self, gdb, monitor, pci, trace, virt, virtio, Bus, Halt, Hart, Step},
+    extension_letters,
+    from_hex,
+    ouch,
+    u32_to_hex,
+    Labels,
+    Object,
+};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, prelude::*, IsTerminal};
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Puts the terminal in raw mode (like `-nographic` does) for as long as it lives, so keystrokes
+/// go straight to the UART.
+struct RawTerminal {
+    saved: Option<String>,
+}
+
+impl RawTerminal {
+    fn new() -> Self {
+        if !io::stdin().is_terminal() {
+            return RawTerminal { saved: None };
+        }
+        let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output()
+            .ok()
+            .filter(|out| out.status.success())
+            .and_then(|out| String::from_utf8(out.stdout).ok());
+        let raw = RawTerminal { saved };
+        raw.set_raw(true);
+        raw
+    }
+
+    /// Switch between raw mode and however the terminal was before, e.g. so the monitor gets line
+    /// editing.
+    fn set_raw(&self, raw: bool) {
+        if let Some(ref saved) = self.saved {
+            if raw {
+                stty(&["raw", "-echo"]);
+            } else {
+                stty(&[saved.trim()]);
+            }
+        }
+    }
+
+    /// Raw mode doesn't turn "\n" into "\r\n", so we have to. (It doesn't hurt when the terminal
+    /// isn't raw.)
+    fn eol(&self) -> &'static str {
+        if self.saved.is_some() { "\r\n" } else { "\n" }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        self.set_raw(false);
+    }
+}
+
+fn stty(args: &[&str]) {
+    // If this fails, the terminal just stays how it was.
+    let _ = Command::new("stty").args(args).stdin(Stdio::inherit()).status();
+}
+
+enum Input {
+    Uart(u8),
+    Monitor,
+    Quit,
+}
+
+/// Read stdin, mostly for the UART. Like QEMU, Ctrl-A X quits and Ctrl-A C switches to the
+/// monitor.
+fn spawn_stdin_reader(tx: mpsc::Sender<Input>) {
+    thread::spawn(move || {
+        let mut ctrl_a = false;
+        for b in io::stdin().lock().bytes() {
+            let b = match b {
+                Ok(b) => b,
+                Err(_) => break,
+            };
+            let input = if ctrl_a {
+                ctrl_a = false;
+                match b {
+                    b'x' | b'X' => Input::Quit,
+                    b'c' | b'C' => Input::Monitor,
+                    0x01 => Input::Uart(b), // Ctrl-A Ctrl-A sends Ctrl-A
+                    _ => continue,
+                }
+            } else if b == 0x01 {
+                ctrl_a = true;
+                continue;
+            } else {
+                Input::Uart(b)
+            };
+            if tx.send(input).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+const USAGE: &str = "\
+usage: srun [OPTIONS] OBJECT
+
+OBJECT is a sam object or an ELF executable.
+
+options:
+    --pci VENDOR:DEVICE:CLASS   add a PCI function, e.g. '#1B36:#000D:#0C'0330'
+    --virtio-console PATH       add a virtio console that writes to PATH
+    --trace                     print every step to stderr
+    --gdb PORT                  wait for gdb to connect to localhost:PORT before starting
+    --debug                     start in the monitor (Ctrl-A C gets back to it)";
+
+struct Options {
+    object: OsString,
+    machine: virt::Config,
+    trace: bool,
+    gdb: Option<u16>,
+    debug: bool,
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("srun: {}\n{}", msg, USAGE);
+    process::exit(2);
+}
+
+fn parse_pci_function(spec: &str) -> Result<pci::Function, String> {
+    let parts: Vec<_> = spec.split(':').collect();
+    if parts.len() != 3 {
+        return Err(format!("expected VENDOR:DEVICE:CLASS but got {:?}", spec));
+    }
+    let vendor_id = from_hex(parts[0], 16)?;
+    let device_id = from_hex(parts[1], 16)?;
+    let class = from_hex(parts[2], 24)?;
+    Ok(pci::Function::new(vendor_id as u16, device_id as u16, class, 0))
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args_os().skip(1);
+    let mut object = None;
+    let mut machine: virt::Config = Default::default();
+    let mut trace = false;
+    let mut gdb = None;
+    let mut debug = false;
+    while let Some(arg) = args.next() {
+        let mut value = |name| args.next()
+            .unwrap_or_else(|| usage_error(&format!("{} needs a value", name)));
+        match arg.to_str() {
+            Some("--pci") => {
+                let spec = value("--pci");
+                let spec = spec.to_str().unwrap_or_else(|| usage_error("--pci must be valid Unicode"));
+                let f = parse_pci_function(spec).unwrap_or_else(|e| usage_error(&e));
+                machine.pci_functions.push(f);
+            },
+            Some("--debug") => debug = true,
+            Some("--gdb") => {
+                let port = value("--gdb");
+                let port = port.to_str().and_then(|s| s.parse().ok())
+                    .unwrap_or_else(|| usage_error("--gdb needs a port number"));
+                gdb = Some(port);
+            },
+            Some("--trace") => trace = true,
+            Some("--virtio-console") => {
+                let path = value("--virtio-console");
+                let out = fs::File::create(&path).unwrap_or_else(ouch);
+                machine.virtio_devices.push(Box::new(virtio::Console::new(Box::new(out))));
+            },
+            Some(s) if s.starts_with("--") => usage_error(&format!("unknown option {}", s)),
+            _ if object.is_none() => object = Some(arg),
+            _ => usage_error("too many arguments"),
+        }
+    }
+    if debug && gdb.is_some() {
+        usage_error("--debug and --gdb can't be used together");
+    }
+    Options {
+        object: object.unwrap_or_else(|| usage_error("missing object")),
+        machine,
+        trace,
+        gdb,
+        debug,
+    }
+}
+
+/// Read and run monitor commands until one resumes the hart. Returns false to quit.
+fn monitor_repl(
+    monitor: &mut monitor::Monitor,
+    hart: &Hart,
+    bus: &Bus,
+    labels: &Labels,
+    input_rx: &mpsc::Receiver<Input>,
+    eol: &str,
+) -> bool {
+    loop {
+        eprint!("(srun) ");
+        let mut line = Vec::new();
+        loop {
+            match input_rx.recv() {
+                Ok(Input::Uart(b'\n')) => break,
+                Ok(Input::Uart(b)) => line.push(b),
+                Ok(Input::Monitor) => (),
+                Ok(Input::Quit) | Err(_) => return false,
+            }
+        }
+        let line = String::from_utf8_lossy(&line);
+        match monitor.command(&line, hart, bus, labels) {
+            Ok(monitor::Reply::Lines(lines)) => {
+                for line in lines {
+                    eprint!("{}{}", line, eol);
+                }
+            },
+            Ok(monitor::Reply::Resume) => return true,
+            Ok(monitor::Reply::Quit) => return false,
+            Err(e) => eprint!("error: {}{}", e, eol),
+        }
+    }
+}
+
+fn run(object: &Object, entry: u32, options: Options, raw: &RawTerminal) -> i32 {
+    let eol = raw.eol();
+    let labels = Labels::new(object);
+    let (input_tx, input_rx) = mpsc::channel();
+    spawn_stdin_reader(input_tx);
+    let (uart_tx, uart_rx) = mpsc::channel();
+    let mut bus = virt::bus(uart_rx, Box::new(io::stdout()), options.machine);
+    let mut gdb = options.gdb.map(|port| {
+        eprint!("srun: waiting for gdb on localhost:{}{}", port, eol);
+        gdb::Stub::accept(port).unwrap_or_else(ouch)
+    });
+    let mut monitor = if options.debug { Some(monitor::Monitor::new()) } else { None };
+
+    'reset: loop {
+        if bus.load_image(object.load_address, &object.code_and_data).is_none() {
+            eprint!(
+                "code and data ({} bytes at {}) don't fit in RAM{}",
+                object.code_and_data.len(),
+                u32_to_hex(object.load_address),
+                eol,
+            );
+            return 1;
+        }
+        if let Some(ref mut gdb) = gdb {
+            gdb.reload(&mut bus);
+        }
+        let mut hart = Hart::new(entry);
+        let mut stop = Some("start".to_owned());
+
+        loop {
+            for _ in 0..0x1000 {
+                if let Some(ref mut monitor) = monitor {
+                    if let Some(reason) = stop.take() {
+                        let current = monitor.current(&hart, &bus, &labels);
+                        eprint!("{}{}{}{}", reason, eol, current, eol);
+                        raw.set_raw(false);
+                        let resume = monitor_repl(monitor, &hart, &bus, &labels, &input_rx, eol);
+                        raw.set_raw(true);
+                        if !resume {
+                            return 0;
+                        }
+                    }
+                }
+                if let Some(ref mut stub) = gdb {
+                    match stub.before_step(&mut hart, &mut bus) {
+                        Ok(gdb::Action::Run) => (),
+                        Ok(gdb::Action::Detach) => gdb = None,
+                        Ok(gdb::Action::Kill) => return 0,
+                        Err(e) => {
+                            eprint!("{}srun: lost gdb: {}{}", eol, e, eol);
+                            gdb = None;
+                        },
+                    }
+                }
+                let step = hart.step(&mut bus);
+                if let Some(ref mut stub) = gdb {
+                    if let Err(e) = stub.after_step(&mut bus) {
+                        eprint!("{}srun: lost gdb: {}{}", eol, e, eol);
+                        gdb = None;
+                    }
+                }
+                if options.trace {
+                    if let Ok(ref step) = step {
+                        if let Some(line) = trace::format_step(step, &hart, &labels) {
+                            eprint!("{}{}", line, eol);
+                        }
+                    }
+                }
+                if let (Some(ref mut monitor), Ok(ref step)) = (&mut monitor, &step) {
+                    stop = monitor.check(step, &hart);
+                }
+                match step {
+                    Ok(Step::Waiting) => {
+                        thread::sleep(Duration::from_millis(1));
+                        break;
+                    },
+                    Ok(_) => (),
+                    Err(e) => {
+                        eprint!(
+                            "{}exception in trap handler at {}: {}{}",
+                            eol,
+                            u32_to_hex(hart.pc),
+                            e,
+                            eol,
+                        );
+                        for (i, x) in hart.x.iter().enumerate() {
+                            eprint!(
+                                "x{:<2} = {}{}",
+                                i,
+                                u32_to_hex(*x),
+                                if i % 4 == 3 { eol } else { "  " },
+                            );
+                        }
+                        if let Some(ref mut stub) = gdb {
+                            let _ = stub.exited(Halt::Fail(1));
+                        }
+                        return 1;
+                    },
+                }
+                if let Some(halt) = bus.halt.take() {
+                    if let Some(ref mut stub) = gdb {
+                        // If gdb is gone, there's no one to tell.
+                        let _ = stub.exited(halt);
+                    }
+                    match halt {
+                        Halt::Pass => return 0,
+                        Halt::Fail(code) => return code as i32,
+                        Halt::Reset => {
+                            bus.reset();
+                            continue 'reset;
+                        },
+                    }
+                }
+            }
+            while let Ok(input) = input_rx.try_recv() {
+                match input {
+                    // The UART's end is only gone if the bus is, which it isn't.
+                    Input::Uart(b) => uart_tx.send(b).unwrap(),
+                    Input::Monitor if monitor.is_some() => stop = Some("interrupted".to_owned()),
+                    Input::Monitor => (),
+                    Input::Quit => {
+                        eprint!("{}srun: terminating{}", eol, eol);
+                        return 0;
+                    },
+                }
+            }
+            if let Some(ref mut stub) = gdb {
+                if let Err(e) = stub.poll_interrupt() {
+                    eprint!("{}srun: lost gdb: {}{}", eol, e, eol);
+                    gdb = None;
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+
+    // An ELF executable, e.g. from GCC or slink --elf, runs the same as a sam object, except that
+    // it starts at its entry point instead of where it loads.
+    let data = fs::read(&options.object).unwrap_or_else(ouch);
+    let (object, entry) = if data.starts_with(b"\x7FELF") {
+        elf::read_exec(&data)
+    } else {
+        Object::deserialize(io::Cursor::new(data)).map(|object| {
+            let entry = object.load_address;
+            (object, entry)
+        })
+    }.unwrap_or_else(|e| {
+        eprintln!("srun: {}: {}", options.object.to_string_lossy(), e);
+        process::exit(1);
+    });
+    let unsupported = object.extensions & !emu::EXTENSIONS;
+    if unsupported != 0 {
+        eprintln!("srun: the object needs extensions this hart doesn't have: {}",
+            extension_letters(unsupported));
+        process::exit(1);
+    }
+    let unresolved = object.unresolved();
+    if !unresolved.is_empty() {
+        eprintln!("srun: the object refers to labels from other objects: {}", unresolved.join(" "));
+        process::exit(1);
+    }
+
+    let status = {
+        let raw = RawTerminal::new();
+        run(&object, entry, options, &raw)
+    };
+    process::exit(status);
+}
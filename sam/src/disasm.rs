@@ -0,0 +1,278 @@
+//! Turning instruction words back into sam syntax.
+
+use crate::insn::*;
+use crate::rvc::{self, sext};
+use crate::{u32_to_hex, upper_imm20_to_hex};
+
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+fn reg(r: u32) -> &'static str {
+    REG_NAMES[r as usize]
+}
+
+/// Format the low width bits of x as a signed number, the way from_hex reads them. The most
+/// negative number has to be written without a '-' because from_hex can't negate it.
+pub fn signed_hex(x: u32, width: u32) -> String {
+    let x = x & (u32::MAX >> (32 - width));
+    let sign_bit = 1 << (width - 1);
+    if x & sign_bit != 0 && x != sign_bit {
+        format!("-#{:X}", x.wrapping_neg() & (u32::MAX >> (32 - width)))
+    } else {
+        format!("#{:X}", x)
+    }
+}
+
+fn pred_succ(n: u32) -> String {
+    "iorw".chars()
+        .zip([0x8, 0x4, 0x2, 0x1].iter())
+        .filter(|&(_, &b)| n & b != 0)
+        .map(|(c, _)| c)
+        .collect()
+}
+
+/// Returns None if insn isn't a valid RV32IMA or Zicsr instruction (or one of the few privileged
+/// instructions sam knows).
+pub fn disassemble(insn: u32) -> Option<String> {
+    let (rd, rs1, rs2) = (reg(rd(insn)), reg(rs1(insn)), reg(rs2(insn)));
+    Some(match opcode(insn) {
+        0x37 => format!("lui {} {}", rd, upper_imm20_to_hex(insn >> 12)),
+        0x17 => format!("auipc {} {}", rd, upper_imm20_to_hex(insn >> 12)),
+        0x6F => format!("jal {} {}", rd, signed_hex(imm_j(insn), 21)),
+        0x67 if funct3(insn) == 0 => {
+            if insn == 0x0000_8067 {
+                "ret".to_owned()
+            } else {
+                format!("jalr {} {} {}", rd, rs1, signed_hex(imm_i(insn), 12))
+            }
+        },
+        0x63 => {
+            let mnemonic = match funct3(insn) {
+                0 => "beq",
+                1 => "bne",
+                4 => "blt",
+                5 => "bge",
+                6 => "bltu",
+                7 => "bgeu",
+                _ => return None,
+            };
+            format!("{} {} {} {}", mnemonic, rs1, rs2, signed_hex(imm_b(insn), 13))
+        },
+        0x03 => {
+            let mnemonic = match funct3(insn) {
+                0 => "lb",
+                1 => "lh",
+                2 => "lw",
+                4 => "lbu",
+                5 => "lhu",
+                _ => return None,
+            };
+            format!("{} {} {} {}", mnemonic, rd, rs1, signed_hex(imm_i(insn), 12))
+        },
+        0x23 => {
+            let mnemonic = match funct3(insn) {
+                0 => "sb",
+                1 => "sh",
+                2 => "sw",
+                _ => return None,
+            };
+            format!("{} {} {} {}", mnemonic, rs2, rs1, signed_hex(imm_s(insn), 12))
+        },
+        0x13 => {
+            let shamt = (insn >> 20) & 0x1F;
+            match (funct3(insn), funct7(insn)) {
+                _ if insn == 0x0000_0013 => "nop".to_owned(),
+                (1, 0x00) => format!("slli {} {} #{:X}", rd, rs1, shamt),
+                (5, 0x00) => format!("srli {} {} #{:X}", rd, rs1, shamt),
+                (5, 0x20) => format!("srai {} {} #{:X}", rd, rs1, shamt),
+                (1, _) | (5, _) => return None,
+                (f3, _) => {
+                    let mnemonic = match f3 {
+                        0 => "addi",
+                        2 => "slti",
+                        3 => "sltiu",
+                        4 => "xori",
+                        6 => "ori",
+                        _ => "andi",
+                    };
+                    format!("{} {} {} {}", mnemonic, rd, rs1, signed_hex(imm_i(insn), 12))
+                },
+            }
+        },
+        0x33 => {
+            let mnemonic = match (funct3(insn), funct7(insn)) {
+                (0, 0x00) => "add",
+                (0, 0x20) => "sub",
+                (1, 0x00) => "sll",
+                (2, 0x00) => "slt",
+                (3, 0x00) => "sltu",
+                (4, 0x00) => "xor",
+                (5, 0x00) => "srl",
+                (5, 0x20) => "sra",
+                (6, 0x00) => "or",
+                (7, 0x00) => "and",
+                (0, 0x01) => "mul",
+                (1, 0x01) => "mulh",
+                (2, 0x01) => "mulhsu",
+                (3, 0x01) => "mulhu",
+                (4, 0x01) => "div",
+                (5, 0x01) => "divu",
+                (6, 0x01) => "rem",
+                (7, 0x01) => "remu",
+                _ => return None,
+            };
+            format!("{} {} {} {}", mnemonic, rd, rs1, rs2)
+        },
+        0x2F if funct3(insn) == 2 => {
+            let mnemonic = match insn >> 27 {
+                0x02 if (insn >> 20) & 0x1F == 0 => "lr.w",
+                0x03 => "sc.w",
+                0x01 => "amoswap.w",
+                0x00 => "amoadd.w",
+                0x04 => "amoxor.w",
+                0x0C => "amoand.w",
+                0x08 => "amoor.w",
+                0x10 => "amomin.w",
+                0x14 => "amomax.w",
+                0x18 => "amominu.w",
+                0x1C => "amomaxu.w",
+                _ => return None,
+            };
+            let ordering = match (insn >> 25) & 0b11 {
+                0b00 => "",
+                0b01 => ".rl",
+                0b10 => ".aq",
+                _ => ".aqrl",
+            };
+            if mnemonic == "lr.w" {
+                format!("{}{} {} ({})", mnemonic, ordering, rd, rs1)
+            } else {
+                format!("{}{} {} {} ({})", mnemonic, ordering, rd, rs2, rs1)
+            }
+        },
+        0x0F => {
+            if insn == 0x0000_100F {
+                "fence.i".to_owned()
+            } else if insn & 0xF00F_FFFF == 0x0000_000F && (insn >> 24) & 0xF != 0
+                && (insn >> 20) & 0xF != 0 {
+                format!("fence {} {}", pred_succ((insn >> 24) & 0xF), pred_succ((insn >> 20) & 0xF))
+            } else {
+                return None;
+            }
+        },
+        0x73 => match funct3(insn) {
+            0 => match insn {
+                0x0000_0073 => "ecall".to_owned(),
+                0x0010_0073 => "ebreak".to_owned(),
+                0x3020_0073 => "mret".to_owned(),
+                0x1050_0073 => "wfi".to_owned(),
+                _ => return None,
+            },
+            4 => return None,
+            f3 => {
+                let mnemonic = ["", "csrrw", "csrrs", "csrrc", "", "csrrwi", "csrrsi", "csrrci"]
+                    [f3 as usize];
+                let src = if f3 & 0b100 != 0 {
+                    format!("#{:X}", crate::insn::rs1(insn))
+                } else {
+                    rs1.to_owned()
+                };
+                format!("{} {} {} #{:X}", mnemonic, rd, src, csr(insn))
+            },
+        },
+        0x00 if insn == 0 => "inval".to_owned(),
+        _ => return None,
+    })
+}
+
+/// Like disassemble, but for a compressed instruction. Returns None if it's not one that
+/// rvc::expand understands.
+pub fn disassemble_compressed(half: u32) -> Option<String> {
+    rvc::expand(half)?;
+    let (rd, rs2) = (reg((half >> 7) & 0x1F), reg((half >> 2) & 0x1F));
+    let rd_ = reg(8 + ((half >> 2) & 0x7)); // rd' or rs2'
+    let rs1_ = reg(8 + ((half >> 7) & 0x7)); // rs1' or rd'
+    let ci = rvc::imm(half, &rvc::CI);
+    let imm = |layout| rvc::imm(half, layout);
+    Some(match (half & 0b11, half >> 13) {
+        (0b00, 0b000) => format!("c.addi4spn {} #{:X}", rd_, imm(&rvc::CIW)),
+        (0b00, 0b010) => format!("c.lw {} {} #{:X}", rd_, rs1_, imm(&rvc::CL)),
+        (0b00, 0b110) => format!("c.sw {} {} #{:X}", rd_, rs1_, imm(&rvc::CL)),
+        (0b01, 0b000) if half == 0x0001 => "c.nop".to_owned(),
+        (0b01, 0b000) => format!("c.addi {} {}", rd, signed_hex(ci, 6)),
+        (0b01, 0b001) => format!("c.jal {}", signed_hex(imm(&rvc::CJ), 12)),
+        (0b01, 0b010) => format!("c.li {} {}", rd, signed_hex(ci, 6)),
+        (0b01, 0b011) if rd == "sp" => {
+            format!("c.addi16sp {}", signed_hex(imm(&rvc::ADDI16SP), 10))
+        },
+        (0b01, 0b011) => format!("c.lui {} {}", rd, upper_imm20_to_hex(sext(ci, 6) & 0xF_FFFF)),
+        (0b01, 0b100) => match (half >> 10) & 0b11 {
+            0b00 => format!("c.srli {} #{:X}", rs1_, ci),
+            0b01 => format!("c.srai {} #{:X}", rs1_, ci),
+            0b10 => format!("c.andi {} {}", rs1_, signed_hex(ci, 6)),
+            _ => {
+                let mnemonic = ["c.sub", "c.xor", "c.or", "c.and"][(half as usize >> 5) & 0b11];
+                format!("{} {} {}", mnemonic, rs1_, rd_)
+            },
+        },
+        (0b01, 0b101) => format!("c.j {}", signed_hex(imm(&rvc::CJ), 12)),
+        (0b01, 0b110) => format!("c.beqz {} {}", rs1_, signed_hex(imm(&rvc::CB), 9)),
+        (0b01, 0b111) => format!("c.bnez {} {}", rs1_, signed_hex(imm(&rvc::CB), 9)),
+        (0b10, 0b000) => format!("c.slli {} #{:X}", rd, ci),
+        (0b10, 0b010) => format!("c.lwsp {} #{:X}", rd, imm(&rvc::LWSP)),
+        (0b10, 0b100) => match (half >> 12 & 1, rd, rs2) {
+            (0, _, "zero") => format!("c.jr {}", rd),
+            (0, _, _) => format!("c.mv {} {}", rd, rs2),
+            (_, "zero", "zero") => "c.ebreak".to_owned(),
+            (_, _, "zero") => format!("c.jalr {}", rd),
+            (_, _, _) => format!("c.add {} {}", rd, rs2),
+        },
+        (0b10, 0b110) => format!("c.swsp {} #{:X}", rs2, imm(&rvc::SWSP)),
+        _ => unreachable!("rvc::expand accepted {:#06x}", half),
+    })
+}
+
+/// What a hart with the C extension would execute given insn, the 32 bits at pc: just the low half
+/// if that's a compressed instruction.
+pub fn disassemble_fetched(insn: u32) -> Option<String> {
+    if insn & 0b11 == 0b11 {
+        disassemble(insn)
+    } else {
+        disassemble_compressed(insn & 0xFFFF)
+    }
+}
+
+/// An instruction's bits as they'd be fetched, so compressed instructions only show 16 bits. Always
+/// as wide as a 32-bit one so columns line up.
+pub fn fetched_hex(insn: u32) -> String {
+    if insn & 0b11 == 0b11 {
+        u32_to_hex(insn)
+    } else {
+        format!("{:>10}", format!("#{:04X}", insn & 0xFFFF))
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    assert_eq!(disassemble(0xFE11_2C23).unwrap(), "sw ra sp -#8");
+    assert_eq!(disassemble(0xFE00_0CE3).unwrap(), "beq zero zero -#8");
+    assert_eq!(disassemble(0x1000_00B7).unwrap(), "lui ra #1000'0");
+    assert_eq!(disassemble(0x3402_9073).unwrap(), "csrrw zero t0 #340");
+    assert_eq!(disassemble(0x8000_0113).unwrap(), "addi sp zero #800");
+    assert_eq!(disassemble(0x0C80_000F).unwrap(), "fence io i");
+    assert!(disassemble(0xFFFF_FFFF).is_none());
+}
+
+#[test]
+fn test_disassemble_compressed() {
+    assert_eq!(disassemble_compressed(0x41C8).unwrap(), "c.lw a0 a1 #4");
+    assert_eq!(disassemble_compressed(0x7139).unwrap(), "c.addi16sp -#40");
+    assert_eq!(disassemble_compressed(0xBFFD).unwrap(), "c.j -#2");
+    assert_eq!(disassemble_compressed(0x8082).unwrap(), "c.jr ra");
+    assert_eq!(disassemble_compressed(0x757D).unwrap(), "c.lui a0 #FFFF'F");
+    assert!(disassemble_compressed(0x0000).is_none());
+}
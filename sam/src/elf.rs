@@ -0,0 +1,1045 @@
+//! Converting between sam objects and ELF32 little-endian RISC-V files, for tools like GDB and
+//! objdump, and for code from GCC or LLVM.
+//!
+//! Written files have all of an object's code and data in one .text section. Code labels become
+//! STT_FUNC symbols and data labels STT_OBJECT, global if they're external and local otherwise.
+//!
+//! Read files go the other way: every allocated section goes into code-and-data, one after
+//! another, and a relocation against a section or with an addend gets a label of its own where it
+//! points, named like ".rodata+0x10", since sam relocations have no addends. Only the C extension
+//! is recorded, from e_flags, because the rest are only in .riscv.attributes.
+
+use crate::{
+    u32_to_hex,
+    DeserializationError,
+    Object,
+    Relocation,
+    RelocationTable,
+    RelocationValue,
+    StringTable,
+    SymbolTable,
+    SymbolValue,
+    EXT_C,
+};
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const EM_RISCV: u16 = 243;
+pub const EF_RISCV_RVC: u32 = 0x1;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_LORESERVE: u16 = 0xFF00;
+pub const SHF_WRITE: u32 = 0x1;
+pub const SHF_ALLOC: u32 = 0x2;
+pub const SHF_EXECINSTR: u32 = 0x4;
+pub const SHF_INFO_LINK: u32 = 0x40;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+pub const R_RISCV_32: u32 = 1;
+pub const R_RISCV_BRANCH: u32 = 16;
+pub const R_RISCV_JAL: u32 = 17;
+pub const R_RISCV_CALL: u32 = 18;
+pub const R_RISCV_CALL_PLT: u32 = 19;
+pub const R_RISCV_PCREL_HI20: u32 = 23;
+pub const R_RISCV_PCREL_LO12_I: u32 = 24;
+pub const R_RISCV_HI20: u32 = 26;
+pub const R_RISCV_LO12_I: u32 = 27;
+pub const R_RISCV_LO12_S: u32 = 28;
+pub const R_RISCV_RVC_BRANCH: u32 = 44;
+pub const R_RISCV_RVC_JUMP: u32 = 45;
+pub const R_RISCV_ALIGN: u32 = 43;
+pub const R_RISCV_RELAX: u32 = 51;
+
+const EHDR_SIZE: u32 = 0x34;
+const PHDR_SIZE: u32 = 0x20;
+const SHDR_SIZE: u32 = 0x28;
+const SYM_SIZE: u32 = 0x10;
+const RELA_SIZE: u32 = 0x0C;
+
+/// Section indices. .rela.text only exists in relocatable files.
+const TEXT: u32 = 1;
+const SYMTAB: u32 = 2;
+const STRTAB: u32 = 3;
+
+struct ElfSymbol {
+    name: u32, // offset in .strtab
+    value: u32,
+    info: u8,
+    shndx: u16,
+}
+
+struct Rela {
+    offset: u32,
+    symbol: (bool, u32), // (global, index among the local or global symbols)
+    kind: u32,
+    addend: u32,
+}
+
+#[derive(Default)]
+struct SectionHeader {
+    name: u32, // offset in .shstrtab
+    typ: u32,
+    flags: u32,
+    addr: u32,
+    place: (u32, u32), // (offset in file, size)
+    link: u32,
+    info: u32,
+    addralign: u32,
+    entsize: u32,
+}
+
+impl SectionHeader {
+    fn serialize(&self, mut writer: impl Write) -> io::Result<()> {
+        let (offset, size) = self.place;
+        for field in [
+            self.name,
+            self.typ,
+            self.flags,
+            self.addr,
+            offset,
+            size,
+            self.link,
+            self.info,
+            self.addralign,
+            self.entsize,
+        ] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn pad_to_word(buf: &mut Vec<u8>) {
+    buf.resize((buf.len() + 0b11) & !0b11, 0);
+}
+
+/// Appends s and a NUL to a string table, returning where it starts.
+fn add_str(table: &mut Vec<u8>, s: &str) -> u32 {
+    let offset = table.len() as u32;
+    table.extend_from_slice(s.as_bytes());
+    table.push(0);
+    offset
+}
+
+/// Writes an object as a relocatable ELF file (ET_REL), with the relocations it still has.
+pub fn write_rel(object: &Object, writer: impl Write) -> io::Result<()> {
+    write_elf(object, ET_REL, writer)
+}
+
+/// Writes an object as an executable ELF file (ET_EXEC) that loads at its load address and
+/// starts there. The object can't have any relocations left.
+pub fn write_exec(object: &Object, writer: impl Write) -> io::Result<()> {
+    let unresolved = object.unresolved();
+    if !unresolved.is_empty() {
+        return Err(invalid(format!("undefined labels: {}", unresolved.join(" "))));
+    }
+    write_elf(object, ET_EXEC, writer)
+}
+
+fn write_elf(object: &Object, kind: u16, mut writer: impl Write) -> io::Result<()> {
+    let exec = kind == ET_EXEC;
+    let base = if exec { object.load_address } else { 0 };
+    let mut code_and_data = object.code_and_data.clone();
+    let mut strtab = vec![0];
+    let symbol_value = |sym_value: &SymbolValue| match *sym_value {
+        SymbolValue::Code { external, offset, .. } => Some((STT_FUNC, external, offset)),
+        SymbolValue::Data { external, offset, .. } => Some((STT_OBJECT, external, offset)),
+        SymbolValue::Metadata { .. } => None,
+    };
+
+    // ELF wants local symbols before global ones, so work out where each sam symbol goes first.
+    let mut locals = vec![ElfSymbol { name: 0, value: 0, info: 0, shndx: 0 }];
+    let mut globals = Vec::new();
+    let mut indices = Vec::with_capacity(object.symbols.symbols.len());
+    for sym in &object.symbols.symbols {
+        let Some((typ, external, offset)) = symbol_value(&sym.value) else {
+            indices.push(None);
+            continue;
+        };
+        let elf_sym = ElfSymbol {
+            name: add_str(&mut strtab, sym.name(&object.strings)),
+            value: offset.map_or(0, |offset| base + offset),
+            info: (if external { STB_GLOBAL } else { STB_LOCAL }) << 4
+                | if offset.is_some() { typ } else { STT_NOTYPE },
+            shndx: if offset.is_some() { TEXT as u16 } else { 0 },
+        };
+        let list = if external { &mut globals } else { &mut locals };
+        indices.push(Some((external, list.len() as u32)));
+        list.push(elf_sym);
+    }
+
+    // ELF's R_RISCV_PCREL_LO12_I points at the auipc rather than the target, so each one gets a
+    // label of its own there, and the addend sam keeps in the instruction goes.
+    let mut relas = Vec::new();
+    let mut pcrel_hi_count = 0;
+    // An executable's absolute relocations have been applied already, and it can't have others.
+    let relocations: Vec<&Relocation> = object.relocations.relocations.iter()
+        .filter(|reloc| !matches!(reloc.value, RelocationValue::UnusedEntry))
+        .filter(|reloc| !(exec && reloc.value.is_absolute()))
+        .collect();
+    for reloc in &relocations {
+        let symbol = indices[reloc.symbol_index as usize].ok_or_else(|| invalid(format!(
+            "relocation against metadata at {}",
+            u32_to_hex(reloc.offset),
+        )))?;
+        let kind = match reloc.value {
+            RelocationValue::UnusedEntry => unreachable!(),
+            RelocationValue::RelCodeBType => R_RISCV_BRANCH,
+            RelocationValue::RelCodeJType => R_RISCV_JAL,
+            RelocationValue::RelUType => R_RISCV_PCREL_HI20,
+            RelocationValue::RelCodeCbType => R_RISCV_RVC_BRANCH,
+            RelocationValue::RelCodeCjType => R_RISCV_RVC_JUMP,
+            RelocationValue::Abs32 => R_RISCV_32,
+            RelocationValue::AbsUType => R_RISCV_HI20,
+            RelocationValue::AbsIType => R_RISCV_LO12_I,
+            RelocationValue::AbsSType => R_RISCV_LO12_S,
+            RelocationValue::RelIType => {
+                let hi = reloc.offset.wrapping_sub(4);
+                let paired = relocations.iter().any(|other| {
+                    other.offset == hi
+                        && other.symbol_index == reloc.symbol_index
+                        && matches!(other.value, RelocationValue::RelUType)
+                });
+                if !paired {
+                    return Err(invalid(format!(
+                        "relocation at {} has no auipc before it, which ELF can't express",
+                        u32_to_hex(reloc.offset),
+                    )));
+                }
+                let start = reloc.offset as usize;
+                let mut insn = [0; 4];
+                insn.copy_from_slice(&code_and_data[start..start + 4]);
+                let insn = u32::from_le_bytes(insn) & 0xF_FFFF;
+                code_and_data[start..start + 4].copy_from_slice(&insn.to_le_bytes());
+                let name = format!(".Lpcrel_hi{}", pcrel_hi_count);
+                pcrel_hi_count += 1;
+                locals.push(ElfSymbol {
+                    name: add_str(&mut strtab, &name),
+                    value: base + hi,
+                    info: STB_LOCAL << 4 | STT_NOTYPE,
+                    shndx: TEXT as u16,
+                });
+                relas.push(Rela {
+                    offset: reloc.offset,
+                    symbol: (false, locals.len() as u32 - 1),
+                    kind: R_RISCV_PCREL_LO12_I,
+                    addend: 0,
+                });
+                continue;
+            },
+        };
+        // Branches and jumps keep their addends in the instruction, but ELF wants them in the
+        // relocation.
+        let start = reloc.offset as usize;
+        let bytes = &mut code_and_data[start..start + reloc.value.insn_size() as usize];
+        let mut insn = [0; 4];
+        insn[..bytes.len()].copy_from_slice(bytes);
+        let (insn, addend) = reloc.value.split_addend(u32::from_le_bytes(insn));
+        let len = bytes.len();
+        bytes.copy_from_slice(&insn.to_le_bytes()[..len]);
+        relas.push(Rela { offset: reloc.offset, symbol, kind, addend });
+    }
+    let first_global = locals.len() as u32;
+    if exec && !relas.is_empty() {
+        return Err(invalid("an executable can't have relocations".to_owned()));
+    }
+
+    let mut shstrtab = vec![0];
+    let text_name = add_str(&mut shstrtab, ".text");
+    let symtab_name = add_str(&mut shstrtab, ".symtab");
+    let strtab_name = add_str(&mut shstrtab, ".strtab");
+    let rela_name = add_str(&mut shstrtab, ".rela.text");
+    let shstrtab_name = add_str(&mut shstrtab, ".shstrtab");
+
+    let mut symtab = Vec::new();
+    for sym in locals.iter().chain(&globals) {
+        symtab.extend_from_slice(&sym.name.to_le_bytes());
+        symtab.extend_from_slice(&sym.value.to_le_bytes());
+        symtab.extend_from_slice(&0u32.to_le_bytes()); // size
+        symtab.push(sym.info);
+        symtab.push(0); // visibility
+        symtab.extend_from_slice(&sym.shndx.to_le_bytes());
+    }
+    let mut rela_text = Vec::new();
+    for rela in &relas {
+        let symbol = match rela.symbol {
+            (true, index) => first_global + index,
+            (false, index) => index,
+        };
+        rela_text.extend_from_slice(&rela.offset.to_le_bytes());
+        rela_text.extend_from_slice(&(symbol << 8 | rela.kind).to_le_bytes());
+        rela_text.extend_from_slice(&rela.addend.to_le_bytes());
+    }
+
+    // Everything after the headers, one section after another, each starting on a word.
+    let mut body = Vec::new();
+    let body_offset = EHDR_SIZE + if exec { PHDR_SIZE } else { 0 };
+    let mut add_section = |data: &[u8]| {
+        pad_to_word(&mut body);
+        let offset = body_offset + body.len() as u32;
+        body.extend_from_slice(data);
+        (offset, data.len() as u32)
+    };
+    let text = add_section(&code_and_data);
+    let symtab = add_section(&symtab);
+    let strtab = add_section(&strtab);
+    let rela_text = (!exec).then(|| add_section(&rela_text));
+    let shstrtab = add_section(&shstrtab);
+    pad_to_word(&mut body);
+    let shoff = body_offset + body.len() as u32;
+
+    let mut sections = vec![
+        SectionHeader::default(),
+        SectionHeader {
+            name: text_name,
+            typ: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR | SHF_WRITE,
+            addr: base,
+            place: text,
+            addralign: 4,
+            ..Default::default()
+        },
+        SectionHeader {
+            name: symtab_name,
+            typ: SHT_SYMTAB,
+            place: symtab,
+            link: STRTAB,
+            info: first_global,
+            addralign: 4,
+            entsize: SYM_SIZE,
+            ..Default::default()
+        },
+        SectionHeader { name: strtab_name, typ: SHT_STRTAB, place: strtab, ..Default::default() },
+    ];
+    if let Some(rela_text) = rela_text {
+        sections.push(SectionHeader {
+            name: rela_name,
+            typ: SHT_RELA,
+            flags: SHF_INFO_LINK,
+            place: rela_text,
+            link: SYMTAB,
+            info: TEXT,
+            addralign: 4,
+            entsize: RELA_SIZE,
+            ..Default::default()
+        });
+    }
+    sections.push(SectionHeader {
+        name: shstrtab_name,
+        typ: SHT_STRTAB,
+        place: shstrtab,
+        ..Default::default()
+    });
+
+    writer.write_all(&[0x7F, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0])?;
+    writer.write_all(&kind.to_le_bytes())?;
+    writer.write_all(&EM_RISCV.to_le_bytes())?;
+    writer.write_all(&1u32.to_le_bytes())?; // version
+    writer.write_all(&(if exec { object.load_address } else { 0 }).to_le_bytes())?; // entry
+    writer.write_all(&(if exec { EHDR_SIZE } else { 0 }).to_le_bytes())?; // phoff
+    writer.write_all(&shoff.to_le_bytes())?;
+    let flags = if object.extensions & EXT_C != 0 { EF_RISCV_RVC } else { 0 };
+    writer.write_all(&flags.to_le_bytes())?;
+    writer.write_all(&(EHDR_SIZE as u16).to_le_bytes())?;
+    writer.write_all(&(PHDR_SIZE as u16).to_le_bytes())?;
+    writer.write_all(&(exec as u16).to_le_bytes())?; // phnum
+    writer.write_all(&(SHDR_SIZE as u16).to_le_bytes())?;
+    writer.write_all(&(sections.len() as u16).to_le_bytes())?;
+    writer.write_all(&(sections.len() as u16 - 1).to_le_bytes())?; // .shstrtab comes last
+
+    if exec {
+        for field in [
+            PT_LOAD,
+            text.0, // offset
+            object.load_address, // vaddr
+            object.load_address, // paddr
+            text.1, // filesz
+            text.1, // memsz
+            0b111, // flags = read, write, execute
+            4, // align
+        ] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+    }
+
+    writer.write_all(&body)?;
+
+    for section in &sections {
+        section.serialize(&mut writer)?;
+    }
+    Ok(())
+}
+
+fn u16_at(data: &[u8], offset: u32) -> Result<u16, DeserializationError> {
+    let bytes = data.get(offset as usize..offset as usize + 2)
+        .ok_or(DeserializationError::PrematureEnd)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: u32) -> Result<u32, DeserializationError> {
+    let bytes = data.get(offset as usize..offset as usize + 4)
+        .ok_or(DeserializationError::PrematureEnd)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Where the ith entry of a table at offset starts, if the entry's len bytes don't go past 4 GiB.
+/// Anything that does can't be in the file.
+fn entry_at(offset: u32, i: u32, entsize: u32, len: u32) -> Result<u32, DeserializationError> {
+    i.checked_mul(entsize)
+        .and_then(|x| x.checked_add(offset))
+        .filter(|x| x.checked_add(len).is_some())
+        .ok_or(DeserializationError::PrematureEnd)
+}
+
+fn put_u32(data: &mut [u8], offset: u32, x: u32) -> Result<(), DeserializationError> {
+    data.get_mut(offset as usize..offset as usize + 4)
+        .ok_or(DeserializationError::PrematureEnd)?
+        .copy_from_slice(&x.to_le_bytes());
+    Ok(())
+}
+
+fn unsupported(msg: String) -> DeserializationError {
+    DeserializationError::Unsupported(msg)
+}
+
+/// The names of relocation types that come up but that sam has nothing like, for error messages.
+fn relocation_name(kind: u32) -> Option<&'static str> {
+    match kind {
+        25 => Some("R_RISCV_PCREL_LO12_S"),
+        _ => None,
+    }
+}
+
+/// A section header as read from a file.
+struct Shdr {
+    name: u32,
+    typ: u32,
+    flags: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    addralign: u32,
+}
+
+/// A symbol table entry, with its name looked up.
+struct Sym {
+    name: String,
+    value: u32,
+    info: u8,
+    shndx: u16,
+}
+
+impl Sym {
+    fn is_external(&self) -> bool {
+        self.info >> 4 != STB_LOCAL
+    }
+
+    /// What a defined ELF symbol becomes in sam, at offset in code-and-data. ELF symbols without a
+    /// type go by whether their section holds code.
+    fn sam_value(&self, offset: u32, in_code: bool) -> SymbolValue {
+        let code = match self.info & 0xF {
+            STT_FUNC => true,
+            STT_OBJECT => false,
+            _ => in_code,
+        };
+        let (external, type_index, offset) = (self.is_external(), 0, Some(offset));
+        if code {
+            SymbolValue::Code { external, type_index, offset }
+        } else {
+            SymbolValue::Data { external, type_index, offset }
+        }
+    }
+}
+
+/// The headers of an ELF file that's been read into memory.
+struct Elf<'a> {
+    data: &'a [u8],
+    typ: u16,
+    entry: u32,
+    flags: u32,
+    sections: Vec<Shdr>,
+    shstrndx: u16,
+}
+
+impl<'a> Elf<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, DeserializationError> {
+        if !data.starts_with(b"\x7FELF") {
+            return Err(DeserializationError::BadElfMagic);
+        }
+        if data.get(4..6) != Some(&[1, 1]) {
+            return Err(unsupported("only 32-bit little-endian ELF files will do".to_owned()));
+        }
+        let machine = u16_at(data, 0x12)?;
+        if machine != EM_RISCV {
+            return Err(unsupported(format!("machine {} isn't RISC-V", machine)));
+        }
+        let shoff = u32_at(data, 0x20)?;
+        let shentsize = u16_at(data, 0x2E)? as u32;
+        let mut sections = Vec::new();
+        for i in 0..u16_at(data, 0x30)? as u32 {
+            let header = entry_at(shoff, i, shentsize, SHDR_SIZE)?;
+            sections.push(Shdr {
+                name: u32_at(data, header)?,
+                typ: u32_at(data, header + 0x04)?,
+                flags: u32_at(data, header + 0x08)?,
+                offset: u32_at(data, header + 0x10)?,
+                size: u32_at(data, header + 0x14)?,
+                link: u32_at(data, header + 0x18)?,
+                info: u32_at(data, header + 0x1C)?,
+                addralign: u32_at(data, header + 0x20)?,
+            });
+        }
+        Ok(Elf {
+            data,
+            typ: u16_at(data, 0x10)?,
+            entry: u32_at(data, 0x18)?,
+            flags: u32_at(data, 0x24)?,
+            sections,
+            shstrndx: u16_at(data, 0x32)?,
+        })
+    }
+
+    fn extensions(&self) -> u32 {
+        if self.flags & EF_RISCV_RVC != 0 { EXT_C } else { 0 }
+    }
+
+    fn contents(&self, section: &Shdr) -> Result<&'a [u8], DeserializationError> {
+        let start = section.offset as usize;
+        self.data.get(start..start + section.size as usize)
+            .ok_or(DeserializationError::PrematureEnd)
+    }
+
+    /// The NUL-terminated string at offset in a string table section.
+    fn str_at(&self, section: u32, offset: u32) -> Result<String, DeserializationError> {
+        let table = self.sections.get(section as usize).ok_or(DeserializationError::PrematureEnd)?;
+        let bytes = self.contents(table)?.get(offset as usize..)
+            .ok_or(DeserializationError::PrematureEnd)?;
+        let len = bytes.iter().position(|&b| b == 0).ok_or(DeserializationError::PrematureEnd)?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    fn section_name(&self, section: &Shdr) -> Result<String, DeserializationError> {
+        self.str_at(self.shstrndx as u32, section.name)
+    }
+
+    /// The entries of .symtab, or nothing if it's been stripped.
+    fn symbols(&self) -> Result<Vec<Sym>, DeserializationError> {
+        let symtab = match self.sections.iter().find(|section| section.typ == SHT_SYMTAB) {
+            Some(symtab) => symtab,
+            None => return Ok(Vec::new()),
+        };
+        let mut symbols = Vec::new();
+        for entry in self.contents(symtab)?.chunks_exact(SYM_SIZE as usize) {
+            symbols.push(Sym {
+                name: self.str_at(symtab.link, u32_at(entry, 0)?)?,
+                value: u32_at(entry, 4)?,
+                info: entry[0xC],
+                shndx: u16_at(entry, 0xE)?,
+            });
+        }
+        Ok(symbols)
+    }
+
+    fn in_code(&self, shndx: u16) -> bool {
+        self.sections.get(shndx as usize)
+            .is_some_and(|section| section.flags & SHF_EXECINSTR != 0)
+    }
+}
+
+/// What an ELF symbol became while converting a relocatable file.
+#[derive(Clone, Copy)]
+enum Target {
+    Symbol(u32), // sam symbol index
+    Section(u16),
+    Unusable, // e.g. absolute, common, or a file name
+}
+
+/// The parts of an object being converted from a relocatable file.
+struct Converter {
+    strings: StringTable,
+    symbols: SymbolTable,
+    bases: Vec<Option<u32>>, // where each section starts in code-and-data
+    code_sections: Vec<bool>,
+    section_names: Vec<String>,
+    targets: Vec<Target>, // by ELF symbol index
+    offset_labels: HashMap<u32, u32>, // labels made up for offsets, to sam symbol indices
+}
+
+impl Converter {
+    fn add_symbol(&mut self, name: &str, value: SymbolValue) -> u32 {
+        // Not get_index_or_insert, because local labels can share names.
+        let name_index = self.strings.insert(name.to_owned());
+        self.symbols.insert(name_index, value)
+    }
+
+    /// The offset in code-and-data that an ELF symbol plus an addend points at, if it's defined.
+    fn offset(&self, symbol: u32, addend: i32) -> Option<(u32, bool)> {
+        let (offset, in_code) = match *self.targets.get(symbol as usize)? {
+            Target::Symbol(index) => match self.symbols.symbols[index as usize].value {
+                SymbolValue::Code { offset, .. } => (offset?, true),
+                SymbolValue::Data { offset, .. } => (offset?, false),
+                SymbolValue::Metadata { .. } => return None,
+            },
+            Target::Section(shndx) => {
+                (self.bases[shndx as usize]?, self.code_sections[shndx as usize])
+            },
+            Target::Unusable => return None,
+        };
+        Some((offset.wrapping_add(addend as u32), in_code))
+    }
+
+    /// A sam symbol for an ELF symbol plus an addend, making one up if there isn't one already.
+    fn resolve(&mut self, symbol: u32, addend: i32, at: u32) -> Result<u32, DeserializationError> {
+        match self.targets.get(symbol as usize) {
+            Some(&Target::Symbol(index)) if addend == 0 => return Ok(index),
+            Some(&Target::Unusable) | None => return Err(unsupported(format!(
+                "relocation at {} refers to a symbol sam can't express",
+                u32_to_hex(at),
+            ))),
+            _ => (),
+        }
+        let (offset, in_code) = self.offset(symbol, addend).ok_or_else(|| unsupported(format!(
+            "relocation at {} has an addend on an undefined symbol",
+            u32_to_hex(at),
+        )))?;
+        if let Some(&index) = self.offset_labels.get(&offset) {
+            return Ok(index);
+        }
+        let name = match self.targets[symbol as usize] {
+            Target::Symbol(index) => self.symbols.symbols[index as usize].name(&self.strings),
+            Target::Section(shndx) => &self.section_names[shndx as usize],
+            Target::Unusable => unreachable!(),
+        };
+        let name = format!("{}{:+#x}", name, addend);
+        let (external, type_index, offset) = (false, 0, Some(offset));
+        let value = if in_code {
+            SymbolValue::Code { external, type_index, offset }
+        } else {
+            SymbolValue::Data { external, type_index, offset }
+        };
+        let index = self.add_symbol(&name, value);
+        self.offset_labels.insert(offset.unwrap(), index);
+        Ok(index)
+    }
+}
+
+/// Converts a relocatable ELF file (ET_REL), e.g. from GCC or LLVM, into a sam object that loads
+/// at load_address. Relocations sam can't express are an error.
+pub fn read_rel(data: &[u8], load_address: u32) -> Result<Object, DeserializationError> {
+    let elf = Elf::new(data)?;
+    if elf.typ != ET_REL {
+        return Err(unsupported("not a relocatable ELF file (ET_REL)".to_owned()));
+    }
+
+    let mut code_and_data = Vec::new();
+    let mut bases = Vec::with_capacity(elf.sections.len());
+    for section in &elf.sections {
+        if section.flags & SHF_ALLOC == 0 {
+            bases.push(None);
+            continue;
+        }
+        let align = section.addralign.max(1) as usize;
+        code_and_data.resize(code_and_data.len().div_ceil(align) * align, 0);
+        bases.push(Some(code_and_data.len() as u32));
+        if section.typ == SHT_NOBITS {
+            code_and_data.resize(code_and_data.len() + section.size as usize, 0);
+        } else {
+            code_and_data.extend_from_slice(elf.contents(section)?);
+        }
+    }
+
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut conv = Converter {
+        strings,
+        symbols: Default::default(),
+        bases,
+        code_sections: (0..elf.sections.len()).map(|i| elf.in_code(i as u16)).collect(),
+        section_names: elf.sections.iter()
+            .map(|section| elf.section_name(section))
+            .collect::<Result<_, _>>()?,
+        targets: Vec::new(),
+        offset_labels: HashMap::new(),
+    };
+    for sym in elf.symbols()? {
+        let base = conv.bases.get(sym.shndx as usize).copied().flatten();
+        let target = match sym.info & 0xF {
+            STT_SECTION if base.is_some() => Target::Section(sym.shndx),
+            STT_SECTION | STT_FILE => Target::Unusable,
+            _ if sym.name.is_empty() => Target::Unusable,
+            _ if sym.shndx == SHN_UNDEF => Target::Symbol(conv.add_symbol(
+                &sym.name,
+                SymbolValue::Code { external: true, type_index: 0, offset: None },
+            )),
+            _ => match base {
+                Some(base) => {
+                    let offset = base.checked_add(sym.value)
+                        .ok_or(DeserializationError::PrematureEnd)?;
+                    let value = sym.sam_value(offset, elf.in_code(sym.shndx));
+                    Target::Symbol(conv.add_symbol(&sym.name, value))
+                },
+                None => Target::Unusable,
+            },
+        };
+        conv.targets.push(target);
+    }
+
+    // (offset, ELF symbol, type, addend)
+    let mut relas = Vec::new();
+    for section in &elf.sections {
+        if section.typ == SHT_REL {
+            return Err(unsupported("REL relocations (only RELA)".to_owned()));
+        }
+        if section.typ != SHT_RELA {
+            continue;
+        }
+        let base = match conv.bases.get(section.info as usize).copied().flatten() {
+            Some(base) => base,
+            None => continue, // e.g. debug info
+        };
+        for entry in elf.contents(section)?.chunks_exact(RELA_SIZE as usize) {
+            let info = u32_at(entry, 4)?;
+            let addend = u32_at(entry, 8)? as i32;
+            let offset = base.checked_add(u32_at(entry, 0)?)
+                .ok_or(DeserializationError::PrematureEnd)?;
+            relas.push((offset, info >> 8, info & 0xFF, addend));
+        }
+    }
+
+    // R_RISCV_PCREL_LO12_I points at the auipc, so find where each auipc goes first.
+    let mut relocations = Vec::new();
+    let mut hi_targets = HashMap::new();
+    for &(offset, symbol, kind, addend) in &relas {
+        if kind == R_RISCV_PCREL_HI20 {
+            let symbol_index = conv.resolve(symbol, addend, offset)?;
+            hi_targets.insert(offset, symbol_index);
+            relocations.push(Relocation { offset, symbol_index, value: RelocationValue::RelUType });
+        }
+    }
+    for &(offset, symbol, kind, addend) in &relas {
+        let value = match kind {
+            R_RISCV_PCREL_HI20 | R_RISCV_RELAX | R_RISCV_ALIGN => continue,
+            R_RISCV_BRANCH => RelocationValue::RelCodeBType,
+            R_RISCV_JAL => RelocationValue::RelCodeJType,
+            R_RISCV_RVC_BRANCH => RelocationValue::RelCodeCbType,
+            R_RISCV_RVC_JUMP => RelocationValue::RelCodeCjType,
+            R_RISCV_32 | R_RISCV_HI20 | R_RISCV_LO12_I | R_RISCV_LO12_S => {
+                let symbol_index = conv.resolve(symbol, addend, offset)?;
+                let value = match kind {
+                    R_RISCV_32 => RelocationValue::Abs32,
+                    R_RISCV_HI20 => RelocationValue::AbsUType,
+                    R_RISCV_LO12_I => RelocationValue::AbsIType,
+                    _ => RelocationValue::AbsSType,
+                };
+                relocations.push(Relocation { offset, symbol_index, value });
+                continue;
+            },
+            R_RISCV_CALL | R_RISCV_CALL_PLT => {
+                // An auipc and a jalr, as call and tail make. sam keeps the jalr's distance from
+                // the auipc in its immediate.
+                let symbol_index = conv.resolve(symbol, addend, offset)?;
+                let jalr_offset = offset.checked_add(4).ok_or(DeserializationError::PrematureEnd)?;
+                let jalr = u32_at(&code_and_data, jalr_offset)?;
+                put_u32(&mut code_and_data, jalr_offset, (jalr & 0xF_FFFF) | 4 << 20)?;
+                let (hi, lo) = (RelocationValue::RelUType, RelocationValue::RelIType);
+                relocations.extend([
+                    Relocation { offset, symbol_index, value: hi },
+                    Relocation { offset: jalr_offset, symbol_index, value: lo },
+                ]);
+                continue;
+            },
+            R_RISCV_PCREL_LO12_I => {
+                let (auipc, _) = conv.offset(symbol, addend).ok_or_else(|| unsupported(format!(
+                    "R_RISCV_PCREL_LO12_I at {} doesn't point at an auipc",
+                    u32_to_hex(offset),
+                )))?;
+                let symbol_index = *hi_targets.get(&auipc).ok_or_else(|| unsupported(format!(
+                    "R_RISCV_PCREL_LO12_I at {} has no R_RISCV_PCREL_HI20 where it points",
+                    u32_to_hex(offset),
+                )))?;
+                let distance = offset.wrapping_sub(auipc);
+                if distance >= 1 << 11 && distance.wrapping_neg() > 1 << 11 {
+                    return Err(unsupported(format!(
+                        "R_RISCV_PCREL_LO12_I at {} is too far from its auipc",
+                        u32_to_hex(offset),
+                    )));
+                }
+                let insn = u32_at(&code_and_data, offset)?;
+                put_u32(&mut code_and_data, offset, (insn & 0xF_FFFF) | distance << 20)?;
+                relocations.push(Relocation {
+                    offset,
+                    symbol_index,
+                    value: RelocationValue::RelIType,
+                });
+                continue;
+            },
+            kind => return Err(unsupported(match relocation_name(kind) {
+                Some(name) => format!("{} relocation at {}", name, u32_to_hex(offset)),
+                None => format!("relocation type {} at {}", kind, u32_to_hex(offset)),
+            })),
+        };
+        // RELA leaves the immediate alone, but sam's branches and jumps keep their addend there.
+        // Only a section needs a label made up for it, so a label from another object can have
+        // an addend too.
+        let start = offset as usize;
+        let bytes = code_and_data.get_mut(start..start + value.insn_size() as usize)
+            .ok_or(DeserializationError::PrematureEnd)?;
+        let mut insn = [0; 4];
+        insn[..bytes.len()].copy_from_slice(bytes);
+        let (insn, _) = value.split_addend(u32::from_le_bytes(insn));
+        let (insn, addend) = match conv.targets.get(symbol as usize) {
+            Some(Target::Symbol(_)) => {
+                let insn = value.join_addend(insn, addend as u32).ok_or_else(|| unsupported(
+                    format!("addend of relocation at {} doesn't fit", u32_to_hex(offset)),
+                ))?;
+                (insn, 0)
+            },
+            _ => (insn, addend),
+        };
+        let len = bytes.len();
+        bytes.copy_from_slice(&insn.to_le_bytes()[..len]);
+        let symbol_index = conv.resolve(symbol, addend, offset)?;
+        if matches!(conv.symbols.symbols[symbol_index as usize].value, SymbolValue::Data { .. }) {
+            return Err(unsupported(format!(
+                "branch at {} to a data label",
+                u32_to_hex(offset),
+            )));
+        }
+        relocations.push(Relocation { offset, symbol_index, value });
+    }
+
+    Ok(Object {
+        load_address,
+        extensions: elf.extensions(),
+        code_and_data,
+        strings: conv.strings,
+        symbols: conv.symbols,
+        relocations: RelocationTable { relocations },
+    })
+}
+
+/// Reads an executable ELF file (ET_EXEC) as an object with everything it loads as code and data,
+/// so the emulator can run it, and returns the entry point along with it. Everything it loads has
+/// to fit in the emulator's RAM.
+pub fn read_exec(data: &[u8]) -> Result<(Object, u32), DeserializationError> {
+    let elf = Elf::new(data)?;
+    if elf.typ != ET_EXEC {
+        return Err(unsupported("not an executable ELF file (ET_EXEC); link it first".to_owned()));
+    }
+
+    // (address, contents, size in memory)
+    let mut segments = Vec::new();
+    let phoff = u32_at(data, 0x1C)?;
+    let phentsize = u16_at(data, 0x2A)? as u32;
+    for i in 0..u16_at(data, 0x2C)? as u32 {
+        let header = entry_at(phoff, i, phentsize, PHDR_SIZE)?;
+        let memsz = u32_at(data, header + 0x14)?;
+        if u32_at(data, header)? != PT_LOAD || memsz == 0 {
+            continue;
+        }
+        let (offset, vaddr, paddr) = (
+            u32_at(data, header + 0x04)?,
+            u32_at(data, header + 0x08)?,
+            u32_at(data, header + 0x0C)?,
+        );
+        if vaddr != paddr {
+            return Err(unsupported(format!(
+                "segment at {} loads somewhere else ({})",
+                u32_to_hex(vaddr),
+                u32_to_hex(paddr),
+            )));
+        }
+        let filesz = u32_at(data, header + 0x10)?.min(memsz);
+        let contents = data.get(offset as usize..offset as usize + filesz as usize)
+            .ok_or(DeserializationError::PrematureEnd)?;
+        // The segment has to end within the address space for end below to mean anything.
+        paddr.checked_add(memsz).ok_or(DeserializationError::PrematureEnd)?;
+        segments.push((paddr, contents, memsz));
+    }
+    let start = segments.iter().map(|&(address, _, _)| address).min()
+        .ok_or_else(|| unsupported("nothing to load".to_owned()))?;
+    let end = segments.iter().map(|&(address, _, memsz)| address + memsz).max().unwrap();
+    // Checked before allocating, since sparse segments could otherwise take up to 4 GiB.
+    if end - start > crate::emu::RAM_SIZE {
+        return Err(unsupported(format!(
+            "segments from {} to {} don't fit in the emulator's RAM",
+            u32_to_hex(start),
+            u32_to_hex(end),
+        )));
+    }
+    if !(start..end).contains(&elf.entry) {
+        return Err(unsupported(format!(
+            "the entry point {} isn't in anything that loads",
+            u32_to_hex(elf.entry),
+        )));
+    }
+    let mut code_and_data = vec![0; (end - start) as usize];
+    for (address, contents, _) in segments {
+        let offset = (address - start) as usize;
+        code_and_data[offset..offset + contents.len()].copy_from_slice(contents);
+    }
+
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut symbols: SymbolTable = Default::default();
+    for sym in elf.symbols()? {
+        if sym.name.is_empty()
+            || matches!(sym.info & 0xF, STT_SECTION | STT_FILE)
+            || sym.shndx == SHN_UNDEF
+            || sym.shndx >= SHN_LORESERVE
+            || !(start..end).contains(&sym.value)
+        {
+            continue;
+        }
+        let name_index = strings.insert(sym.name.clone());
+        symbols.insert(name_index, sym.sam_value(sym.value - start, elf.in_code(sym.shndx)));
+    }
+
+    let object = Object {
+        load_address: start,
+        extensions: elf.extensions(),
+        code_and_data,
+        strings,
+        symbols,
+        relocations: Default::default(),
+    };
+    Ok((object, elf.entry))
+}
+
+/// Writes a relocatable file and reads it back.
+#[test]
+fn test_rel_round_trip() {
+    use crate::{StringTable, SymbolTable};
+
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut symbols: SymbolTable = Default::default();
+    let start = strings.get_index_or_insert("start");
+    symbols.insert(start, SymbolValue::Code { external: false, type_index: 0, offset: Some(0) });
+    let putc = strings.get_index_or_insert("putc");
+    symbols.insert(putc, SymbolValue::Code { external: true, type_index: 0, offset: None });
+    let object = Object {
+        load_address: 0x8000_0000,
+        extensions: 0,
+        // call putc, then j putc+#8
+        code_and_data: [0x0000_0097u32, 0x0040_80E7, 0x0080_006F].iter()
+            .flat_map(|insn| insn.to_le_bytes())
+            .collect(),
+        strings,
+        symbols,
+        relocations: crate::RelocationTable { relocations: vec![
+            Relocation { offset: 0, symbol_index: 1, value: RelocationValue::RelUType },
+            Relocation { offset: 4, symbol_index: 1, value: RelocationValue::RelIType },
+            Relocation { offset: 8, symbol_index: 1, value: RelocationValue::RelCodeJType },
+        ] },
+    };
+    let mut elf = Vec::new();
+    write_rel(&object, &mut elf).unwrap();
+
+    let word = |offset: u32| u32_at(&elf, offset).unwrap();
+    assert_eq!(&elf[..4], b"\x7FELF");
+    assert_eq!(word(0x10), (EM_RISCV as u32) << 16 | ET_REL as u32);
+    let section = |index: u32| word(0x20) + index * SHDR_SIZE;
+    assert_eq!(word(section(4) + 4), SHT_RELA);
+    let (text, relas) = (word(section(TEXT) + 0x10), word(section(4) + 0x10));
+    // The addend sam keeps in the jalr is gone, and the jump's is in its relocation instead.
+    assert_eq!(word(text + 4), 0x0000_80E7);
+    assert_eq!(word(text + 8), 0x0000_006F);
+    // start and .Lpcrel_hi0 are local, then putc is the first global.
+    assert_eq!(word(section(SYMTAB) + 0x1C), 3);
+    assert_eq!(word(relas + 4), 3 << 8 | R_RISCV_PCREL_HI20);
+    assert_eq!(word(relas + 0x10), 2 << 8 | R_RISCV_PCREL_LO12_I);
+    assert_eq!((word(relas + 0x1C), word(relas + 0x20)), (3 << 8 | R_RISCV_JAL, 8));
+
+    let read = read_rel(&elf, 0x8000_0000).unwrap();
+    assert_eq!(read.code_and_data, object.code_and_data);
+    let relocations: Vec<_> = read.relocations.relocations.iter()
+        .map(|reloc| (reloc.offset, reloc.symbol(&read.symbols).name(&read.strings), reloc.value))
+        .collect();
+    assert!(
+        matches!(relocations[..], [
+            (0, "putc", RelocationValue::RelUType),
+            (4, "putc", RelocationValue::RelIType),
+            (8, "putc", RelocationValue::RelCodeJType),
+        ]),
+        "{:?}",
+        relocations,
+    );
+}
+
+/// Header tables that would run past 4 GiB are a premature end, not an overflow.
+#[test]
+fn test_huge_header_offsets() {
+    let object = Object {
+        load_address: 0x8000_0000,
+        extensions: 0,
+        code_and_data: 0x0010_0073u32.to_le_bytes().to_vec(), // ebreak
+        strings: Default::default(),
+        symbols: Default::default(),
+        relocations: Default::default(),
+    };
+    let mut elf = Vec::new();
+    write_exec(&object, &mut elf).unwrap();
+    assert_eq!(read_exec(&elf).unwrap().1, 0x8000_0000);
+
+    for (field, value) in [(0x1C, 0xFFFF_FFF0), (0x20, 0xFFFF_FFF0)] { // phoff, shoff
+        let mut elf = elf.clone();
+        put_u32(&mut elf, field, value).unwrap();
+        assert!(matches!(read_exec(&elf), Err(DeserializationError::PrematureEnd)), "{:#x}", field);
+    }
+    // So are segments whose contents would end past 4 GiB.
+    let mut elf = elf.clone();
+    let phoff = u32_at(&elf, 0x1C).unwrap();
+    put_u32(&mut elf, phoff + 0x04, 0xFFFF_FFF0).unwrap();
+    assert!(matches!(read_exec(&elf), Err(DeserializationError::PrematureEnd)));
+}
+
+/// An executable can start anywhere in what it loads, but what it loads has to fit in RAM.
+#[test]
+fn test_exec_entry_and_span() {
+    let object = Object {
+        load_address: 0x8000_0000,
+        extensions: 0,
+        code_and_data: [0x0000_0013u32, 0x0010_0073].iter() // nop, ebreak
+            .flat_map(|insn| insn.to_le_bytes())
+            .collect(),
+        strings: Default::default(),
+        symbols: Default::default(),
+        relocations: Default::default(),
+    };
+    let mut elf = Vec::new();
+    write_exec(&object, &mut elf).unwrap();
+
+    put_u32(&mut elf, 0x18, 0x8000_0004).unwrap(); // e_entry
+    let (read, entry) = read_exec(&elf).unwrap();
+    assert_eq!((read.load_address, entry), (0x8000_0000, 0x8000_0004));
+    put_u32(&mut elf, 0x18, 0x8000_0008).unwrap();
+    assert!(read_exec(&elf).is_err());
+
+    put_u32(&mut elf, 0x18, 0x8000_0000).unwrap();
+    let phoff = u32_at(&elf, 0x1C).unwrap();
+    put_u32(&mut elf, phoff + 0x14, 0x1000_0000).unwrap(); // p_memsz
+    assert!(matches!(read_exec(&elf), Err(DeserializationError::Unsupported(_))));
+}
@@ -0,0 +1,825 @@
+//! An in-process RV32IMAC+Zicsr hart, so sam objects can be run without QEMU.
+//!
+//! The memory map follows QEMU's `virt` machine, since that's what all our programs are written
+//! for.
+
+use crate::insn::*;
+use crate::rvc;
+use crate::u32_to_hex;
+use std::convert::TryFrom;
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+use std::time::Instant;
+
+pub mod gdb;
+pub mod monitor;
+pub mod pci;
+pub mod trace;
+pub mod virt;
+pub mod virtio;
+
+pub const RAM_BASE: u32 = 0x8000_0000;
+pub const RAM_SIZE: u32 = 128 << 20; // same as `-m 128` in sim.sh
+
+/// mtime ticks per second. QEMU's `virt` uses 10 MHz.
+pub const TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// The source of mtime. It's shared between the CLINT and the time CSR.
+#[derive(Clone)]
+pub struct Clock {
+    start: Option<Instant>, // None if only advance moves it
+    offset: Rc<Cell<u64>>, // added to host time so mtime can be written
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock { start: Some(Instant::now()), offset: Default::default() }
+    }
+}
+
+impl Clock {
+    /// A clock that ignores the host and only moves when advance is called, so runs are
+    /// repeatable.
+    pub fn manual() -> Self {
+        Clock { start: None, offset: Default::default() }
+    }
+
+    fn host_ticks(&self) -> u64 {
+        let elapsed = match self.start {
+            Some(start) => start.elapsed(),
+            None => return 0,
+        };
+        elapsed.as_secs() * TIMEBASE_FREQ
+            + elapsed.subsec_nanos() as u64 * TIMEBASE_FREQ / 1_000_000_000
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.host_ticks().wrapping_add(self.offset.get())
+    }
+
+    pub fn set_mtime(&self, mtime: u64) {
+        self.offset.set(mtime.wrapping_sub(self.host_ticks()));
+    }
+
+    pub fn advance(&self, ticks: u64) {
+        self.offset.set(self.offset.get().wrapping_add(ticks));
+    }
+}
+
+/// Something that lives at a range of physical addresses.
+pub trait Device {
+    /// offset is relative to the start of the device. size is 1, 2, or 4, and the result is
+    /// zero-extended. None means access fault.
+    fn load(&mut self, offset: u32, size: u32) -> Option<u32>;
+
+    /// Like load, but only the low size bytes of value count.
+    fn store(&mut self, offset: u32, size: u32, value: u32) -> Option<()>;
+
+    /// Checked after every store to this device.
+    fn halt(&mut self) -> Option<Halt> {
+        None
+    }
+
+    /// Which bits of mip this device is asserting.
+    fn interrupts(&self) -> u32 {
+        0
+    }
+
+    /// The mtime at which this device will next assert an interrupt on its own, if it will.
+    fn deadline(&self) -> Option<u64> {
+        None
+    }
+
+    /// Called when the machine resets, to put the registers back how they were at power-on.
+    fn reset(&mut self) {}
+}
+
+/// A request from the guest to stop the whole machine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Halt {
+    Pass,
+    Fail(u16),
+    Reset,
+}
+
+pub struct Bus {
+    pub ram: Vec<u8>,
+    pub clock: Clock,
+    pub halt: Option<Halt>,
+    devices: Vec<(u32, u32, Box<dyn Device>)>, // (base, len, device)
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus {
+            ram: vec![0; RAM_SIZE as usize],
+            clock: Default::default(),
+            halt: None,
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl Bus {
+    pub fn add_device(&mut self, base: u32, len: u32, device: Box<dyn Device>) {
+        self.devices.push((base, len, device));
+    }
+
+    fn ram_offset(&self, addr: u32, len: u32) -> Option<usize> {
+        let offset = addr.wrapping_sub(RAM_BASE);
+        if offset < RAM_SIZE && len <= RAM_SIZE - offset {
+            Some(offset as usize)
+        } else {
+            None
+        }
+    }
+
+    fn device(&mut self, addr: u32, len: u32) -> Option<(u32, &mut dyn Device)> {
+        self.devices.iter_mut()
+            .find(|&&mut (base, dev_len, _)| {
+                let offset = addr.wrapping_sub(base);
+                offset < dev_len && len <= dev_len - offset
+            })
+            .map(|&mut (base, _, ref mut device)| (addr - base, &mut **device as &mut dyn Device))
+    }
+
+    /// size is 1, 2, or 4. The result is zero-extended.
+    pub fn load(&mut self, addr: u32, size: u32) -> Option<u32> {
+        if let Some(value) = self.peek(addr, size) {
+            return Some(value);
+        }
+        let (offset, device) = self.device(addr, size)?;
+        device.load(offset, size)
+    }
+
+    /// Like load, but for debuggers: it only reads RAM and returns None for device registers,
+    /// since reading some of those changes them (the UART's RBR, for one).
+    pub fn peek(&self, addr: u32, size: u32) -> Option<u32> {
+        let offset = self.ram_offset(addr, size)?;
+        let mut buf = [0; 4];
+        buf[..size as usize].copy_from_slice(&self.ram[offset..offset + size as usize]);
+        Some(u32::from_le_bytes(buf))
+    }
+
+    /// size is 1, 2, or 4. Only the low size bytes of value are stored.
+    pub fn store(&mut self, addr: u32, size: u32, value: u32) -> Option<()> {
+        if let Some(offset) = self.ram_offset(addr, size) {
+            self.ram[offset..offset + size as usize]
+                .copy_from_slice(&value.to_le_bytes()[..size as usize]);
+            return Some(());
+        }
+        let (offset, device) = self.device(addr, size)?;
+        device.store(offset, size, value)?;
+        if let Some(halt) = device.halt() {
+            self.halt = Some(halt);
+        }
+        Some(())
+    }
+
+    /// The bits of mip asserted by all devices together.
+    pub fn interrupts(&self) -> u32 {
+        self.devices.iter().fold(0, |mip, (_, _, device)| mip | device.interrupts())
+    }
+
+    /// The earliest deadline of any device, so a hart in wfi with a manual clock knows how far to
+    /// skip ahead.
+    pub fn deadline(&self) -> Option<u64> {
+        self.devices.iter().filter_map(|(_, _, device)| device.deadline()).min()
+    }
+
+    /// Resets every device, as for Halt::Reset. RAM keeps its contents, like QEMU's does.
+    pub fn reset(&mut self) {
+        for (_, _, device) in &mut self.devices {
+            device.reset();
+        }
+        self.halt = None;
+    }
+
+    /// Copy an image into RAM. Returns None if it doesn't fit.
+    pub fn load_image(&mut self, addr: u32, image: &[u8]) -> Option<()> {
+        let len = u32::try_from(image.len()).ok()?;
+        let offset = self.ram_offset(addr, len)?;
+        self.ram[offset..offset + image.len()].copy_from_slice(image);
+        Some(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    InsnAddressMisaligned(u32),
+    InsnAccessFault(u32),
+    IllegalInsn(u32),
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAddressMisaligned(u32),
+    StoreAccessFault(u32),
+    EcallFromM,
+}
+
+impl Exception {
+    pub fn cause(self) -> u32 {
+        match self {
+            Self::InsnAddressMisaligned(_) => 0,
+            Self::InsnAccessFault(_) => 1,
+            Self::IllegalInsn(_) => 2,
+            Self::Breakpoint(_) => 3,
+            Self::LoadAddressMisaligned(_) => 4,
+            Self::LoadAccessFault(_) => 5,
+            Self::StoreAddressMisaligned(_) => 6,
+            Self::StoreAccessFault(_) => 7,
+            Self::EcallFromM => 11,
+        }
+    }
+
+    /// The value mtval gets on trap entry.
+    pub fn tval(self) -> u32 {
+        match self {
+            Self::InsnAddressMisaligned(x)
+            | Self::InsnAccessFault(x)
+            | Self::IllegalInsn(x)
+            | Self::Breakpoint(x)
+            | Self::LoadAddressMisaligned(x)
+            | Self::LoadAccessFault(x)
+            | Self::StoreAddressMisaligned(x)
+            | Self::StoreAccessFault(x) => x,
+            Self::EcallFromM => 0,
+        }
+    }
+}
+
+impl Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let what = match *self {
+            Self::InsnAddressMisaligned(_) => "instruction address misaligned",
+            Self::InsnAccessFault(_) => "instruction access fault",
+            Self::IllegalInsn(_) => "illegal instruction",
+            Self::Breakpoint(_) => "breakpoint",
+            Self::LoadAddressMisaligned(_) => "load address misaligned",
+            Self::LoadAccessFault(_) => "load access fault",
+            Self::StoreAddressMisaligned(_) => "store address misaligned",
+            Self::StoreAccessFault(_) => "store access fault",
+            Self::EcallFromM => return write!(f, "ecall from M-mode"),
+        };
+        write!(f, "{} ({})", what, u32_to_hex(self.tval()))
+    }
+}
+
+pub const INT_MSI: u32 = 3;
+pub const INT_MTI: u32 = 7;
+pub const INT_MEI: u32 = 11;
+
+/// What an instruction did, for tracing and debugging.
+#[derive(Clone, Copy, Debug)]
+pub struct Retired {
+    pub pc: u32,
+    pub insn: u32, // as it is in memory, so only 16 bits if it's compressed
+    pub rd_write: Option<(u32, u32)>, // (register, value)
+    pub store: Option<(u32, u32, u32)>, // (address, size, value)
+}
+
+pub const MISA: u32 = (1 << 30) + EXTENSIONS + (1 << ('I' as u32 - 'A' as u32)); // RV32IMAC
+
+/// The extensions the hart implements beyond RV32I, in the same format as an object's.
+pub const EXTENSIONS: u32 = crate::EXT_A | crate::EXT_C | crate::EXT_M;
+
+const MSTATUS_MIE: u32 = 1 << 3;
+const MSTATUS_MPIE: u32 = 1 << 7;
+// MPP is hardwired to M because there are no other modes.
+const MSTATUS_MPP: u32 = 0b11 << 11;
+
+// Only M-mode interrupts exist.
+const MIE_MASK: u32 = (1 << INT_MSI) | (1 << INT_MTI) | (1 << INT_MEI);
+
+/// The CSRs the hart has, by name, for debuggers.
+pub const CSRS: [(&str, u32); 19] = [
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mtval", 0x343),
+    ("mip", 0x344),
+    ("mcycle", 0xB00),
+    ("minstret", 0xB02),
+    ("mcycleh", 0xB80),
+    ("minstreth", 0xB82),
+    ("time", 0xC01),
+    ("timeh", 0xC81),
+    ("mvendorid", 0xF11),
+    ("marchid", 0xF12),
+    ("mimpid", 0xF13),
+    ("mhartid", 0xF14),
+];
+
+/// The result of Hart::step.
+#[derive(Clone, Copy, Debug)]
+pub enum Step {
+    Retired(Retired),
+    /// An exception was raised instead, and the hart is now at the trap handler.
+    Exception(Exception),
+    /// An interrupt was taken before the next instruction, and the hart is now at the trap
+    /// handler. The interrupt code is mcause without the high bit.
+    Interrupt(u32),
+    /// The hart is in wfi and no enabled interrupt is pending, so nothing happened.
+    Waiting,
+}
+
+pub struct Hart {
+    pub x: [u32; 32],
+    pub pc: u32,
+    pub mstatus: u32,
+    pub mie: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub cycle: u64,
+    pub instret: u64,
+    pub waiting: bool, // in wfi
+    entering_trap: bool, // no instruction has retired since the last trap
+    reservation: Option<u32>, // address of the last lr.w, until an sc.w or a trap
+}
+
+impl Hart {
+    pub fn new(pc: u32) -> Self {
+        Hart {
+            x: [0; 32],
+            pc,
+            mstatus: 0,
+            mie: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            cycle: 0,
+            instret: 0,
+            waiting: false,
+            entering_trap: false,
+            reservation: None,
+        }
+    }
+
+    /// Returns None if there's no such CSR.
+    pub fn read_csr(&self, csr: u32, bus: &Bus) -> Option<u32> {
+        Some(match csr {
+            0xC01 => bus.clock.mtime() as u32,
+            0xC81 => (bus.clock.mtime() >> 32) as u32,
+            0xC00 | 0xB00 => self.cycle as u32,
+            0xC80 | 0xB80 => (self.cycle >> 32) as u32,
+            0xC02 | 0xB02 => self.instret as u32,
+            0xC82 | 0xB82 => (self.instret >> 32) as u32,
+            0xF11..=0xF14 => 0, // mvendorid, marchid, mimpid, mhartid
+            0x300 => self.mstatus | MSTATUS_MPP,
+            0x301 => MISA,
+            0x304 => self.mie,
+            0x305 => self.mtvec,
+            0x340 => self.mscratch,
+            0x341 => self.mepc,
+            0x342 => self.mcause,
+            0x343 => self.mtval,
+            0x344 => bus.interrupts(),
+            _ => return None,
+        })
+    }
+
+    /// Returns None if there's no such CSR or it's read-only.
+    pub fn write_csr(&mut self, csr: u32, value: u32) -> Option<()> {
+        match csr {
+            0xB00 => self.cycle = (self.cycle & !0xFFFF_FFFF) | value as u64,
+            0xB80 => self.cycle = (self.cycle & 0xFFFF_FFFF) | (value as u64) << 32,
+            0xB02 => self.instret = (self.instret & !0xFFFF_FFFF) | value as u64,
+            0xB82 => self.instret = (self.instret & 0xFFFF_FFFF) | (value as u64) << 32,
+            0x300 => self.mstatus = value & (MSTATUS_MIE | MSTATUS_MPIE),
+            0x301 => (), // misa is WARL and we don't let anything change
+            0x304 => self.mie = value & MIE_MASK,
+            0x305 => {
+                // Like QEMU, ignore attempts to set a reserved mode.
+                if value & 0b11 < 2 {
+                    self.mtvec = value;
+                }
+            },
+            0x340 => self.mscratch = value,
+            0x341 => self.mepc = value & !1, // IALIGN is 16 because of C
+            0x342 => self.mcause = value,
+            0x343 => self.mtval = value,
+            0x344 => (), // every bit is read-only
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn enter_trap(&mut self, cause: u32, tval: u32) {
+        self.mepc = self.pc;
+        self.mcause = cause;
+        self.mtval = tval;
+        self.reservation = None;
+        let mpie = if self.mstatus & MSTATUS_MIE != 0 { MSTATUS_MPIE } else { 0 };
+        self.mstatus = mpie;
+        let base = self.mtvec & !0b11;
+        let vectored = self.mtvec & 0b11 == 1;
+        self.pc = if vectored && cause >> 31 != 0 {
+            base.wrapping_add(cause << 2)
+        } else {
+            base
+        };
+        self.entering_trap = true;
+    }
+
+    /// Take a pending interrupt, execute one instruction, or wait.
+    ///
+    /// Exceptions are taken as traps. The only error is an exception raised by the first
+    /// instruction of a trap handler, which would otherwise loop forever.
+    pub fn step(&mut self, bus: &mut Bus) -> Result<Step, Exception> {
+        let pending = bus.interrupts() & self.mie;
+        if self.waiting {
+            if pending == 0 {
+                return Ok(Step::Waiting);
+            }
+            self.waiting = false;
+        }
+        if pending != 0 && self.mstatus & MSTATUS_MIE != 0 {
+            // highest priority first
+            let code = [INT_MEI, INT_MSI, INT_MTI].iter().copied()
+                .find(|&code| pending & (1 << code) != 0)
+                .unwrap();
+            self.enter_trap((1 << 31) | code, 0);
+            return Ok(Step::Interrupt(code));
+        }
+
+        match self.execute(bus) {
+            Ok(retired) => {
+                self.entering_trap = false;
+                Ok(Step::Retired(retired))
+            },
+            Err(e) if self.entering_trap => Err(e),
+            Err(e) => {
+                self.enter_trap(e.cause(), e.tval());
+                Ok(Step::Exception(e))
+            },
+        }
+    }
+
+    /// Execute one instruction. If it raises an exception, the hart's state is left unchanged.
+    fn execute(&mut self, bus: &mut Bus) -> Result<Retired, Exception> {
+        let pc = self.pc;
+        let low = bus.load(pc, 2).ok_or(Exception::InsnAccessFault(pc))?;
+        // raw is what's in memory, and insn is what to execute, which is different for compressed
+        // instructions.
+        let (raw, insn, len) = if low & 0b11 != 0b11 {
+            (low, rvc::expand(low).ok_or(Exception::IllegalInsn(low))?, 2)
+        } else {
+            let high = bus.load(pc.wrapping_add(2), 2).ok_or(Exception::InsnAccessFault(pc))?;
+            let insn = low | (high << 16);
+            (insn, insn, 4)
+        };
+        let mut retired = Retired { pc, insn: raw, rd_write: None, store: None };
+        let mut next_pc = pc.wrapping_add(len);
+
+        let a = self.x[rs1(insn) as usize];
+        let b = self.x[rs2(insn) as usize];
+        let illegal = Exception::IllegalInsn(raw);
+
+        // With the C extension, instructions only have to be 2-byte aligned, and jalr clears the
+        // low bit, so this can't actually fail.
+        let jump = |target: u32| {
+            if target & 0b1 != 0 {
+                Err(Exception::InsnAddressMisaligned(target))
+            } else {
+                Ok(target)
+            }
+        };
+
+        let rd_value = match opcode(insn) {
+            0x37 => Some(imm_u(insn)), // lui
+            0x17 => Some(pc.wrapping_add(imm_u(insn))), // auipc
+            0x6F => { // jal
+                next_pc = jump(pc.wrapping_add(imm_j(insn)))?;
+                Some(pc.wrapping_add(len))
+            },
+            0x67 if funct3(insn) == 0 => { // jalr
+                next_pc = jump(a.wrapping_add(imm_i(insn)) & !1)?;
+                Some(pc.wrapping_add(len))
+            },
+            0x63 => {
+                let taken = match funct3(insn) {
+                    0 => a == b,
+                    1 => a != b,
+                    4 => (a as i32) < (b as i32),
+                    5 => (a as i32) >= (b as i32),
+                    6 => a < b,
+                    7 => a >= b,
+                    _ => return Err(illegal),
+                };
+                if taken {
+                    next_pc = jump(pc.wrapping_add(imm_b(insn)))?;
+                }
+                None
+            },
+            0x03 => {
+                let addr = a.wrapping_add(imm_i(insn));
+                let (size, signed) = match funct3(insn) {
+                    0 => (1, true),
+                    1 => (2, true),
+                    2 => (4, false),
+                    4 => (1, false),
+                    5 => (2, false),
+                    _ => return Err(illegal),
+                };
+                if addr & (size - 1) != 0 {
+                    return Err(Exception::LoadAddressMisaligned(addr));
+                }
+                let value = bus.load(addr, size).ok_or(Exception::LoadAccessFault(addr))?;
+                Some(if signed {
+                    let shift = 32 - 8 * size;
+                    ((value << shift) as i32 >> shift) as u32
+                } else {
+                    value
+                })
+            },
+            0x23 => {
+                let addr = a.wrapping_add(imm_s(insn));
+                let size = match funct3(insn) {
+                    0 => 1,
+                    1 => 2,
+                    2 => 4,
+                    _ => return Err(illegal),
+                };
+                if addr & (size - 1) != 0 {
+                    return Err(Exception::StoreAddressMisaligned(addr));
+                }
+                bus.store(addr, size, b).ok_or(Exception::StoreAccessFault(addr))?;
+                retired.store = Some((addr, size, b));
+                None
+            },
+            0x13 => {
+                let imm = imm_i(insn);
+                let shamt = imm & 0x1F;
+                Some(match (funct3(insn), funct7(insn)) {
+                    (0, _) => a.wrapping_add(imm),
+                    (2, _) => ((a as i32) < (imm as i32)) as u32,
+                    (3, _) => (a < imm) as u32,
+                    (4, _) => a ^ imm,
+                    (6, _) => a | imm,
+                    (7, _) => a & imm,
+                    (1, 0x00) => a << shamt,
+                    (5, 0x00) => a >> shamt,
+                    (5, 0x20) => (a as i32 >> shamt) as u32,
+                    _ => return Err(illegal),
+                })
+            },
+            0x33 => {
+                let shamt = b & 0x1F;
+                Some(match (funct3(insn), funct7(insn)) {
+                    (0, 0x00) => a.wrapping_add(b),
+                    (0, 0x20) => a.wrapping_sub(b),
+                    (1, 0x00) => a << shamt,
+                    (2, 0x00) => ((a as i32) < (b as i32)) as u32,
+                    (3, 0x00) => (a < b) as u32,
+                    (4, 0x00) => a ^ b,
+                    (5, 0x00) => a >> shamt,
+                    (5, 0x20) => (a as i32 >> shamt) as u32,
+                    (6, 0x00) => a | b,
+                    (7, 0x00) => a & b,
+                    (0, 0x01) => a.wrapping_mul(b),
+                    (1, 0x01) => ((a as i32 as i64 * b as i32 as i64) >> 32) as u32,
+                    (2, 0x01) => ((a as i32 as i64 * b as i64) >> 32) as u32,
+                    (3, 0x01) => ((a as u64 * b as u64) >> 32) as u32,
+                    // Division by zero and overflow don't trap; the results are what the spec
+                    // says they are.
+                    (4, 0x01) => match b {
+                        0 => u32::MAX,
+                        _ => (a as i32).wrapping_div(b as i32) as u32,
+                    },
+                    (5, 0x01) => a.checked_div(b).unwrap_or(u32::MAX),
+                    (6, 0x01) => match b {
+                        0 => a,
+                        _ => (a as i32).wrapping_rem(b as i32) as u32,
+                    },
+                    (7, 0x01) => a.checked_rem(b).unwrap_or(a),
+                    _ => return Err(illegal),
+                })
+            },
+            0x2F if funct3(insn) == 2 => {
+                let addr = a;
+                let funct5 = insn >> 27;
+                if funct5 == 0x02 { // lr.w
+                    if rs2(insn) != 0 {
+                        return Err(illegal);
+                    }
+                    if addr & 0b11 != 0 {
+                        return Err(Exception::LoadAddressMisaligned(addr));
+                    }
+                    let value = bus.load(addr, 4).ok_or(Exception::LoadAccessFault(addr))?;
+                    self.reservation = Some(addr);
+                    Some(value)
+                } else if addr & 0b11 != 0 {
+                    return Err(Exception::StoreAddressMisaligned(addr));
+                } else if funct5 == 0x03 { // sc.w
+                    // There's only one hart, so nothing else can break the reservation. The
+                    // aq and rl bits don't matter for the same reason.
+                    if self.reservation == Some(addr) {
+                        bus.store(addr, 4, b).ok_or(Exception::StoreAccessFault(addr))?;
+                        retired.store = Some((addr, 4, b));
+                        self.reservation = None;
+                        Some(0)
+                    } else {
+                        self.reservation = None;
+                        Some(1)
+                    }
+                } else {
+                    let old = bus.load(addr, 4).ok_or(Exception::StoreAccessFault(addr))?;
+                    let new = match funct5 {
+                        0x00 => old.wrapping_add(b),
+                        0x01 => b,
+                        0x04 => old ^ b,
+                        0x08 => old | b,
+                        0x0C => old & b,
+                        0x10 => (old as i32).min(b as i32) as u32,
+                        0x14 => (old as i32).max(b as i32) as u32,
+                        0x18 => old.min(b),
+                        0x1C => old.max(b),
+                        _ => return Err(illegal),
+                    };
+                    bus.store(addr, 4, new).ok_or(Exception::StoreAccessFault(addr))?;
+                    retired.store = Some((addr, 4, new));
+                    Some(old)
+                }
+            },
+            0x0F => match funct3(insn) {
+                // There's only one hart and no caches, so fence and fence.i are no-ops.
+                0 | 1 => None,
+                _ => return Err(illegal),
+            },
+            0x73 => match funct3(insn) {
+                0 => match insn {
+                    0x0000_0073 => return Err(Exception::EcallFromM),
+                    0x0010_0073 => return Err(Exception::Breakpoint(pc)),
+                    0x3020_0073 => { // mret
+                        next_pc = self.mepc;
+                        let mie = if self.mstatus & MSTATUS_MPIE != 0 { MSTATUS_MIE } else { 0 };
+                        self.mstatus = mie | MSTATUS_MPIE;
+                        None
+                    },
+                    0x1050_0073 => { // wfi
+                        self.waiting = true;
+                        None
+                    },
+                    _ => return Err(illegal),
+                },
+                4 => return Err(illegal),
+                f3 => {
+                    let csr = csr(insn);
+                    let src = if f3 & 0b100 != 0 { rs1(insn) } else { a };
+                    // csrrs and csrrc don't write anything if rs1 is x0 (or uimm is 0 for csrrsi
+                    // and csrrci), so they can be used on read-only CSRs.
+                    let writes = f3 & 0b11 == 1 || rs1(insn) != 0;
+                    let old = self.read_csr(csr, bus).ok_or(illegal)?;
+                    if writes {
+                        if csr >> 10 == 0b11 {
+                            return Err(illegal);
+                        }
+                        let new = match f3 & 0b11 {
+                            1 => src,
+                            2 => old | src,
+                            _ => old & !src,
+                        };
+                        self.write_csr(csr, new).ok_or(illegal)?;
+                    }
+                    Some(old)
+                },
+            },
+            _ => return Err(illegal),
+        };
+
+        if let Some(value) = rd_value {
+            let rd = rd(insn);
+            if rd != 0 {
+                self.x[rd as usize] = value;
+                retired.rd_write = Some((rd, value));
+            }
+        }
+        self.pc = next_pc;
+        self.cycle = self.cycle.wrapping_add(1);
+        self.instret = self.instret.wrapping_add(1);
+        Ok(retired)
+    }
+}
+
+/// A hart at the start of RAM, where insns are, with the registers in x set.
+#[cfg(test)]
+fn load(insns: &[u32], x: &[(usize, u32)]) -> (Hart, Bus) {
+    let mut bus = Bus::default();
+    let image: Vec<u8> = insns.iter().flat_map(|insn| insn.to_le_bytes()).collect();
+    bus.load_image(RAM_BASE, &image).unwrap();
+    let mut hart = Hart::new(RAM_BASE);
+    for &(i, value) in x {
+        hart.x[i] = value;
+    }
+    (hart, bus)
+}
+
+/// Like load, but then runs insns and returns what each one did.
+#[cfg(test)]
+fn run(insns: &[u32], x: &[(usize, u32)]) -> (Hart, Bus, Vec<Step>) {
+    let (mut hart, mut bus) = load(insns, x);
+    let steps = insns.iter().map(|_| hart.step(&mut bus).unwrap()).collect();
+    (hart, bus, steps)
+}
+
+/// An R-type instruction with rd = a0, rs1 = a1, and rs2 = a2, which is also the layout of the
+/// A extension's instructions.
+#[cfg(test)]
+fn r_type(funct7: u32, funct3: u32, opcode: u32) -> u32 {
+    funct7 << 25 | 12 << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | opcode
+}
+
+#[test]
+fn test_rv32m() {
+    let m = |funct3, a: i32, b: i32| {
+        let (hart, ..) = run(&[r_type(0x01, funct3, 0x33)], &[(11, a as u32), (12, b as u32)]);
+        hart.x[10] as i32
+    };
+    assert_eq!(m(0, -3, 5), -15); // mul
+    assert_eq!(m(1, -2, 3), -1); // mulh of -6
+    assert_eq!(m(2, -2, -1), -2); // mulhsu, where -1 is 2^32 - 1
+    assert_eq!(m(3, -2, -1) as u32, 0xFFFF_FFFD); // mulhu
+    // Signed division rounds toward zero, and the remainder takes the dividend's sign.
+    assert_eq!((m(4, -7, 2), m(6, -7, 2)), (-3, -1));
+    assert_eq!((m(5, -7, 2), m(7, -7, 2)), (0x7FFF_FFFC, 1));
+    // Division by zero and overflow don't trap.
+    assert_eq!((m(4, 7, 0), m(5, 7, 0), m(6, 7, 0), m(7, 7, 0)), (-1, -1, 7, 7));
+    assert_eq!((m(4, i32::MIN, -1), m(6, i32::MIN, -1)), (i32::MIN, 0));
+}
+
+#[test]
+fn test_rv32a() {
+    const DATA: u32 = RAM_BASE + 0x100;
+    let amo = |funct5: u32| r_type(funct5 << 2, 2, 0x2F);
+    let lr = amo(0x02) & !(0x1F << 20);
+    let sc = amo(0x03);
+    // (funct5, old, a2, new) where each returns old in a0
+    for (funct5, old, b, new) in [
+        (0x00, 5, 3, 8), // amoadd.w
+        (0x01, 5, 3, 3), // amoswap.w
+        (0x04, 6, 3, 5), // amoxor.w
+        (0x08, 6, 3, 7), // amoor.w
+        (0x0C, 6, 3, 2), // amoand.w
+        (0x10, u32::MAX, 1, u32::MAX), // amomin.w: -1 < 1
+        (0x14, u32::MAX, 1, 1), // amomax.w
+        (0x18, u32::MAX, 1, 1), // amominu.w
+        (0x1C, u32::MAX, 1, u32::MAX), // amomaxu.w
+    ] {
+        let (mut hart, mut bus) = load(&[amo(funct5)], &[(11, DATA), (12, b)]);
+        bus.store(DATA, 4, old).unwrap();
+        assert!(matches!(hart.step(&mut bus), Ok(Step::Retired(_))), "{:#x}", funct5);
+        assert_eq!((hart.x[10], bus.load(DATA, 4)), (old, Some(new)), "{:#x}", funct5);
+    }
+
+    // sc.w only stores with a reservation from lr.w, which it uses up.
+    let (hart, mut bus, _) = run(&[sc, lr, sc, sc], &[(11, DATA), (12, 7)]);
+    assert_eq!((hart.x[10], bus.load(DATA, 4)), (1, Some(7)));
+    let (hart, mut bus, _) = run(&[sc], &[(11, DATA), (12, 7)]);
+    assert_eq!((hart.x[10], bus.load(DATA, 4)), (1, Some(0)));
+    let (hart, mut bus, _) = run(&[lr, sc], &[(11, DATA), (12, 7)]);
+    assert_eq!((hart.x[10], bus.load(DATA, 4)), (0, Some(7)));
+    // A trap in between breaks the reservation too. mtvec points at the sc.w.
+    let csrw_mtvec_t0 = 0x305 << 20 | 5 << 15 | 1 << 12 | 0x73;
+    let (hart, mut bus, steps) = run(
+        &[csrw_mtvec_t0, lr, 0x0000_0073, sc], // ecall
+        &[(5, RAM_BASE + 12), (11, DATA), (12, 7)],
+    );
+    assert!(matches!(steps[2], Step::Exception(Exception::EcallFromM)));
+    assert_eq!((hart.x[10], bus.load(DATA, 4)), (1, Some(0)));
+
+    // Misaligned addresses trap instead of being split up.
+    let (_, _, steps) = run(&[lr], &[(11, DATA + 2)]);
+    assert!(matches!(
+        steps[0],
+        Step::Exception(Exception::LoadAddressMisaligned(a)) if a == DATA + 2,
+    ));
+    for insn in [sc, amo(0x00)] {
+        let (_, _, steps) = run(&[insn], &[(11, DATA + 2)]);
+        assert!(matches!(
+            steps[0],
+            Step::Exception(Exception::StoreAddressMisaligned(a)) if a == DATA + 2,
+        ));
+    }
+}
+
+/// mepc only has to be 2-byte aligned, so a trap handler can skip a compressed instruction.
+#[test]
+fn test_mret_to_halfword() {
+    let mut bus = Bus::default();
+    bus.load_image(RAM_BASE, &0x3020_0073u32.to_le_bytes()).unwrap(); // mret
+    let mut hart = Hart::new(RAM_BASE);
+    hart.write_csr(0x341, RAM_BASE + 0x102).unwrap();
+    assert_eq!(hart.mepc, RAM_BASE + 0x102);
+    hart.step(&mut bus).unwrap();
+    assert_eq!(hart.pc, RAM_BASE + 0x102);
+}
@@ -0,0 +1,571 @@
+//! A GDB remote serial protocol stub, for `srun --gdb`.
+//!
+//! This implements just enough of the protocol for `gdb-multiarch` and `riscv32-elf-gdb`:
+//! registers (including CSRs), memory, software breakpoints, single-step, and continue.
+//! Breakpoints are `ebreak`s (or `c.ebreak`s) written into memory, and the stub stops whenever the
+//! hart is about to execute one, whether or not gdb put it there. Reading memory only sees RAM, so
+//! looking at a device's registers can't change them.
+
+use super::{Bus, Halt, Hart, CSRS};
+use std::io::{self, prelude::*};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u32 = 0x9002;
+
+/// The breakpoint instruction that fits in size bytes: gdb asks for 2-byte breakpoints on
+/// compressed instructions.
+fn ebreak(size: u32) -> u32 {
+    if size == 2 { C_EBREAK } else { EBREAK }
+}
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// GDB numbers CSRs starting here.
+const FIRST_CSR_REGNUM: u32 = 65;
+
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\">\
+         <architecture>riscv:rv32</architecture>\
+         <feature name=\"org.gnu.gdb.riscv.cpu\">",
+    );
+    for (i, name) in crate::disasm::REG_NAMES.iter().enumerate() {
+        let ty = match i {
+            1 => "code_ptr",
+            2 => "data_ptr",
+            _ => "int",
+        };
+        xml += &format!("<reg name=\"{}\" bitsize=\"32\" type=\"{}\"/>", name, ty);
+    }
+    xml += "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>\
+            </feature>\
+            <feature name=\"org.gnu.gdb.riscv.csr\">";
+    for &(name, csr) in CSRS.iter() {
+        xml += &format!(
+            "<reg name=\"{}\" bitsize=\"32\" regnum=\"{}\" group=\"csr\"/>",
+            name,
+            FIRST_CSR_REGNUM + csr,
+        );
+    }
+    xml += "</feature></target>";
+    xml
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns None if s has an odd number of digits.
+fn from_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+/// Parse "addr,len".
+fn parse_range(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, ',');
+    Some((parse_u32(parts.next()?)?, parse_u32(parts.next()?)?))
+}
+
+/// What the emulator should do next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Run,
+    /// gdb detached, so the hart should keep running without the stub.
+    Detach,
+    Kill,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Stopped(u8), // signal
+    Continuing,
+    Stepping,
+}
+
+pub struct Stub {
+    stream: TcpStream,
+    input: Vec<u8>,
+    state: State,
+    breakpoints: Vec<(u32, u32, u32)>, // (address, size, original instruction)
+    /// Set when resuming, so the instruction at pc runs even if it's an ebreak.
+    resumed_at: Option<u32>,
+}
+
+impl Stub {
+    /// Wait for gdb to connect. The hart starts out stopped.
+    pub fn accept(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Stub {
+            stream,
+            input: Vec::new(),
+            state: State::Stopped(SIGTRAP),
+            breakpoints: Vec::new(),
+            resumed_at: None,
+        })
+    }
+
+    fn fill_input(&mut self) -> io::Result<()> {
+        let mut buf = [0; 0x1000];
+        let n = self.stream.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "gdb disconnected"));
+        }
+        self.input.extend_from_slice(&buf[..n]);
+        Ok(())
+    }
+
+    /// Read one packet and acknowledge it. Acks from gdb and interrupts while stopped are dropped.
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(start) = self.input.iter().position(|&b| b == b'$') {
+                if let Some(hash) = self.input[start..].iter().position(|&b| b == b'#') {
+                    let end = start + hash + 3;
+                    if self.input.len() >= end {
+                        let body = self.input[start + 1..start + hash].to_vec();
+                        let checksum = std::str::from_utf8(&self.input[end - 2..end]).ok()
+                            .and_then(|s| u8::from_str_radix(s, 16).ok());
+                        self.input.drain(..end);
+                        let sum = body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                        if checksum != Some(sum) {
+                            self.stream.write_all(b"-")?;
+                            continue;
+                        }
+                        self.stream.write_all(b"+")?;
+                        return Ok(String::from_utf8_lossy(&body).into_owned());
+                    }
+                }
+            } else {
+                self.input.clear();
+            }
+            self.fill_input()?;
+        }
+    }
+
+    fn send(&mut self, body: &str) -> io::Result<()> {
+        let sum = body.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", body, sum)?;
+        self.stream.flush()
+    }
+
+    /// Check whether gdb has sent an interrupt (Ctrl-C). This doesn't block, so it's fine to call
+    /// it every so often while the hart is running.
+    pub fn poll_interrupt(&mut self) -> io::Result<()> {
+        if self.state != State::Continuing {
+            return Ok(());
+        }
+        self.stream.set_nonblocking(true)?;
+        let result = self.fill_input();
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            r => r?,
+        }
+        if let Some(i) = self.input.iter().position(|&b| b == 0x03) {
+            self.input.remove(i);
+            self.state = State::Stopped(SIGINT);
+        }
+        Ok(())
+    }
+
+    fn patch_all(&mut self, bus: &mut Bus) {
+        for &(addr, size, _) in self.breakpoints.iter() {
+            bus.store(addr, size, ebreak(size));
+        }
+    }
+
+    fn unpatch_all(&mut self, bus: &mut Bus) {
+        for &(addr, size, orig) in self.breakpoints.iter().rev() {
+            bus.store(addr, size, orig);
+        }
+    }
+
+    /// Call this after the image is (re)loaded, since that overwrites the breakpoints.
+    pub fn reload(&mut self, bus: &mut Bus) {
+        for bp in self.breakpoints.iter_mut() {
+            bp.2 = bus.peek(bp.0, bp.1).unwrap_or(0);
+        }
+        self.patch_all(bus);
+    }
+
+    fn read_memory(&mut self, bus: &mut Bus, addr: u32, len: u32) -> Option<Vec<u8>> {
+        self.unpatch_all(bus);
+        let bytes = (0..len)
+            .map(|i| bus.peek(addr.wrapping_add(i), 1).map(|b| b as u8))
+            .collect();
+        self.patch_all(bus);
+        bytes
+    }
+
+    fn write_memory(&mut self, bus: &mut Bus, addr: u32, bytes: &[u8]) -> Option<()> {
+        self.unpatch_all(bus);
+        let result = bytes.iter().enumerate()
+            .try_for_each(|(i, &b)| bus.store(addr.wrapping_add(i as u32), 1, b as u32));
+        self.reload(bus);
+        result
+    }
+
+    fn read_reg(hart: &Hart, bus: &Bus, n: u32) -> Option<u32> {
+        match n {
+            0..=31 => Some(hart.x[n as usize]),
+            32 => Some(hart.pc),
+            _ => hart.read_csr(n.checked_sub(FIRST_CSR_REGNUM)?, bus),
+        }
+    }
+
+    fn write_reg(hart: &mut Hart, n: u32, value: u32) -> Option<()> {
+        match n {
+            0 => (),
+            1..=31 => hart.x[n as usize] = value,
+            32 => hart.pc = value,
+            _ => hart.write_csr(n.checked_sub(FIRST_CSR_REGNUM)?, value)?,
+        }
+        Some(())
+    }
+
+    /// Handle one packet while stopped. Returns Some if the hart should resume.
+    fn handle(&mut self, packet: &str, hart: &mut Hart, bus: &mut Bus)
+        -> io::Result<Option<Action>>
+    {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => format!("S{:02x}", self.stop_signal()),
+            "g" => {
+                let regs: Vec<u8> = hart.x.iter().chain(Some(&hart.pc))
+                    .flat_map(|r| r.to_le_bytes().to_vec())
+                    .collect();
+                to_hex(&regs)
+            },
+            "G" => match from_hex_bytes(args) {
+                Some(ref bytes) if bytes.len() == 33 * 4 => {
+                    for (n, chunk) in bytes.chunks(4).enumerate() {
+                        let mut word = [0; 4];
+                        word.copy_from_slice(chunk);
+                        Self::write_reg(hart, n as u32, u32::from_le_bytes(word));
+                    }
+                    "OK".to_owned()
+                },
+                _ => "E01".to_owned(),
+            },
+            "p" => match parse_u32(args).and_then(|n| Self::read_reg(hart, bus, n)) {
+                Some(value) => to_hex(&value.to_le_bytes()),
+                None => "E01".to_owned(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(parse_u32);
+                let value = parts.next().and_then(from_hex_bytes);
+                match (n, value) {
+                    (Some(n), Some(ref bytes)) if bytes.len() == 4 => {
+                        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                        match Self::write_reg(hart, n, value) {
+                            Some(()) => "OK".to_owned(),
+                            None => "E01".to_owned(),
+                        }
+                    },
+                    _ => "E01".to_owned(),
+                }
+            },
+            "m" => match parse_range(args).and_then(|(a, len)| self.read_memory(bus, a, len)) {
+                Some(bytes) => to_hex(&bytes),
+                None => "E14".to_owned(), // EFAULT
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let bytes = parts.next().and_then(from_hex_bytes);
+                match (range, bytes) {
+                    (Some((addr, len)), Some(ref bytes)) if bytes.len() == len as usize => {
+                        match self.write_memory(bus, addr, bytes) {
+                            Some(()) => "OK".to_owned(),
+                            None => "E14".to_owned(),
+                        }
+                    },
+                    _ => "E01".to_owned(),
+                }
+            },
+            "c" | "s" => {
+                if let Some(addr) = parse_u32(args) {
+                    hart.pc = addr;
+                }
+                self.state = if cmd == "c" { State::Continuing } else { State::Stepping };
+                self.resumed_at = Some(hart.pc);
+                return Ok(Some(Action::Run));
+            },
+            "Z" | "z" if args.starts_with("0,") => {
+                let mut parts = args[2..].splitn(2, ',');
+                let addr = parts.next().and_then(parse_u32);
+                // The kind is how many bytes the instruction there takes up.
+                let size = match parts.next() {
+                    Some("2") => 2,
+                    _ => 4,
+                };
+                match addr {
+                    Some(addr) if addr & 0b1 == 0 => {
+                        let present = self.breakpoints.iter().any(|&(a, _, _)| a == addr);
+                        if cmd == "Z" && !present {
+                            match bus.peek(addr, size) {
+                                Some(orig) if bus.store(addr, size, ebreak(size)).is_some() => {
+                                    self.breakpoints.push((addr, size, orig));
+                                    "OK".to_owned()
+                                },
+                                _ => "E14".to_owned(),
+                            }
+                        } else if cmd == "z" && present {
+                            let i = self.breakpoints.iter()
+                                .position(|&(a, _, _)| a == addr)
+                                .unwrap();
+                            let (_, size, orig) = self.breakpoints.remove(i);
+                            bus.store(addr, size, orig);
+                            "OK".to_owned()
+                        } else {
+                            "OK".to_owned()
+                        }
+                    },
+                    _ => "E01".to_owned(),
+                }
+            },
+            "k" => return Ok(Some(Action::Kill)),
+            "D" => {
+                self.unpatch_all(bus);
+                self.breakpoints.clear();
+                self.send("OK")?;
+                return Ok(Some(Action::Detach));
+            },
+            "H" => "OK".to_owned(), // there's only one thread
+            "T" => "OK".to_owned(),
+            "q" => {
+                if args.starts_with("Supported") {
+                    "PacketSize=1000;qXfer:features:read+".to_owned()
+                } else if args == "Attached" {
+                    "1".to_owned()
+                } else if args == "C" {
+                    "QC1".to_owned()
+                } else if args == "fThreadInfo" {
+                    "m1".to_owned()
+                } else if args == "sThreadInfo" {
+                    "l".to_owned()
+                } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+                    match parse_range(range) {
+                        Some((offset, len)) => {
+                            let xml = target_xml();
+                            let start = (offset as usize).min(xml.len());
+                            let end = start.saturating_add(len as usize).min(xml.len());
+                            let more = if end < xml.len() { "m" } else { "l" };
+                            format!("{}{}", more, &xml[start..end])
+                        },
+                        None => "E01".to_owned(),
+                    }
+                } else {
+                    String::new()
+                }
+            },
+            _ => String::new(), // unsupported
+        };
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    fn stop_signal(&self) -> u8 {
+        match self.state {
+            State::Stopped(signal) => signal,
+            _ => SIGTRAP,
+        }
+    }
+
+    /// Call this before every step. If the hart should stop, this talks to gdb until it says to
+    /// resume.
+    pub fn before_step(&mut self, hart: &mut Hart, bus: &mut Bus) -> io::Result<Action> {
+        if self.state == State::Continuing && self.resumed_at != Some(hart.pc)
+            && (bus.peek(hart.pc, 4) == Some(EBREAK)
+                || bus.peek(hart.pc, 2) == Some(C_EBREAK))
+        {
+            self.state = State::Stopped(SIGTRAP);
+            self.send(&format!("S{:02x}", SIGTRAP))?;
+        } else if self.state == State::Stopped(SIGINT) {
+            // poll_interrupt doesn't send anything, because it isn't allowed to block.
+            self.send(&format!("S{:02x}", SIGINT))?;
+        }
+        while let State::Stopped(_) = self.state {
+            let packet = self.read_packet()?;
+            match self.handle(&packet, hart, bus)? {
+                Some(Action::Run) | None => (),
+                Some(action) => return Ok(action),
+            }
+        }
+        // To resume from a breakpoint, run the original instruction in its place.
+        if let Some(pc) = self.resumed_at {
+            if let Some(&(addr, size, orig)) = self.breakpoints.iter().find(|&&(a, _, _)| a == pc) {
+                bus.store(addr, size, orig);
+            }
+        }
+        Ok(Action::Run)
+    }
+
+    /// Call this after every step.
+    pub fn after_step(&mut self, bus: &mut Bus) -> io::Result<()> {
+        if let Some(pc) = self.resumed_at.take() {
+            if let Some(&(_, size, _)) = self.breakpoints.iter().find(|&&(a, _, _)| a == pc) {
+                bus.store(pc, size, ebreak(size));
+            }
+        }
+        if self.state == State::Stepping {
+            self.state = State::Stopped(SIGTRAP);
+            self.send(&format!("S{:02x}", SIGTRAP))?;
+        }
+        Ok(())
+    }
+
+    /// Tell gdb the machine stopped for good.
+    pub fn exited(&mut self, halt: Halt) -> io::Result<()> {
+        match halt {
+            Halt::Pass => self.send("W00"),
+            Halt::Fail(code) => self.send(&format!("W{:02x}", code as u8)),
+            Halt::Reset => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_hex_bytes() {
+    assert_eq!(from_hex_bytes("78563412").unwrap(), [0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(to_hex(&[0x78, 0x56, 0x34, 0x12]), "78563412");
+    assert!(from_hex_bytes("785").is_none());
+    assert_eq!(parse_range("80000008,4"), Some((0x8000_0008, 4)));
+}
+
+/// A stub talking to a fake gdb over loopback.
+#[cfg(test)]
+fn connect() -> (Stub, TcpStream) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let gdb = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    (Stub::new(stream).unwrap(), gdb)
+}
+
+/// Read the next packet the stub sent, skipping acks, and check its checksum.
+#[cfg(test)]
+fn receive(gdb: &mut TcpStream) -> String {
+    // One byte at a time, so nothing after this packet gets read.
+    let mut next = || {
+        let mut b = [0];
+        gdb.read_exact(&mut b).unwrap();
+        b[0]
+    };
+    let mut bytes = std::iter::from_fn(|| Some(next())).skip_while(|&b| b == b'+');
+    assert_eq!(bytes.next(), Some(b'$'));
+    let body: Vec<u8> = bytes.by_ref().take_while(|&b| b != b'#').collect();
+    let checksum: Vec<u8> = bytes.take(2).collect();
+    let sum = body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    assert_eq!(checksum, format!("{:02x}", sum).as_bytes());
+    String::from_utf8(body).unwrap()
+}
+
+/// Handle a packet that doesn't resume the hart and return the reply.
+#[cfg(test)]
+fn ask(stub: &mut Stub, gdb: &mut TcpStream, packet: &str, hart: &mut Hart, bus: &mut Bus)
+    -> String
+{
+    assert_eq!(stub.handle(packet, hart, bus).unwrap(), None);
+    receive(gdb)
+}
+
+/// Packets with a bad checksum get a "-" so gdb sends them again, and good ones get a "+".
+#[test]
+fn test_checksums() {
+    let (mut stub, mut gdb) = connect();
+    gdb.write_all(b"+$?#00$?#3f").unwrap();
+    assert_eq!(stub.read_packet().unwrap(), "?");
+    let mut acks = [0; 2];
+    gdb.read_exact(&mut acks).unwrap();
+    assert_eq!(&acks, b"-+");
+    let (mut hart, mut bus) = super::load(&[], &[]);
+    assert_eq!(ask(&mut stub, &mut gdb, "?", &mut hart, &mut bus), "S05");
+}
+
+/// g and p use gdb's numbering: x0 to x31, then pc, then the CSRs from 65. Values are
+/// little-endian.
+#[test]
+fn test_registers() {
+    let (mut stub, mut gdb) = connect();
+    let (mut hart, mut bus) = super::load(&[], &[(1, 0x8000_0010), (31, 0x1234_5678)]);
+    let regs = ask(&mut stub, &mut gdb, "g", &mut hart, &mut bus);
+    assert_eq!(regs.len(), 33 * 8);
+    assert_eq!(&regs[..16], "0000000010000080");
+    assert_eq!(&regs[31 * 8..], "7856341200000080");
+    assert_eq!(ask(&mut stub, &mut gdb, "p1f", &mut hart, &mut bus), "78563412");
+    assert_eq!(ask(&mut stub, &mut gdb, "p20", &mut hart, &mut bus), "00000080");
+    assert_eq!(ask(&mut stub, &mut gdb, "p21", &mut hart, &mut bus), "E01");
+
+    assert_eq!(ask(&mut stub, &mut gdb, "P20=04000080", &mut hart, &mut bus), "OK");
+    assert_eq!(hart.pc, 0x8000_0004);
+    assert_eq!(ask(&mut stub, &mut gdb, "P381=efbeadde", &mut hart, &mut bus), "OK");
+    assert_eq!(hart.mscratch, 0xDEAD_BEEF);
+    assert_eq!(ask(&mut stub, &mut gdb, "p381", &mut hart, &mut bus), "efbeadde");
+
+    // Writing x0 does nothing.
+    let regs = "01000000".repeat(33);
+    assert_eq!(ask(&mut stub, &mut gdb, &format!("G{}", regs), &mut hart, &mut bus), "OK");
+    assert_eq!((hart.x[0], hart.x[1], hart.x[31], hart.pc), (0, 1, 1, 1));
+}
+
+/// Breakpoints are ebreaks in memory, but gdb reads the original instructions, and continuing
+/// from one runs the original instruction without losing the breakpoint.
+#[test]
+fn test_breakpoints() {
+    let (mut stub, mut gdb) = connect();
+    // addi a0, a0, 1; j -4
+    let (mut hart, mut bus) = super::load(&[0x0015_0513, 0xFFDF_F06F], &[]);
+    let start = hart.pc;
+    assert_eq!(ask(&mut stub, &mut gdb, "Z0,80000000,4", &mut hart, &mut bus), "OK");
+    assert_eq!(bus.peek(start, 4), Some(EBREAK));
+    assert_eq!(ask(&mut stub, &mut gdb, "Z0,80000004,2", &mut hart, &mut bus), "OK");
+    assert_eq!(bus.peek(start + 4, 4), Some(0xFFDF_0000 | C_EBREAK));
+    assert_eq!(ask(&mut stub, &mut gdb, "m80000000,8", &mut hart, &mut bus), "130515006ff0dfff");
+    assert_eq!(ask(&mut stub, &mut gdb, "z0,80000004,2", &mut hart, &mut bus), "OK");
+    assert_eq!(bus.peek(start + 4, 4), Some(0xFFDF_F06F));
+    assert_eq!(ask(&mut stub, &mut gdb, "Z0,80000001,4", &mut hart, &mut bus), "E01");
+
+    // Continue, go around the loop back to the breakpoint, step over it, and kill.
+    gdb.write_all(b"$c#63$s#73$k#6b").unwrap();
+    assert_eq!(stub.before_step(&mut hart, &mut bus).unwrap(), Action::Run);
+    assert_eq!(bus.peek(start, 4), Some(0x0015_0513));
+    for _ in 0..2 {
+        hart.step(&mut bus).unwrap();
+        stub.after_step(&mut bus).unwrap();
+        assert_eq!(bus.peek(start, 4), Some(EBREAK));
+        assert_eq!(stub.before_step(&mut hart, &mut bus).unwrap(), Action::Run);
+    }
+    assert_eq!((hart.pc, hart.x[10]), (start, 1));
+    assert_eq!(receive(&mut gdb), "S05");
+    hart.step(&mut bus).unwrap();
+    stub.after_step(&mut bus).unwrap();
+    assert_eq!(receive(&mut gdb), "S05");
+    assert_eq!(stub.before_step(&mut hart, &mut bus).unwrap(), Action::Kill);
+    assert_eq!((hart.pc, hart.x[10]), (start + 4, 2));
+}
+
+/// Reading memory mustn't take the byte waiting in the UART.
+#[test]
+fn test_read_device() {
+    let (mut stub, mut gdb) = connect();
+    let (uart_tx, uart_rx) = std::sync::mpsc::channel();
+    let mut bus = super::virt::bus(uart_rx, Box::new(io::sink()), Default::default());
+    let mut hart = Hart::new(super::RAM_BASE);
+    uart_tx.send(b'x').unwrap();
+    assert_eq!(ask(&mut stub, &mut gdb, "m10000000,1", &mut hart, &mut bus), "E14");
+    assert_eq!(bus.load(super::virt::UART_BASE, 1), Some(b'x' as u32));
+}
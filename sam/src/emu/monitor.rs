@@ -0,0 +1,398 @@
+//! The command interpreter behind `srun --debug`.
+//!
+//! Addresses can be written in hex (`#8000'0010`) or as labels from the object, optionally with an
+//! offset (`loop+#8`). Counts are decimal unless they start with `#`. Examining memory only reads
+//! RAM, since reading some device registers changes them.
+
+use super::{Bus, Hart, Step, CSRS};
+use crate::disasm::{disassemble_fetched, fetched_hex, REG_NAMES};
+use crate::{from_hex, u32_to_hex, Labels};
+
+pub const HELP: &str = "\
+commands:
+    b, break [ADDR]         stop before executing ADDR (or list breakpoints)
+    d, delete ADDR          remove a breakpoint
+    w, watch [ADDR]         stop after a store to ADDR (or list watchpoints)
+    unwatch ADDR            remove a watchpoint
+    s, step [N]             execute N instructions (default 1)
+    c, continue             run until something stops the hart
+    r, regs                 show registers and CSRs
+    x ADDR [N]              examine N words (default 4)
+    xb ADDR [N]             examine N bytes (default 16)
+    xs ADDR [N]             examine at most N bytes (default 64) as UTF-8, up to a NUL
+    q, quit                 exit srun
+    h, help                 show this";
+
+/// What a command did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    Lines(Vec<String>),
+    Resume,
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Monitor {
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<u32>,
+    steps_left: Option<u64>, // None means run until stopped
+}
+
+fn parse_addr(s: &str, labels: &Labels) -> Result<u32, String> {
+    if s.starts_with('#') {
+        return from_hex(s, 32);
+    }
+    let (name, offset) = match s.find('+') {
+        Some(i) => (&s[..i], from_hex(&s[i + 1..], 32)?),
+        None => (s, 0),
+    };
+    labels.address_of(name)
+        .map(|addr| addr.wrapping_add(offset))
+        .ok_or_else(|| format!("no label named {:?}", name))
+}
+
+fn parse_count(s: Option<&str>, default: u32) -> Result<u32, String> {
+    match s {
+        None => Ok(default),
+        Some(s) if s.starts_with('#') => from_hex(s, 32),
+        Some(s) => s.parse().map_err(|_| format!("invalid count {:?}", s)),
+    }
+}
+
+/// addr, with its label if it has one.
+fn location(addr: u32, labels: &Labels) -> String {
+    match labels.symbolize(addr) {
+        Some(s) => format!("{} {}", u32_to_hex(addr), s),
+        None => u32_to_hex(addr),
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Where the hart is and what it's about to execute.
+    pub fn current(&self, hart: &Hart, bus: &Bus, labels: &Labels) -> String {
+        // A compressed instruction could be the last thing in memory.
+        let fetched = bus.peek(hart.pc, 4).or_else(|| bus.peek(hart.pc, 2));
+        let insn = match fetched {
+            Some(insn) => format!(
+                "{}  {}",
+                fetched_hex(insn),
+                disassemble_fetched(insn).unwrap_or_else(|| "???".to_owned()),
+            ),
+            None => "(can't fetch)".to_owned(),
+        };
+        let waiting = if hart.waiting { " (in wfi)" } else { "" };
+        format!("{}: {}{}", location(hart.pc, labels), insn, waiting)
+    }
+
+    /// Call this after every step. If the hart should stop, returns why.
+    pub fn check(&mut self, step: &Step, hart: &Hart) -> Option<String> {
+        if let Step::Waiting = *step {
+            return None;
+        }
+        if let Step::Retired(ref r) = *step {
+            if let Some((addr, size, value)) = r.store {
+                let end = addr.wrapping_add(size);
+                if let Some(&w) = self.watchpoints.iter().find(|&&w| addr <= w && w < end) {
+                    return Some(format!(
+                        "watchpoint {}: [{}] <- {}",
+                        u32_to_hex(w),
+                        u32_to_hex(addr),
+                        u32_to_hex(value),
+                    ));
+                }
+            }
+        }
+        if let Some(ref mut n) = self.steps_left {
+            *n -= 1;
+            if *n == 0 {
+                return Some("stepped".to_owned());
+            }
+        }
+        if self.breakpoints.contains(&hart.pc) {
+            return Some("breakpoint".to_owned());
+        }
+        None
+    }
+
+    pub fn command(&mut self, line: &str, hart: &Hart, bus: &Bus, labels: &Labels)
+        -> Result<Reply, String>
+    {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(Reply::Lines(Vec::new())),
+        };
+        let arg = words.next();
+        let arg2 = words.next();
+        if words.next().is_some() {
+            return Err("too many arguments".to_owned());
+        }
+        let addr = || parse_addr(arg.ok_or("missing address")?, labels);
+
+        let lines = match cmd {
+            "b" | "break" | "w" | "watch" => {
+                let list = if cmd.starts_with('b') {
+                    &mut self.breakpoints
+                } else {
+                    &mut self.watchpoints
+                };
+                if arg.is_none() {
+                    list.iter().map(|&a| location(a, labels)).collect()
+                } else {
+                    let a = addr()?;
+                    if !list.contains(&a) {
+                        list.push(a);
+                    }
+                    vec![location(a, labels)]
+                }
+            },
+            "d" | "delete" | "unwatch" => {
+                let list = if cmd == "unwatch" {
+                    &mut self.watchpoints
+                } else {
+                    &mut self.breakpoints
+                };
+                let a = addr()?;
+                let i = list.iter().position(|&x| x == a)
+                    .ok_or_else(|| format!("nothing set at {}", u32_to_hex(a)))?;
+                list.remove(i);
+                Vec::new()
+            },
+            "s" | "step" => {
+                let n = parse_count(arg, 1)?;
+                if n == 0 {
+                    return Err("can't step 0 instructions".to_owned());
+                }
+                self.steps_left = Some(n as u64);
+                return Ok(Reply::Resume);
+            },
+            "c" | "continue" => {
+                self.steps_left = None;
+                return Ok(Reply::Resume);
+            },
+            "r" | "regs" => {
+                let mut lines: Vec<String> = REG_NAMES.chunks(4).enumerate()
+                    .map(|(row, names)| {
+                        names.iter().enumerate()
+                            .map(|(col, name)| {
+                                format!("{:<4} {}", name, u32_to_hex(hart.x[row * 4 + col]))
+                            })
+                            .collect::<Vec<_>>()
+                            .join("  ")
+                    })
+                    .collect();
+                lines.push(format!("{:<4} {}", "pc", location(hart.pc, labels)));
+                lines.extend(CSRS.chunks(4).map(|csrs| {
+                    csrs.iter()
+                        .map(|&(name, csr)| {
+                            let value = hart.read_csr(csr, bus).unwrap_or(0);
+                            format!("{:<9} {}", name, u32_to_hex(value))
+                        })
+                        .collect::<Vec<_>>()
+                        .join("  ")
+                }));
+                lines
+            },
+            "x" => {
+                let a = addr()?;
+                if a & 0b11 != 0 {
+                    return Err(format!("{} isn't word-aligned", u32_to_hex(a)));
+                }
+                let n = parse_count(arg2, 4)?;
+                let mut lines = Vec::new();
+                for row in (0..n).step_by(4) {
+                    let row_addr = a.wrapping_add(row * 4);
+                    let mut line = format!("{}:", u32_to_hex(row_addr));
+                    for i in row..n.min(row + 4) {
+                        let word_addr = a.wrapping_add(i * 4);
+                        let word = bus.peek(word_addr, 4)
+                            .ok_or_else(|| format!("can't read {}", u32_to_hex(word_addr)))?;
+                        line += &format!(" {}", u32_to_hex(word));
+                    }
+                    lines.push(line);
+                }
+                lines
+            },
+            "xb" => {
+                let a = addr()?;
+                let n = parse_count(arg2, 16)?;
+                let mut lines = Vec::new();
+                for row in (0..n).step_by(8) {
+                    let mut line = format!("{}:", u32_to_hex(a.wrapping_add(row)));
+                    for i in row..n.min(row + 8) {
+                        let byte_addr = a.wrapping_add(i);
+                        let byte = bus.peek(byte_addr, 1)
+                            .ok_or_else(|| format!("can't read {}", u32_to_hex(byte_addr)))?;
+                        line += &format!(" #{:02X}", byte);
+                    }
+                    lines.push(line);
+                }
+                lines
+            },
+            "xs" => {
+                let a = addr()?;
+                let n = parse_count(arg2, 64)?;
+                let mut bytes = Vec::new();
+                for i in 0..n {
+                    let byte_addr = a.wrapping_add(i);
+                    match bus.peek(byte_addr, 1) {
+                        Some(0) => break,
+                        Some(b) => bytes.push(b as u8),
+                        None if bytes.is_empty() => {
+                            return Err(format!("can't read {}", u32_to_hex(byte_addr)));
+                        },
+                        None => break,
+                    }
+                }
+                vec![format!("{}: {:?}", u32_to_hex(a), String::from_utf8_lossy(&bytes))]
+            },
+            "q" | "quit" => return Ok(Reply::Quit),
+            "h" | "help" => HELP.lines().map(str::to_owned).collect(),
+            _ => return Err(format!("unknown command {:?} (try help)", cmd)),
+        };
+        Ok(Reply::Lines(lines))
+    }
+}
+
+#[test]
+fn test_parse_count() {
+    assert_eq!(parse_count(None, 4), Ok(4));
+    assert_eq!(parse_count(Some("10"), 4), Ok(10));
+    assert_eq!(parse_count(Some("#10"), 4), Ok(0x10));
+    assert!(parse_count(Some("ten"), 4).is_err());
+}
+
+/// labels for a program at the start of RAM with `loop` at +4 and `data` at +#100.
+#[cfg(test)]
+fn test_labels() -> Labels {
+    let mut strings: crate::StringTable = Default::default();
+    let mut symbols: crate::SymbolTable = Default::default();
+    let type_index = strings.get_index_or_insert("");
+    for (name, offset) in [("loop", 4), ("data", 0x100)] {
+        let name_index = strings.get_index_or_insert(name);
+        let value = crate::SymbolValue::Code { external: false, type_index, offset: Some(offset) };
+        symbols.insert(name_index, value);
+    }
+    Labels::new(&crate::Object {
+        load_address: super::RAM_BASE,
+        extensions: 0,
+        code_and_data: vec![0; 0x104],
+        strings,
+        symbols,
+        relocations: Default::default(),
+    })
+}
+
+#[cfg(test)]
+fn lines(lines: &[&str]) -> Result<Reply, String> {
+    Ok(Reply::Lines(lines.iter().map(|&line| line.to_owned()).collect()))
+}
+
+#[test]
+fn test_breakpoints() {
+    let labels = test_labels();
+    // addi a0, a0, 1; addi a0, a0, 1; j -4
+    let (mut hart, mut bus) = super::load(&[0x0015_0513, 0x0015_0513, 0xFFDF_F06F], &[]);
+    let mut monitor = Monitor::new();
+    assert_eq!(monitor.command("b loop", &hart, &bus, &labels), lines(&["#8000'0004 loop"]));
+    assert_eq!(monitor.command("b #8000'0004", &hart, &bus, &labels), lines(&["#8000'0004 loop"]));
+    assert_eq!(monitor.command("b", &hart, &bus, &labels), lines(&["#8000'0004 loop"]));
+    assert!(monitor.command("b nowhere", &hart, &bus, &labels).is_err());
+
+    assert_eq!(monitor.command("c", &hart, &bus, &labels), Ok(Reply::Resume));
+    let mut stops = Vec::new();
+    for _ in 0..3 {
+        let step = hart.step(&mut bus).unwrap();
+        stops.push((hart.pc, monitor.check(&step, &hart)));
+    }
+    let stop = Some("breakpoint".to_owned());
+    assert_eq!(stops, [(0x8000_0004, stop.clone()), (0x8000_0008, None), (0x8000_0004, stop)]);
+
+    assert_eq!(monitor.command("d loop", &hart, &bus, &labels), lines(&[]));
+    assert_eq!(
+        monitor.command("d loop", &hart, &bus, &labels),
+        Err("nothing set at #8000'0004".to_owned()),
+    );
+    let step = hart.step(&mut bus).unwrap();
+    assert_eq!(monitor.check(&step, &hart), None);
+}
+
+/// A watchpoint is on one byte, and stops after any store that covers it.
+#[test]
+fn test_watchpoints() {
+    let labels = test_labels();
+    // sb a0, 4(a1); sw a0, 0(a1); sb a0, 3(a1)
+    let insns = [0x00A5_8223, 0x00A5_A023, 0x00A5_81A3];
+    let (mut hart, mut bus) = super::load(&insns, &[(10, 7), (11, 0x8000_0100)]);
+    let mut monitor = Monitor::new();
+    assert_eq!(monitor.command("w data+#3", &hart, &bus, &labels), lines(&["#8000'0103 data+#3"]));
+    assert_eq!(monitor.command("c", &hart, &bus, &labels), Ok(Reply::Resume));
+    let mut stops = Vec::new();
+    for _ in insns {
+        let step = hart.step(&mut bus).unwrap();
+        stops.push(monitor.check(&step, &hart));
+    }
+    let stop = "watchpoint #8000'0103: [#8000'0100] <- #0000'0007";
+    assert_eq!(stops, [None, Some(stop.to_owned()), Some(stop.replace("0100]", "0103]"))]);
+
+    assert_eq!(monitor.command("unwatch data+#3", &hart, &bus, &labels), lines(&[]));
+    assert_eq!(monitor.command("w", &hart, &bus, &labels), lines(&[]));
+}
+
+/// step N stops after N instructions, not counting time spent in wfi.
+#[test]
+fn test_step() {
+    let labels = test_labels();
+    let (mut hart, mut bus) = super::load(&[0x0015_0513; 4], &[]);
+    let mut monitor = Monitor::new();
+    assert_eq!(monitor.command("s 3", &hart, &bus, &labels), Ok(Reply::Resume));
+    let mut stops = Vec::new();
+    for _ in 0..3 {
+        let step = hart.step(&mut bus).unwrap();
+        stops.push(monitor.check(&step, &hart));
+        assert_eq!(monitor.check(&Step::Waiting, &hart), None);
+    }
+    assert_eq!(stops, [None, None, Some("stepped".to_owned())]);
+    assert_eq!(hart.x[10], 3);
+
+    assert_eq!(monitor.command("step", &hart, &bus, &labels), Ok(Reply::Resume));
+    let step = hart.step(&mut bus).unwrap();
+    assert_eq!(monitor.check(&step, &hart), Some("stepped".to_owned()));
+    assert!(monitor.command("s 0", &hart, &bus, &labels).is_err());
+}
+
+/// Examining memory shows RAM, but not device registers, since reading the UART's RBR would take
+/// the byte waiting there.
+#[test]
+fn test_examine() {
+    let labels = test_labels();
+    let (uart_tx, uart_rx) = std::sync::mpsc::channel();
+    let mut bus = super::virt::bus(uart_rx, Box::new(std::io::sink()), Default::default());
+    bus.load_image(super::RAM_BASE, b"hi\0\0\x13\x05\x15\x00").unwrap();
+    let hart = Hart::new(super::RAM_BASE);
+    let mut monitor = Monitor::new();
+    assert_eq!(
+        monitor.command("x #8000'0000 2", &hart, &bus, &labels),
+        lines(&["#8000'0000: #0000'6968 #0015'0513"]),
+    );
+    assert_eq!(
+        monitor.command("xb #8000'0000 3", &hart, &bus, &labels),
+        lines(&["#8000'0000: #68 #69 #00"]),
+    );
+    assert_eq!(
+        monitor.command("xs #8000'0000", &hart, &bus, &labels),
+        lines(&["#8000'0000: \"hi\""]),
+    );
+
+    uart_tx.send(b'x').unwrap();
+    assert_eq!(
+        monitor.command("xb #1000'0000 1", &hart, &bus, &labels),
+        Err("can't read #1000'0000".to_owned()),
+    );
+    assert!(monitor.command("xs #1000'0000", &hart, &bus, &labels).is_err());
+    assert_eq!(bus.load(super::virt::UART_BASE, 1), Some(b'x' as u32));
+}
@@ -0,0 +1,111 @@
+//! A PCIe host bridge with ECAM config space, like the one on QEMU's `virt`.
+
+use super::Device;
+
+pub const ECAM_BASE: u32 = 0x3000_0000;
+pub const ECAM_LEN: u32 = 0x1000_0000; // 256 buses
+
+const HEADER_LEN: usize = 0x40;
+
+/// One PCI function with a type 0 header and no BARs or capabilities.
+pub struct Function {
+    header: [u8; HEADER_LEN],
+}
+
+impl Function {
+    /// class is the 24-bit class code (base class, subclass, programming interface).
+    pub fn new(vendor_id: u16, device_id: u16, class: u32, revision_id: u8) -> Self {
+        let mut header = [0; HEADER_LEN];
+        header[0x00..0x02].copy_from_slice(&vendor_id.to_le_bytes());
+        header[0x02..0x04].copy_from_slice(&device_id.to_le_bytes());
+        header[0x08] = revision_id;
+        header[0x09..0x0C].copy_from_slice(&class.to_le_bytes()[..3]);
+        header[0x2C..0x2E].copy_from_slice(&vendor_id.to_le_bytes()); // subsystem vendor ID
+        Function { header }
+    }
+
+    /// The host bridge QEMU puts at 00:00.0.
+    pub fn host_bridge() -> Self {
+        Function::new(0x1B36, 0x0008, 0x06_00_00, 0)
+    }
+
+    fn load(&self, offset: u32, size: u32) -> u32 {
+        let offset = offset as usize;
+        if offset >= HEADER_LEN {
+            return 0;
+        }
+        let mut buf = [0; 4];
+        buf[..size as usize].copy_from_slice(&self.header[offset..offset + size as usize]);
+        u32::from_le_bytes(buf)
+    }
+
+    fn store(&mut self, offset: u32, size: u32, value: u32) {
+        for (i, &b) in value.to_le_bytes()[..size as usize].iter().enumerate() {
+            let offset = offset as usize + i;
+            // Only the writable registers we care about: command (but not status), cache line
+            // size, latency timer, and interrupt line.
+            if let 0x04 | 0x05 | 0x0C | 0x0D | 0x3C = offset {
+                self.header[offset] = b;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for offset in [0x04, 0x05, 0x0C, 0x0D, 0x3C] {
+            self.header[offset] = 0;
+        }
+    }
+}
+
+/// Config space for every function on every bus. Reads from functions that don't exist return
+/// all ones, which is how software tells they aren't there.
+pub struct Ecam {
+    functions: Vec<(u32, Function)>, // (bus << 8 | device << 3 | function, function)
+}
+
+impl Ecam {
+    /// Starts with just the host bridge.
+    pub fn new() -> Self {
+        Ecam { functions: vec![(0, Function::host_bridge())] }
+    }
+
+    pub fn add_function(&mut self, bus: u8, device: u8, function: u8, f: Function) {
+        assert!(device < 32 && function < 8);
+        let bdf = (bus as u32) << 8 | (device as u32) << 3 | function as u32;
+        self.functions.retain(|&(x, _)| x != bdf);
+        self.functions.push((bdf, f));
+    }
+
+    fn function(&mut self, offset: u32) -> Option<&mut Function> {
+        let bdf = offset >> 12;
+        self.functions.iter_mut().find(|&&mut (x, _)| x == bdf).map(|&mut (_, ref mut f)| f)
+    }
+}
+
+impl Default for Ecam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Ecam {
+    fn load(&mut self, offset: u32, size: u32) -> Option<u32> {
+        Some(match self.function(offset) {
+            Some(f) => f.load(offset & 0xFFF, size),
+            None => u32::MAX >> (32 - 8 * size),
+        })
+    }
+
+    fn store(&mut self, offset: u32, size: u32, value: u32) -> Option<()> {
+        if let Some(f) = self.function(offset) {
+            f.store(offset & 0xFFF, size, value);
+        }
+        Some(())
+    }
+
+    fn reset(&mut self) {
+        for (_, f) in &mut self.functions {
+            f.reset();
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! One line per step, for `srun --trace`.
+
+use super::{Hart, Retired, Step};
+use crate::disasm::{disassemble_fetched, fetched_hex, REG_NAMES};
+use crate::{u32_to_hex, Labels};
+
+fn location(addr: u32, labels: &Labels) -> String {
+    match labels.symbolize(addr) {
+        Some(s) => format!("{} {}", u32_to_hex(addr), s),
+        None => u32_to_hex(addr),
+    }
+}
+
+fn format_retired(r: &Retired, labels: &Labels) -> String {
+    let disasm = disassemble_fetched(r.insn).unwrap_or_else(|| "???".to_owned());
+    let hex = fetched_hex(r.insn);
+    let mut line = format!("{:<36} {}  {:<28}", location(r.pc, labels), hex, disasm);
+    if let Some((rd, value)) = r.rd_write {
+        line += &format!(" {} <- {}", REG_NAMES[rd as usize], u32_to_hex(value));
+    }
+    if let Some((addr, _, value)) = r.store {
+        line += &format!(" [{}] <- {}", u32_to_hex(addr), u32_to_hex(value));
+    }
+    line.trim_end().to_owned()
+}
+
+/// Describe what a step did. hart is the state after the step. Returns None for Step::Waiting,
+/// since printing a line for every idle step would be a lot of lines.
+pub fn format_step(step: &Step, hart: &Hart, labels: &Labels) -> Option<String> {
+    Some(match *step {
+        Step::Retired(ref r) => format_retired(r, labels),
+        Step::Exception(e) => format!(
+            "{:<36} exception: {} -> {}",
+            location(hart.mepc, labels),
+            e,
+            location(hart.pc, labels),
+        ),
+        Step::Interrupt(code) => format!(
+            "{:<36} interrupt {} -> {}",
+            location(hart.mepc, labels),
+            code,
+            location(hart.pc, labels),
+        ),
+        Step::Waiting => return None,
+    })
+}
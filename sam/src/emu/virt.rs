@@ -0,0 +1,276 @@
+//! The peripherals of QEMU's `virt` machine that our programs use.
+
+use super::{pci, virtio, Bus, Clock, Device, Halt, INT_MSI, INT_MTI};
+use std::io::Write;
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const UART_LEN: u32 = 0x100;
+pub const TEST_BASE: u32 = 0x10_0000;
+pub const TEST_LEN: u32 = 0x1000;
+pub const CLINT_BASE: u32 = 0x200_0000;
+pub const CLINT_LEN: u32 = 0x1_0000;
+
+/// The optional parts of the machine, like QEMU's `-device`.
+#[derive(Default)]
+pub struct Config {
+    /// These go at 00:01.0, 00:02.0, and so on.
+    pub pci_functions: Vec<pci::Function>,
+    /// Like QEMU, these fill the virtio-mmio slots starting from the highest address.
+    pub virtio_devices: Vec<Box<dyn virtio::Backend>>,
+    /// Where mtime comes from. The default follows the host's clock.
+    pub clock: Clock,
+}
+
+/// A bus with RAM, the UART, the SiFive test finisher, the CLINT, PCI config space, and the
+/// virtio-mmio slots at the same addresses as `qemu-system-riscv32 -machine virt`.
+pub fn bus(uart_rx: Receiver<u8>, uart_tx: Box<dyn Write>, config: Config) -> Bus {
+    let mut bus = Bus { clock: config.clock, ..Default::default() };
+    let clint = Clint::new(bus.clock.clone());
+    bus.add_device(UART_BASE, UART_LEN, Box::new(Uart::new(uart_rx, uart_tx)));
+    bus.add_device(TEST_BASE, TEST_LEN, Box::new(Test::default()));
+    bus.add_device(CLINT_BASE, CLINT_LEN, Box::new(clint));
+
+    assert!(config.pci_functions.len() < 32, "too many PCI functions");
+    let mut ecam = pci::Ecam::new();
+    for (i, f) in config.pci_functions.into_iter().enumerate() {
+        ecam.add_function(0, i as u8 + 1, 0, f);
+    }
+    bus.add_device(pci::ECAM_BASE, pci::ECAM_LEN, Box::new(ecam));
+
+    assert!(config.virtio_devices.len() <= virtio::MMIO_COUNT as usize, "too many virtio devices");
+    let mut virtio_devices = config.virtio_devices.into_iter();
+    for i in (0..virtio::MMIO_COUNT).rev() {
+        bus.add_device(
+            virtio::MMIO_BASE + i * virtio::MMIO_LEN,
+            virtio::MMIO_LEN,
+            Box::new(virtio::Mmio::new(virtio_devices.next())),
+        );
+    }
+    bus
+}
+
+const LSR_DR: u8 = 1 << 0; // data ready
+const LSR_THRE: u8 = 1 << 5; // transmitter holding register empty
+const LSR_TEMT: u8 = 1 << 6; // transmitter empty
+const LCR_DLAB: u8 = 1 << 7; // divisor latch access bit
+
+/// An NS16550A with no FIFO and no interrupts. Transmission is instantaneous, so THRE is always
+/// set.
+pub struct Uart {
+    rx: Receiver<u8>,
+    rx_pending: Option<u8>,
+    tx: Box<dyn Write>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+}
+
+impl Uart {
+    /// Bytes received from rx show up in RBR. Bytes written to THR go to tx.
+    pub fn new(rx: Receiver<u8>, tx: Box<dyn Write>) -> Self {
+        Uart { rx, rx_pending: None, tx, ier: 0, lcr: 0, mcr: 0, scr: 0, divisor: 0 }
+    }
+
+    fn poll_rx(&mut self) {
+        if self.rx_pending.is_none() {
+            match self.rx.try_recv() {
+                Ok(b) => self.rx_pending = Some(b),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => (),
+            }
+        }
+    }
+}
+
+impl Device for Uart {
+    fn load(&mut self, offset: u32, _size: u32) -> Option<u32> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            0 if dlab => self.divisor as u8,
+            0 => {
+                self.poll_rx();
+                self.rx_pending.take().unwrap_or(0)
+            },
+            1 if dlab => (self.divisor >> 8) as u8,
+            1 => self.ier,
+            2 => 0x01, // IIR: no interrupt pending
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                self.poll_rx();
+                let dr = if self.rx_pending.is_some() { LSR_DR } else { 0 };
+                dr | LSR_THRE | LSR_TEMT
+            },
+            6 => 0xB0, // MSR: DCD, DSR, and CTS, like QEMU
+            7 => self.scr,
+            _ => 0,
+        };
+        Some(value as u32)
+    }
+
+    fn store(&mut self, offset: u32, _size: u32, value: u32) -> Option<()> {
+        let value = value as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor = (self.divisor & 0xFF00) | value as u16,
+            0 => {
+                // There's nowhere to report a failed write to, and QEMU ignores them too.
+                let _ = self.tx.write_all(&[value]).and_then(|()| self.tx.flush());
+            },
+            1 if dlab => self.divisor = (self.divisor & 0x00FF) | (value as u16) << 8,
+            1 => self.ier = value & 0x0F,
+            2 => (), // FCR
+            3 => self.lcr = value,
+            4 => self.mcr = value & 0x1F,
+            7 => self.scr = value,
+            _ => (),
+        }
+        Some(())
+    }
+
+    fn reset(&mut self) {
+        // Anything already received stays, since it came from outside the machine.
+        (self.ier, self.lcr, self.mcr, self.scr, self.divisor) = (0, 0, 0, 0, 0);
+    }
+}
+
+/// The SiFive test device, which QEMU's `virt` uses to power off or reboot.
+#[derive(Default)]
+pub struct Test {
+    halt: Option<Halt>,
+}
+
+impl Device for Test {
+    fn load(&mut self, _offset: u32, _size: u32) -> Option<u32> {
+        Some(0)
+    }
+
+    fn store(&mut self, offset: u32, size: u32, value: u32) -> Option<()> {
+        if offset == 0 && size == 4 {
+            self.halt = match value & 0xFFFF {
+                0x3333 => Some(Halt::Fail((value >> 16) as u16)),
+                0x5555 => Some(Halt::Pass),
+                0x7777 => Some(Halt::Reset),
+                _ => None,
+            };
+        }
+        Some(())
+    }
+
+    fn halt(&mut self) -> Option<Halt> {
+        self.halt.take()
+    }
+
+    fn reset(&mut self) {
+        self.halt = None;
+    }
+}
+
+const CLINT_MSIP: u32 = 0x0;
+const CLINT_MTIMECMP: u32 = 0x4000;
+const CLINT_MTIME: u32 = 0xBFF8;
+
+/// The CLINT for a single hart.
+pub struct Clint {
+    clock: Clock,
+    pub msip: bool,
+    pub mtimecmp: u64,
+}
+
+impl Clint {
+    pub fn new(clock: Clock) -> Self {
+        // QEMU doesn't raise a timer interrupt until mtimecmp is written, so start it off as far
+        // in the future as possible.
+        Clint { clock, msip: false, mtimecmp: u64::MAX }
+    }
+}
+
+fn load_u64_half(x: u64, offset: u32, size: u32) -> u32 {
+    ((x >> (8 * offset)) as u32) & (u32::MAX >> (32 - 8 * size))
+}
+
+fn store_u64_half(x: u64, offset: u32, size: u32, value: u32) -> u64 {
+    let mask = ((u32::MAX >> (32 - 8 * size)) as u64) << (8 * offset);
+    (x & !mask) | (((value as u64) << (8 * offset)) & mask)
+}
+
+impl Device for Clint {
+    fn load(&mut self, offset: u32, size: u32) -> Option<u32> {
+        Some(match offset {
+            CLINT_MSIP..=0x3 => load_u64_half(self.msip as u64, offset - CLINT_MSIP, size),
+            CLINT_MTIMECMP..=0x4007 => load_u64_half(self.mtimecmp, offset - CLINT_MTIMECMP, size),
+            CLINT_MTIME..=0xBFFF => load_u64_half(self.clock.mtime(), offset - CLINT_MTIME, size),
+            _ => 0,
+        })
+    }
+
+    fn store(&mut self, offset: u32, size: u32, value: u32) -> Option<()> {
+        match offset {
+            CLINT_MSIP..=0x3 => {
+                let msip = store_u64_half(self.msip as u64, offset - CLINT_MSIP, size, value);
+                self.msip = msip & 1 != 0;
+            },
+            CLINT_MTIMECMP..=0x4007 => {
+                self.mtimecmp = store_u64_half(self.mtimecmp, offset - CLINT_MTIMECMP, size, value);
+            },
+            CLINT_MTIME..=0xBFFF => {
+                let mtime = self.clock.mtime();
+                self.clock.set_mtime(store_u64_half(mtime, offset - CLINT_MTIME, size, value));
+            },
+            _ => (),
+        }
+        Some(())
+    }
+
+    fn interrupts(&self) -> u32 {
+        let msip = if self.msip { 1 << INT_MSI } else { 0 };
+        let mtip = if self.clock.mtime() >= self.mtimecmp { 1 << INT_MTI } else { 0 };
+        msip | mtip
+    }
+
+    fn deadline(&self) -> Option<u64> {
+        if self.mtimecmp == u64::MAX { None } else { Some(self.mtimecmp) }
+    }
+
+    /// mtime keeps counting, as it does in QEMU.
+    fn reset(&mut self) {
+        self.msip = false;
+        self.mtimecmp = u64::MAX;
+    }
+}
+
+/// Resetting puts the UART, CLINT, and virtio registers back how they started.
+#[test]
+fn test_reset() {
+    let (_uart_tx, uart_rx) = std::sync::mpsc::channel();
+    let config = Config {
+        virtio_devices: vec![Box::new(virtio::Console::new(Box::new(std::io::sink())))],
+        clock: Clock::manual(),
+        ..Default::default()
+    };
+    let mut bus = bus(uart_rx, Box::new(std::io::sink()), config);
+    let console = virtio::MMIO_BASE + (virtio::MMIO_COUNT - 1) * virtio::MMIO_LEN;
+    let registers = [
+        (UART_BASE + 3, 1, 0x03), // LCR
+        (UART_BASE + 7, 1, 0x5A), // SCR
+        (CLINT_BASE, 4, 1), // msip
+        (CLINT_BASE + 0x4000, 4, 0x10), // mtimecmp
+        (console + 0x070, 4, 0x0F), // Status
+        (pci::ECAM_BASE + 0x3C, 1, 0x0B), // the host bridge's interrupt line
+    ];
+    for &(addr, size, value) in &registers {
+        bus.store(addr, size, value).unwrap();
+        assert_eq!(bus.load(addr, size), Some(value), "{:#x}", addr);
+    }
+    bus.clock.advance(0x20);
+    assert_ne!(bus.interrupts(), 0);
+
+    bus.reset();
+    for &(addr, size, _) in &registers {
+        let value = if addr == CLINT_BASE + 0x4000 { u32::MAX } else { 0 };
+        assert_eq!(bus.load(addr, size), Some(value), "{:#x}", addr);
+    }
+    assert_eq!(bus.interrupts(), 0);
+}
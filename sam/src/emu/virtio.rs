@@ -0,0 +1,166 @@
+//! virtio-mmio transports, like the eight on QEMU's `virt`.
+//!
+//! These use the legacy (version 1) register layout because that's what QEMU does unless you ask
+//! it not to. Virtqueues can be set up but are never processed, so the only way to get data
+//! through a device is its config space.
+
+use super::Device;
+use std::io::Write;
+
+pub const MMIO_BASE: u32 = 0x1000_1000;
+pub const MMIO_LEN: u32 = 0x1000;
+pub const MMIO_COUNT: u32 = 8;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const VENDOR_ID: u32 = 0x554D_4551; // "QEMU"
+const CONFIG: u32 = 0x100;
+const QUEUE_COUNT: usize = 8;
+const QUEUE_NUM_MAX: u32 = 0x400;
+
+/// The device-specific half of a virtio device.
+pub trait Backend {
+    fn device_id(&self) -> u32;
+
+    fn features(&self) -> u64;
+
+    /// offset is relative to the start of the config space.
+    fn load_config(&mut self, offset: u32, size: u32) -> u32;
+
+    fn store_config(&mut self, offset: u32, size: u32, value: u32);
+}
+
+/// One virtio-mmio slot, which might be empty.
+pub struct Mmio {
+    backend: Option<Box<dyn Backend>>,
+    host_features_sel: u32,
+    queue_sel: u32,
+    queue_pfns: [u32; QUEUE_COUNT],
+    status: u32,
+}
+
+impl Mmio {
+    pub fn new(backend: Option<Box<dyn Backend>>) -> Self {
+        Mmio {
+            backend,
+            host_features_sel: 0,
+            queue_sel: 0,
+            queue_pfns: [0; QUEUE_COUNT],
+            status: 0,
+        }
+    }
+
+    fn queue_pfn(&mut self) -> Option<&mut u32> {
+        self.queue_pfns.get_mut(self.queue_sel as usize)
+    }
+}
+
+impl Device for Mmio {
+    fn load(&mut self, offset: u32, size: u32) -> Option<u32> {
+        let backend = match self.backend {
+            Some(ref mut backend) => backend,
+            // An empty slot still identifies itself, but with device ID 0.
+            None => return Some(match offset {
+                0x000 => MAGIC,
+                0x004 => 1,
+                0x00C => VENDOR_ID,
+                _ => 0,
+            }),
+        };
+        if offset >= CONFIG {
+            return Some(backend.load_config(offset - CONFIG, size));
+        }
+        Some(match offset {
+            0x000 => MAGIC,
+            0x004 => 1, // version
+            0x008 => backend.device_id(),
+            0x00C => VENDOR_ID,
+            0x010 => match self.host_features_sel {
+                0 => backend.features() as u32,
+                1 => (backend.features() >> 32) as u32,
+                _ => 0,
+            },
+            0x034 => self.queue_pfn().map_or(0, |_| QUEUE_NUM_MAX),
+            0x040 => self.queue_pfn().map_or(0, |&mut pfn| pfn),
+            0x060 => 0, // InterruptStatus
+            0x070 => self.status,
+            _ => 0,
+        })
+    }
+
+    fn store(&mut self, offset: u32, size: u32, value: u32) -> Option<()> {
+        if self.backend.is_none() {
+            return Some(());
+        }
+        if offset >= CONFIG {
+            self.backend.as_mut().unwrap().store_config(offset - CONFIG, size, value);
+            return Some(());
+        }
+        match offset {
+            0x014 => self.host_features_sel = value,
+            // GuestFeatures, GuestFeaturesSel, GuestPageSize, QueueNum, and QueueAlign are
+            // write-only and nothing here needs them.
+            0x020 | 0x024 | 0x028 | 0x038 | 0x03C => (),
+            0x030 => self.queue_sel = value,
+            0x040 => if let Some(pfn) = self.queue_pfn() { *pfn = value },
+            0x050 => (), // QueueNotify: see the module docs
+            0x064 => (), // InterruptACK
+            0x070 => {
+                if value == 0 {
+                    self.reset();
+                } else {
+                    self.status = value;
+                }
+            },
+            _ => (),
+        }
+        Some(())
+    }
+
+    fn reset(&mut self) {
+        *self = Mmio::new(self.backend.take());
+    }
+}
+
+const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 1 << 2;
+
+/// A virtio console whose emergency write register sends bytes to out.
+pub struct Console {
+    out: Box<dyn Write>,
+}
+
+impl Console {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Console { out }
+    }
+}
+
+impl Backend for Console {
+    fn device_id(&self) -> u32 {
+        3
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_CONSOLE_F_EMERG_WRITE
+    }
+
+    fn load_config(&mut self, offset: u32, size: u32) -> u32 {
+        let config: [u8; 12] = [
+            80, 0, // cols
+            25, 0, // rows
+            1, 0, 0, 0, // max_nr_ports
+            0, 0, 0, 0, // emerg_wr
+        ];
+        let mut buf = [0; 4];
+        for (i, b) in buf[..size as usize].iter_mut().enumerate() {
+            *b = config.get(offset as usize + i).copied().unwrap_or(0);
+        }
+        u32::from_le_bytes(buf)
+    }
+
+    fn store_config(&mut self, offset: u32, _size: u32, value: u32) {
+        if offset == 0x8 { // emerg_wr
+            // Like the UART, there's nowhere to report a failed write to.
+            let _ = self.out.write_all(&[value as u8]).and_then(|()| self.out.flush());
+        }
+    }
+}
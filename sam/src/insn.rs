@@ -0,0 +1,72 @@
+//! Field extraction for 32-bit RISC-V instruction words.
+//!
+//! The immediate decoders undo the bit scrambling that `assemble_line2` and `Relocation::apply` do
+//! and return the immediate sign-extended to 32 bits.
+
+pub fn opcode(insn: u32) -> u32 {
+    insn & 0x7F
+}
+
+pub fn rd(insn: u32) -> u32 {
+    (insn >> 7) & 0x1F
+}
+
+pub fn funct3(insn: u32) -> u32 {
+    (insn >> 12) & 0x7
+}
+
+pub fn rs1(insn: u32) -> u32 {
+    (insn >> 15) & 0x1F
+}
+
+pub fn rs2(insn: u32) -> u32 {
+    (insn >> 20) & 0x1F
+}
+
+pub fn funct7(insn: u32) -> u32 {
+    insn >> 25
+}
+
+pub fn csr(insn: u32) -> u32 {
+    insn >> 20
+}
+
+pub fn imm_i(insn: u32) -> u32 {
+    (insn as i32 >> 20) as u32
+}
+
+pub fn imm_s(insn: u32) -> u32 {
+    ((insn as i32 >> 25) << 5) as u32 | ((insn >> 7) & 0x1F)
+}
+
+pub fn imm_b(insn: u32) -> u32 {
+    ((insn as i32 >> 31) << 12) as u32
+        | ((insn << 4) & 0x800)
+        | ((insn >> 20) & 0x7E0)
+        | ((insn >> 7) & 0x1E)
+}
+
+pub fn imm_u(insn: u32) -> u32 {
+    insn & 0xFFFF_F000
+}
+
+pub fn imm_j(insn: u32) -> u32 {
+    ((insn as i32 >> 31) << 20) as u32
+        | (insn & 0xF_F000)
+        | ((insn >> 9) & 0x800)
+        | ((insn >> 20) & 0x7FE)
+}
+
+#[test]
+fn test_imm() {
+    // beq x0 x0 -#8
+    assert_eq!(imm_b(0xFE00_0CE3), 0xFFFF_FFF8);
+    // jal x0 #7FE
+    assert_eq!(imm_j(0x7FE0_006F), 0x7FE);
+    // jal x0 -#4
+    assert_eq!(imm_j(0xFFDF_F06F), 0xFFFF_FFFC);
+    // sw ra sp -#8
+    assert_eq!(imm_s(0xFE11_2C23), 0xFFFF_FFF8);
+    // addi sp sp -#40
+    assert_eq!(imm_i(0xFC01_0113), 0xFFFF_FFC0);
+}
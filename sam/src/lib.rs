@@ -2,20 +2,35 @@ use std::collections::HashMap;
 use std::fmt::{self, Display};
 use std::io::{self, prelude::*, SeekFrom};
 
+pub mod archive;
+pub mod disasm;
+pub mod elf;
+pub mod emu;
+pub mod insn;
+pub mod link;
+pub mod records;
+pub mod rvc;
+
+// dc867b72-87f7-47da-a770-752af3299a3c
+pub const MAGIC: [u8; 16] = [
+    0xdc, 0x86, 0x7b, 0x72, 0x87, 0xf7, 0x47, 0xda,
+    0xa7, 0x70, 0x75, 0x2a, 0xf3, 0x29, 0x9a, 0x3c,
+];
+
 // Having this return ! makes the type checker say e.g. "expected `!`, found `usize`".
 pub fn ouch<E: Display, X>(e: E) -> X {
     panic!("Error: {}", e);
 }
 
 pub fn from_hex(s: &str, width: u32) -> Result<u32, String> {
-    assert!(1 <= width && width <= 32);
+    assert!((1..=32).contains(&width));
     let is_neg = s.starts_with('-');
     let s = if is_neg { &s["-".len()..] } else { s };
     if !s.starts_with('#') {
         return Err("number doesn't start with '#'".to_string());
     }
     let s = &s["#".len()..];
-    if s.len() == 0 {
+    if s.is_empty() {
         return Err("number is empty".to_string());
     }
     let mut n: u32 = 0;
@@ -61,7 +76,7 @@ pub fn from_hex(s: &str, width: u32) -> Result<u32, String> {
         if n >= 1 << (width - 1) {
             return Err("number is too wide to negate".to_string());
         }
-        n = (!n).wrapping_add(1) & ((1 << width) - 1);
+        n = n.wrapping_neg() & (u32::MAX >> (32 - width));
     }
     Ok(n)
 }
@@ -87,6 +102,7 @@ fn test_from_hex() {
     assert!(from_hex("#F000", 12).is_err());
 
     assert_eq!(from_hex("#1234abcd", 31), Ok(0x1234_ABCD));
+    assert_eq!(from_hex("-#4", 32), Ok(0xFFFF_FFFC));
     assert!(from_hex("#8234abcd", 31).is_err());
 
     assert_eq!(from_hex("#1234abcd", 32), Ok(0x1234_ABCD));
@@ -137,13 +153,11 @@ pub fn read_u32_at<F: Read + Seek>(mut f: F, pos: u64) -> io::Result<u32> {
 
 pub fn read_len_prefixed_str(mut r: impl Read) -> io::Result<String> {
     let s_len = read_u32(&mut r)?;
-    let mut buf = Vec::with_capacity(s_len as usize);
-    buf.resize(s_len as usize, 0);
+    let mut buf = vec![0; s_len as usize];
     r.read_exact(&mut buf)?;
     let s = String::from_utf8(buf).unwrap_or_else(ouch);
     let padding_len = s_len.wrapping_neg() as usize & 0b11;
-    let mut buf = Vec::with_capacity(padding_len);
-    buf.resize(padding_len, 0);
+    let mut buf = vec![0; padding_len];
     r.read_exact(&mut buf)?;
     Ok(s)
 }
@@ -186,6 +200,65 @@ pub fn parse_reg(s: &str) -> Result<u32, String> {
     })
 }
 
+/// Standard names of the CSRs an RV32 hart with only M-mode might have. The assembler takes these
+/// anywhere it takes a CSR number, whether or not the emulator has the CSR.
+pub const CSR_NAMES: [(&str, u32); 37] = [
+    ("cycle", 0xC00),
+    ("time", 0xC01),
+    ("instret", 0xC02),
+    ("cycleh", 0xC80),
+    ("timeh", 0xC81),
+    ("instreth", 0xC82),
+    ("mvendorid", 0xF11),
+    ("marchid", 0xF12),
+    ("mimpid", 0xF13),
+    ("mhartid", 0xF14),
+    ("mconfigptr", 0xF15),
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("medeleg", 0x302),
+    ("mideleg", 0x303),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mcounteren", 0x306),
+    ("menvcfg", 0x30A),
+    ("mstatush", 0x310),
+    ("menvcfgh", 0x31A),
+    ("mcountinhibit", 0x320),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mtval", 0x343),
+    ("mip", 0x344),
+    ("mtinst", 0x34A),
+    ("mtval2", 0x34B),
+    ("pmpcfg0", 0x3A0),
+    ("pmpcfg1", 0x3A1),
+    ("pmpcfg2", 0x3A2),
+    ("pmpcfg3", 0x3A3),
+    ("mcycle", 0xB00),
+    ("minstret", 0xB02),
+    ("mcycleh", 0xB80),
+    ("minstreth", 0xB82),
+];
+
+pub fn parse_csr(s: &str) -> Result<u32, String> {
+    CSR_NAMES.iter()
+        .find(|&&(name, _)| name == s)
+        .map(|&(_, csr)| csr)
+        .ok_or_else(|| format!("unknown CSR '{}'", s))
+}
+
+#[test]
+fn test_parse_csr() {
+    assert_eq!(parse_csr("mscratch"), Ok(0x340));
+    assert_eq!(parse_csr("mscrach"), Err("unknown CSR 'mscrach'".to_owned()));
+    // Everything the emulator has should be assemblable by name.
+    for &(name, csr) in emu::CSRS.iter() {
+        assert_eq!(parse_csr(name), Ok(csr), "{}", name);
+    }
+}
+
 #[derive(Debug)]
 pub enum DeserializationError {
     Io(io::Error),
@@ -193,6 +266,10 @@ pub enum DeserializationError {
     ReservedField(String),
     PrematureEnd,
     DuplicateItem(String),
+    BadMagic,
+    BadArchiveMagic,
+    BadElfMagic,
+    Unsupported(String),
 }
 
 impl From<io::Error> for DeserializationError {
@@ -209,6 +286,10 @@ impl Display for DeserializationError {
             Self::ReservedField(ref s) => write!(f, "reserved field; {}", s),
             Self::PrematureEnd => write!(f, "premature end"),
             Self::DuplicateItem(ref s) => write!(f, "duplicate item; {}", s),
+            Self::BadMagic => write!(f, "not a sam object (bad magic)"),
+            Self::BadArchiveMagic => write!(f, "not a sam archive (bad magic)"),
+            Self::BadElfMagic => write!(f, "not an ELF file (bad magic)"),
+            Self::Unsupported(ref s) => write!(f, "unsupported; {}", s),
         }
     }
 }
@@ -255,7 +336,89 @@ pub enum RelocationValue {
     RelCodeJType,
     RelUType,
     RelIType,
-    // TODO: AbsUType and AbsIType
+    RelCodeCbType, // c.beqz and c.bnez
+    RelCodeCjType, // c.j and c.jal
+    Abs32, // a data word holding the label's address, from .word
+    AbsUType, // lui with %hi(label)
+    AbsIType, // %lo(label), the rest of the address after AbsUType
+    AbsSType, // %lo(label) in a store
+}
+
+impl RelocationValue {
+    /// How many bytes the instruction being relocated takes up.
+    pub fn insn_size(self) -> u32 {
+        match self {
+            RelocationValue::RelCodeCbType | RelocationValue::RelCodeCjType => 2,
+            _ => 4,
+        }
+    }
+
+    /// Splits a branch or jump into the instruction with its immediate cleared and the addend
+    /// that the immediate held, e.g. 8 for `beq a0 a1 loop+#8`. Other kinds keep no addend there,
+    /// so they come back as they are with an addend of 0.
+    pub fn split_addend(self, insn: u32) -> (u32, u32) {
+        match self {
+            RelocationValue::RelCodeBType => (insn & !0xFE00_0F80, insn::imm_b(insn)),
+            RelocationValue::RelCodeJType => (insn & 0xFFF, insn::imm_j(insn)),
+            RelocationValue::RelCodeCbType | RelocationValue::RelCodeCjType => {
+                let (width, layout) = match self {
+                    RelocationValue::RelCodeCbType => (9, &rvc::CB),
+                    _ => (12, &rvc::CJ),
+                };
+                let field = rvc::put_imm(u32::MAX, layout);
+                (insn & !field, rvc::sext(rvc::imm(insn, layout), width))
+            },
+            _ => (insn, 0),
+        }
+    }
+
+    /// Puts an addend in a branch or jump's cleared immediate, the other way from split_addend, or
+    /// returns None if it doesn't fit or is odd, since there's no low bit to put it in. Other kinds
+    /// only take 0.
+    pub fn join_addend(self, insn: u32, addend: u32) -> Option<u32> {
+        let width = match self {
+            RelocationValue::RelCodeBType => 13,
+            RelocationValue::RelCodeJType => 21,
+            RelocationValue::RelCodeCbType => 9,
+            RelocationValue::RelCodeCjType => 12,
+            _ => return (addend == 0).then_some(insn),
+        };
+        if rvc::sext(addend, width) != addend || addend & 1 != 0 {
+            return None;
+        }
+        let mut insn = insn;
+        match self {
+            RelocationValue::RelCodeBType => {
+                insn += addend << (31-12) >> (31-12+12) << 31;
+                insn += addend << (31-10) >> (31-10+5) << 25;
+                insn += addend << (31-4) >> (31-4+1) << 8;
+                insn += addend << (31-11) >> (31-11+11) << 7;
+            },
+            RelocationValue::RelCodeJType => {
+                insn += addend << (31-20) >> (31-20+20) << 31;
+                insn += addend << (31-10) >> (31-10+1) << 21;
+                insn += addend << (31-11) >> (31-11+11) << 20;
+                insn += addend << (31-19) >> (31-19+12) << 12;
+            },
+            RelocationValue::RelCodeCbType => insn += rvc::put_imm(addend, &rvc::CB),
+            _ => insn += rvc::put_imm(addend, &rvc::CJ),
+        }
+        Some(insn)
+    }
+
+    /// Whether the relocation depends on where the object loads, not just on distances within
+    /// it. These stay in the relocation table once they're applied, so the linker can apply them
+    /// again when it moves the object, and applying one overwrites what's there instead of adding
+    /// to it.
+    pub fn is_absolute(self) -> bool {
+        matches!(
+            self,
+            RelocationValue::Abs32
+                | RelocationValue::AbsUType
+                | RelocationValue::AbsIType
+                | RelocationValue::AbsSType,
+        )
+    }
 }
 
 impl Relocation {
@@ -272,6 +435,12 @@ impl Relocation {
             RelocationValue::RelCodeJType => 2,
             RelocationValue::RelUType => 3,
             RelocationValue::RelIType => 4,
+            RelocationValue::RelCodeCbType => 5,
+            RelocationValue::RelCodeCjType => 6,
+            RelocationValue::Abs32 => 7,
+            RelocationValue::AbsUType => 8,
+            RelocationValue::AbsIType => 9,
+            RelocationValue::AbsSType => 10,
         };
         writer.write_all(&kind.to_le_bytes())?;
         writer.write_all(&[0; 6])?; // reserved
@@ -302,6 +471,30 @@ impl Relocation {
                 read_u16(&mut reader)?;
                 RelocationValue::RelIType
             },
+            5 => {
+                read_u16(&mut reader)?;
+                RelocationValue::RelCodeCbType
+            },
+            6 => {
+                read_u16(&mut reader)?;
+                RelocationValue::RelCodeCjType
+            },
+            7 => {
+                read_u16(&mut reader)?;
+                RelocationValue::Abs32
+            },
+            8 => {
+                read_u16(&mut reader)?;
+                RelocationValue::AbsUType
+            },
+            9 => {
+                read_u16(&mut reader)?;
+                RelocationValue::AbsIType
+            },
+            10 => {
+                read_u16(&mut reader)?;
+                RelocationValue::AbsSType
+            },
             n => return Err(DeserializationError::ReservedValue(
                 format!("can't understand relocation value kind {}", n)
             )),
@@ -310,63 +503,48 @@ impl Relocation {
         Ok(Relocation { offset, symbol_index, value })
     }
 
-    pub fn apply(&self, insn: u32, symbol_table: &SymbolTable) -> Result<u32, String> {
+    /// Patches insn, the instruction or data word at the relocation's offset, for an object that
+    /// loads at load_address.
+    pub fn apply(&self, insn: u32, symbol_table: &SymbolTable, load_address: u32)
+        -> Result<u32, String>
+    {
         let symbol = self.symbol(symbol_table);
-        assert!(!symbol.is_external());
+        assert!(symbol.is_defined(), "can't apply a relocation against an undefined symbol");
+        // Metadata counts as defined, but there's no address to put anywhere.
+        if let SymbolValue::Metadata { .. } = symbol.value {
+            return Err(format!("symbol {} is metadata, not a label", self.symbol_index));
+        }
         match self.value {
-            RelocationValue::UnusedEntry => panic!("can't apply unused relocation"),
-            RelocationValue::RelCodeBType => {
+            RelocationValue::UnusedEntry => Err("can't apply an unused entry".to_owned()),
+            RelocationValue::RelCodeBType
+            | RelocationValue::RelCodeJType
+            | RelocationValue::RelCodeCbType
+            | RelocationValue::RelCodeCjType => {
                 let symbol_offset = match symbol.value {
                     SymbolValue::Code { offset, .. } => offset,
-                    _ => panic!("symbol {} is not a code symbol", self.symbol_index),
-                };
-                let imm13 = symbol_offset.unwrap().wrapping_sub(self.offset);
-                if imm13 >= (1<<13) && imm13.wrapping_neg() >= (1<<13) {
-                    return Err(format!("branch target is too far away ({})", u32_to_hex(imm13)));
-                }
-                let mut insn = insn;
-                // FIXME: Permit initial immediate to be nonzero. (Currently it wouldn't overflow
-                // correctly.)
-                insn += imm13 << (31-12) >> (31-12+12) << 31;
-                insn += imm13 << (31-10) >> (31-10+5) << 25;
-                insn += imm13 << (31-4) >> (31-4+1) << 8;
-                insn += imm13 << (31-11) >> (31-11+11) << 7;
-                Ok(insn)
-            },
-            RelocationValue::RelCodeJType => {
-                let symbol_offset = match symbol.value {
-                    SymbolValue::Code { offset, .. } => offset,
-                    _ => panic!("symbol {} is not a code symbol", self.symbol_index),
+                    SymbolValue::Data { .. } => {
+                        return Err("branch target is a data label".to_owned());
+                    },
+                    SymbolValue::Metadata { .. } => unreachable!(),
                 };
-                let imm21 = symbol_offset.unwrap().wrapping_sub(self.offset);
-                if imm21 >= (1<<21) && imm21.wrapping_neg() >= (1<<21) {
-                    return Err(format!("branch target is too far away ({})", u32_to_hex(imm21)));
+                let (insn, addend) = self.value.split_addend(insn);
+                let imm = symbol_offset.unwrap().wrapping_sub(self.offset).wrapping_add(addend);
+                if imm & 1 != 0 {
+                    return Err(format!("branch target isn't 2-byte aligned ({})", u32_to_hex(imm)));
                 }
-                let mut insn = insn;
-                // FIXME: Permit initial immediate to be nonzero. (Currently
-                // it wouldn't overflow correctly.)
-                insn += imm21 << (31-20) >> (31-20+20) << 31;
-                insn += imm21 << (31-10) >> (31-10+1) << 21;
-                insn += imm21 << (31-11) >> (31-11+11) << 20;
-                insn += imm21 << (31-19) >> (31-19+12) << 12;
-                Ok(insn)
+                self.value.join_addend(insn, imm)
+                    .ok_or_else(|| format!("branch target is too far away ({})", u32_to_hex(imm)))
             },
             RelocationValue::RelUType => {
                 let symbol_offset = match symbol.value {
                     SymbolValue::Code { offset, .. }
                     | SymbolValue::Data { offset, .. } => offset,
-                    _ => panic!("symbol {} is not a code or data symbol", self.symbol_index),
+                    SymbolValue::Metadata { .. } => unreachable!(),
                 };
                 // dis = displacement (what?)
                 let dis32 = symbol_offset.unwrap().wrapping_sub(self.offset);
                 let dis20 = (dis32 >> 12).wrapping_add((dis32 >> 11) & 1) & 0xF_FFFF;
                 let imm20 = (insn >> 12).wrapping_add(dis20) & 0xF_FFFF;
-                println!(
-                    "dis32 = {}, dis20 = {}, imm20 = {}",
-                    u32_to_hex(dis32),
-                    u32_to_hex(dis20),
-                    u32_to_hex(imm20),
-                );
                 let insn = (insn & 0xFFF) + (imm20 << 12);
                 Ok(insn)
             },
@@ -374,22 +552,81 @@ impl Relocation {
                 let symbol_offset = match symbol.value {
                     SymbolValue::Code { offset, .. }
                     | SymbolValue::Data { offset, .. } => offset,
-                    _ => panic!("symbol {} is not a code or data symbol", self.symbol_index),
+                    SymbolValue::Metadata { .. } => unreachable!(),
                 };
                 let dis12 = symbol_offset.unwrap().wrapping_sub(self.offset) & 0xFFF;
                 let imm12 = (insn >> 20).wrapping_add(dis12) & 0xFFF;
-                println!(
-                    "dis12 = {}, imm12 = {}",
-                    u32_to_hex(dis12),
-                    u32_to_hex(imm12),
-                );
                 let insn = (insn & 0xF_FFFF) + (imm12 << 20);
                 Ok(insn)
             },
+            RelocationValue::Abs32
+            | RelocationValue::AbsUType
+            | RelocationValue::AbsIType
+            | RelocationValue::AbsSType => {
+                let symbol_offset = match symbol.value {
+                    SymbolValue::Code { offset, .. }
+                    | SymbolValue::Data { offset, .. } => offset,
+                    SymbolValue::Metadata { .. } => unreachable!(),
+                };
+                let addr = load_address.wrapping_add(symbol_offset.unwrap());
+                Ok(match self.value {
+                    RelocationValue::Abs32 => addr,
+                    // Rounded, because the low 12 bits get sign-extended.
+                    RelocationValue::AbsUType => {
+                        (insn & 0xFFF) | (addr.wrapping_add(0x800) & 0xFFFF_F000)
+                    },
+                    RelocationValue::AbsIType => (insn & 0xF_FFFF) | addr << 20,
+                    _ => (insn & 0x01FF_F07F) | (addr & 0xFE0) << 20 | (addr & 0x1F) << 7,
+                })
+            },
         }
     }
 }
 
+#[test]
+fn test_absolute_relocations() {
+    let mut symbols: SymbolTable = Default::default();
+    let (external, type_index) = (false, 0);
+    symbols.insert(1, SymbolValue::Data { external, type_index, offset: Some(0x800) });
+    let apply = |value, insn| {
+        Relocation { offset: 0, symbol_index: 0, value }.apply(insn, &symbols, 0x8000_0000).unwrap()
+    };
+    // The low half is negative, so the high half rounds up. Applying again changes nothing.
+    let lui = apply(RelocationValue::AbsUType, 0x0000_0537); // lui a0 #0
+    assert_eq!(lui, 0x8000_1537);
+    assert_eq!(apply(RelocationValue::AbsUType, lui), lui);
+    let addi = apply(RelocationValue::AbsIType, 0x0005_0513); // addi a0 a0 #0
+    assert_eq!(addi, 0x8005_0513);
+    assert_eq!(apply(RelocationValue::AbsIType, addi), addi);
+    let sw = apply(RelocationValue::AbsSType, 0x00A5_2023); // sw a0 a0 #0
+    assert_eq!(sw, 0x80A5_2023);
+    assert_eq!(apply(RelocationValue::AbsSType, sw), sw);
+    assert_eq!(apply(RelocationValue::Abs32, 0xFFFF_FFFF), 0x8000_0800);
+}
+
+#[test]
+fn test_branch_addends() {
+    let mut symbols: SymbolTable = Default::default();
+    let (external, type_index) = (false, 0);
+    symbols.insert(1, SymbolValue::Code { external, type_index, offset: Some(0) });
+    let apply = |value, insn| {
+        Relocation { offset: 0x10, symbol_index: 0, value }.apply(insn, &symbols, 0)
+    };
+    // beq a0 a1 label+#8, which goes back 8.
+    assert_eq!(apply(RelocationValue::RelCodeBType, 0x00B5_0463), Ok(0xFEB5_0CE3));
+    // beq a0 a1 label-#FF8, which is 8 bytes too far back.
+    assert!(apply(RelocationValue::RelCodeBType, 0x80B5_0463).is_err());
+    // jal zero label+#10, which is where it is.
+    assert_eq!(apply(RelocationValue::RelCodeJType, 0x0100_006F), Ok(0x0000_006F));
+    // c.j label-#2
+    assert_eq!(apply(RelocationValue::RelCodeCjType, 0xBFFD), Ok(0xB7FD));
+    assert_eq!(RelocationValue::RelCodeCjType.split_addend(0xBFFD), (0xA001, (-2i32) as u32));
+    // jal zero label from an odd offset, e.g. after a .byte.
+    let odd = Relocation { offset: 0x11, symbol_index: 0, value: RelocationValue::RelCodeJType };
+    assert!(odd.apply(0x0000_006F, &symbols, 0).is_err());
+    assert_eq!(RelocationValue::RelCodeJType.join_addend(0x0000_006F, 1), None);
+}
+
 #[derive(Default)]
 pub struct SymbolTable {
     pub symbols: Vec<Symbol>,
@@ -397,7 +634,7 @@ pub struct SymbolTable {
 }
 
 impl SymbolTable {
-    pub fn get<'a>(&'a self, name_index: u32) -> Option<&'a Symbol> {
+    pub fn get(&self, name_index: u32) -> Option<&Symbol> {
         self.name_index_to_symbol_index.get(&name_index)
             .map(|&symbol_index| &self.symbols[symbol_index as usize])
     }
@@ -508,14 +745,14 @@ impl Symbol {
             },
             SymbolValue::Code { external, type_index, offset } => {
                 writer.write_all(&[1])?;
-                let flags = ((self.is_defined() as u8) << 1) + ((external as u8) << 0);
+                let flags = ((self.is_defined() as u8) << 1) + (external as u8);
                 writer.write_all(&[flags])?;
                 writer.write_all(&string_table.strings[type_index as usize].0.to_le_bytes())?;
                 writer.write_all(&offset.unwrap_or(0).to_le_bytes())?;
             },
             SymbolValue::Data { external, type_index, offset } => {
                 writer.write_all(&[2])?;
-                let flags = ((self.is_defined() as u8) << 1) + ((external as u8) << 0);
+                let flags = ((self.is_defined() as u8) << 1) + (external as u8);
                 writer.write_all(&[flags])?;
                 writer.write_all(&string_table.strings[type_index as usize].0.to_le_bytes())?;
                 writer.write_all(&offset.unwrap_or(0).to_le_bytes())?;
@@ -614,21 +851,19 @@ impl StringTable {
 
     /// Returns index. s may be in the table already. In this case, value_to_index is not updated
     /// and continues to point to the first occurrence.
-    fn insert(&mut self, s: String) -> u32 {
+    pub fn insert(&mut self, s: String) -> u32 {
         let offset = self.len;
         let index = self.strings.len() as u32;
         let padding_len = s.len().wrapping_neg() & 0b11;
         self.len += 4 + s.len() as u32 + padding_len as u32;
         self.strings.push((offset, s.clone()));
-        if !self.value_to_index.contains_key(&s) {
-            self.value_to_index.insert(s, index);
-        }
+        self.value_to_index.entry(s).or_insert(index);
         self.offset_to_index.insert(offset, index);
         index
     }
 
     pub fn serialize(&self, mut writer: impl Write) -> io::Result<()> {
-        for &(_, ref s) in &self.strings {
+        for (_, s) in &self.strings {
             write_len_prefixed_str(&mut writer, s)?;
         }
         Ok(())
@@ -647,18 +882,251 @@ impl StringTable {
             if len < s_len + padding_len {
                 return Err(DeserializationError::PrematureEnd);
             }
-            let mut buf = Vec::with_capacity(s_len as usize);
-            buf.resize(s_len as usize, 0);
+            let mut buf = vec![0; s_len as usize];
             reader.read_exact(&mut buf)?;
             len -= s_len;
             let s = String::from_utf8(buf).unwrap_or_else(ouch);
             table.insert(s);
 
-            let mut buf = Vec::with_capacity(padding_len as usize);
-            buf.resize(padding_len as usize, 0);
+            let mut buf = vec![0; padding_len as usize];
             reader.read_exact(&mut buf)?;
             len -= padding_len;
         }
         Ok(table)
     }
 }
+
+/// Bits of an object's RISC-V extensions field. Like misa, bit N stands for the Nth letter of the
+/// alphabet. RV32I itself isn't recorded, so objects from before the field existed read as plain
+/// RV32I.
+pub const EXT_A: u32 = ext(b'A');
+pub const EXT_C: u32 = ext(b'C');
+pub const EXT_M: u32 = ext(b'M');
+
+const fn ext(letter: u8) -> u32 {
+    1 << (letter - b'A')
+}
+
+/// The letters of the extensions in a bitmap, e.g. "MA" for M and A.
+pub fn extension_letters(extensions: u32) -> String {
+    (0..26)
+        .filter(|&bit| extensions & (1 << bit) != 0)
+        .map(|bit| (b'A' + bit as u8) as char)
+        .collect()
+}
+
+#[test]
+fn test_extension_letters() {
+    assert_eq!(extension_letters(0), "");
+    assert_eq!(extension_letters(EXT_M), "M");
+    assert_eq!(extension_letters(EXT_A | EXT_C | EXT_M), "ACM");
+}
+
+/// A whole sam object file, read into memory.
+pub struct Object {
+    pub load_address: u32,
+    pub extensions: u32,
+    pub code_and_data: Vec<u8>,
+    pub strings: StringTable,
+    pub symbols: SymbolTable,
+    pub relocations: RelocationTable,
+}
+
+impl Object {
+    pub fn deserialize<R: Read + Seek>(mut reader: R) -> Result<Self, DeserializationError> {
+        let mut magic = [0; 16];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(DeserializationError::BadMagic);
+        }
+        match read_u8(&mut reader)? {
+            0 => (),
+            n => return Err(DeserializationError::ReservedValue(
+                format!("can't understand version {}", n)
+            )),
+        }
+
+        let load_address_offset = read_u32_at(&mut reader, 0x14)?;
+        let code_and_data_offset = read_u32(&mut reader)?;
+        let string_table_offset = read_u32(&mut reader)?;
+        let symbol_table_offset = read_u32(&mut reader)?;
+        let relocation_table_offset = read_u32(&mut reader)?;
+        let extensions = match read_u16(&mut reader)? {
+            1 => { // risc-v
+                read_u16(&mut reader)?;
+                let extensions = read_u32(&mut reader)?;
+                if extensions >> 26 != 0 {
+                    return Err(DeserializationError::ReservedField(
+                        "risc-v extensions above Z are reserved but nonzero".to_owned()
+                    ));
+                }
+                extensions
+            },
+            n => return Err(DeserializationError::ReservedValue(
+                format!("can't understand arch {}", n)
+            )),
+        };
+        let file_end_offset = reader.seek(SeekFrom::End(0))? as u32;
+
+        let load_address = read_u32_at(&mut reader, load_address_offset as u64)?;
+
+        if string_table_offset < code_and_data_offset {
+            return Err(DeserializationError::PrematureEnd);
+        }
+        reader.seek(SeekFrom::Start(code_and_data_offset as u64))?;
+        let mut code_and_data = vec![0; (string_table_offset - code_and_data_offset) as usize];
+        reader.read_exact(&mut code_and_data)?;
+
+        let strings = StringTable::deserialize(
+            &mut reader,
+            symbol_table_offset.checked_sub(string_table_offset)
+                .ok_or(DeserializationError::PrematureEnd)?,
+        )?;
+        reader.seek(SeekFrom::Start(symbol_table_offset as u64))?;
+        let symbols = SymbolTable::deserialize(
+            &mut reader,
+            relocation_table_offset.checked_sub(symbol_table_offset)
+                .ok_or(DeserializationError::PrematureEnd)?,
+            &strings,
+        )?;
+        reader.seek(SeekFrom::Start(relocation_table_offset as u64))?;
+        let relocations = RelocationTable::deserialize(
+            &mut reader,
+            file_end_offset.checked_sub(relocation_table_offset)
+                .ok_or(DeserializationError::PrematureEnd)?,
+        )?;
+
+        Ok(Object { load_address, extensions, code_and_data, strings, symbols, relocations })
+    }
+
+    /// Writes the object the same way the assembler does, except that unused relocation entries
+    /// are left out.
+    pub fn serialize(&self, mut writer: impl Write) -> io::Result<()> {
+        let relocations: Vec<_> = self.relocations.relocations.iter()
+            .filter(|reloc| !matches!(reloc.value, RelocationValue::UnusedEntry))
+            .collect();
+        // The string table has to be word-aligned.
+        let padding_len = self.code_and_data.len().wrapping_neg() & 0b11;
+        let load_address_offset: u32 = 0x30;
+        let code_and_data_offset = load_address_offset + 4;
+        let string_table_offset =
+            code_and_data_offset + (self.code_and_data.len() + padding_len) as u32;
+        let symbol_table_offset = string_table_offset + self.strings.len;
+        let relocation_table_offset =
+            symbol_table_offset + 0x10 * self.symbols.symbols.len() as u32;
+
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[0; 4])?; // version 0, then reserved
+        for offset in [
+            load_address_offset,
+            code_and_data_offset,
+            string_table_offset,
+            symbol_table_offset,
+            relocation_table_offset,
+        ] {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        writer.write_all(&[0x01, 0x00, 0x00, 0x00])?; // arch = risc-v, then reserved
+        writer.write_all(&self.extensions.to_le_bytes())?;
+        writer.write_all(&self.load_address.to_le_bytes())?;
+        writer.write_all(&self.code_and_data)?;
+        writer.write_all(&vec![0; padding_len])?;
+        self.strings.serialize(&mut writer)?;
+        self.symbols.serialize(&mut writer, &self.strings)?;
+        for reloc in relocations {
+            reloc.serialize(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// The names of the labels that relocations still refer to but no symbol defines, each once,
+    /// in sorted order. An object can't run until these are linked in from other objects.
+    pub fn unresolved(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.relocations.relocations.iter()
+            .filter(|reloc| !matches!(reloc.value, RelocationValue::UnusedEntry))
+            .map(|reloc| reloc.symbol(&self.symbols))
+            .filter(|sym| !sym.is_defined())
+            .map(|sym| sym.name(&self.strings))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[test]
+fn test_object_extensions() {
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let object = Object {
+        load_address: 0x8000_0000,
+        extensions: EXT_C | EXT_M,
+        code_and_data: vec![0x01, 0x00], // c.nop
+        strings,
+        symbols: Default::default(),
+        relocations: Default::default(),
+    };
+    let mut buf = Vec::new();
+    object.serialize(&mut buf).unwrap();
+    assert_eq!(buf[0x2C..0x30], (EXT_C | EXT_M).to_le_bytes());
+    let read = Object::deserialize(io::Cursor::new(buf)).unwrap();
+    assert_eq!(read.extensions, EXT_C | EXT_M);
+}
+
+/// The defined code and data symbols of an object, sorted by address, for turning addresses back
+/// into names.
+pub struct Labels {
+    labels: Vec<(u32, String)>, // (address, name)
+    end: u32, // address just past the end of code-and-data
+}
+
+impl Labels {
+    pub fn new(object: &Object) -> Self {
+        let mut labels: Vec<_> = object.symbols.symbols.iter()
+            .filter_map(|sym| match sym.value {
+                SymbolValue::Code { offset: Some(offset), .. }
+                | SymbolValue::Data { offset: Some(offset), .. } => Some((
+                    object.load_address.wrapping_add(offset),
+                    sym.name(&object.strings).to_owned(),
+                )),
+                _ => None,
+            })
+            .collect();
+        // Stable, so the first of several labels at the same address stays first.
+        labels.sort_by_key(|&(addr, _)| addr);
+        let end = object.load_address.wrapping_add(object.code_and_data.len() as u32);
+        Labels { labels, end }
+    }
+
+    /// The closest label at or before addr, and how far past it addr is.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        if addr >= self.end {
+            return None;
+        }
+        let i = self.labels.partition_point(|&(a, _)| a <= addr);
+        let (label_addr, _) = *self.labels[..i].last()?;
+        let first = self.labels.partition_point(|&(a, _)| a < label_addr);
+        let (_, ref name) = self.labels[first];
+        Some((name, addr - label_addr))
+    }
+
+    /// addr as `label+#offset`, or just `label` if it's right at one.
+    pub fn symbolize(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|(name, offset)| match offset {
+            0 => name.to_owned(),
+            _ => format!("{}+#{:X}", name, offset),
+        })
+    }
+
+    /// Every label at exactly addr, in the order they were defined.
+    pub fn names_at(&self, addr: u32) -> impl Iterator<Item = &str> {
+        let start = self.labels.partition_point(|&(a, _)| a < addr);
+        self.labels[start..].iter()
+            .take_while(move |&&(a, _)| a == addr)
+            .map(|(_, name)| name as &str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.labels.iter().find(|&(_, n)| n == name).map(|&(addr, _)| addr)
+    }
+}
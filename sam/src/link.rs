@@ -0,0 +1,256 @@
+//! Combining sam objects into one, for slink.
+//!
+//! Objects are laid out one after another in the order given, starting at the first one's load
+//! address. Each object's labels stay private to it except for external ones (from .global or
+//! .extern), which are shared by name: a reference to an external label that one object doesn't
+//! define goes to whichever object does. Relocations that end up with a defined target are applied
+//! and dropped, and the rest are kept so the result can be linked again. Absolute ones (.word
+//! label) are applied and kept too, since they have to change if the result moves.
+//!
+//! Members of archives only go in if they define an external label that's still undefined, and
+//! then they go after everything else, so a program can link against a big archive and only get
+//! the routines it uses.
+
+use crate::archive::Archive;
+use crate::{
+    u32_to_hex,
+    Object,
+    Relocation,
+    RelocationTable,
+    RelocationValue,
+    StringTable,
+    SymbolTable,
+    SymbolValue,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// Two objects define the same external label.
+    DuplicateLabel { label: String, first: String, second: String },
+    /// A relocation couldn't be applied, e.g. because its target is too far away.
+    Relocation { object: String, offset: u32, msg: String },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match *self {
+            Self::DuplicateLabel { ref label, ref first, ref second } => {
+                write!(f, "{:?} is defined in both {} and {}", label, first, second)
+            },
+            Self::Relocation { ref object, offset, ref msg } => {
+                write!(f, "{} at {}: {}", object, u32_to_hex(offset), msg)
+            },
+        }
+    }
+}
+
+/// A symbol value from an object that starts at base in the output, with the strings it refers to
+/// moved to the output's string table.
+fn move_value(value: SymbolValue, from: &StringTable, to: &mut StringTable, base: u32)
+    -> SymbolValue
+{
+    let mut copy = |index: u32| to.get_index_or_insert(&from.strings[index as usize].1);
+    match value {
+        SymbolValue::Metadata { value_index } => {
+            SymbolValue::Metadata { value_index: copy(value_index) }
+        },
+        SymbolValue::Code { external, type_index, offset } => SymbolValue::Code {
+            external,
+            type_index: copy(type_index),
+            offset: offset.map(|offset| base + offset),
+        },
+        SymbolValue::Data { external, type_index, offset } => SymbolValue::Data {
+            external,
+            type_index: copy(type_index),
+            offset: offset.map(|offset| base + offset),
+        },
+    }
+}
+
+/// Moves the archive members that the objects need, and the ones those need, onto the end of
+/// objects, named like "ARCHIVE(MEMBER)". Archives are searched in order, so if two members
+/// define the same label the earlier one wins.
+pub fn pull_members(objects: &mut Vec<(String, Object)>, archives: Vec<(String, Archive)>) {
+    let mut members: Vec<_> = archives.into_iter()
+        .flat_map(|(archive_name, archive)| {
+            archive.members.into_iter()
+                .map(move |(name, object)| Some((format!("{}({})", archive_name, name), object)))
+        })
+        .collect();
+    loop {
+        let mut defined = HashSet::new();
+        let mut undefined = HashSet::new();
+        for (_, object) in objects.iter() {
+            for sym in object.symbols.symbols.iter().filter(|sym| sym.is_external()) {
+                let set = if sym.is_defined() { &mut defined } else { &mut undefined };
+                set.insert(sym.name(&object.strings).to_owned());
+            }
+        }
+        let wanted = members.iter().position(|member| member.as_ref().is_some_and(|(_, object)| {
+            object.symbols.symbols.iter().any(|sym| {
+                let label = sym.name(&object.strings);
+                sym.is_external() && sym.is_defined()
+                    && undefined.contains(label) && !defined.contains(label)
+            })
+        }));
+        match wanted {
+            Some(i) => objects.push(members[i].take().unwrap()),
+            None => break,
+        }
+    }
+}
+
+/// Links objects, each with a name for error messages, into one.
+pub fn link(objects: &[(String, Object)]) -> Result<Object, LinkError> {
+    let mut strings: StringTable = Default::default();
+    strings.get_index_or_insert("");
+    let mut symbols: SymbolTable = Default::default();
+
+    // Where each object starts in the output, which is always on a word boundary, the same as
+    // whatever follows compressed instructions in the assembler's output.
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut len: u32 = 0;
+    for (_, object) in objects {
+        bases.push(len);
+        len += (object.code_and_data.len() as u32 + 0b11) & !0b11;
+    }
+
+    // External labels go in first, so looking one up by name finds it rather than some object's
+    // private label that happens to have the same name.
+    let mut definers: HashMap<&str, usize> = HashMap::new();
+    for (i, (name, object)) in objects.iter().enumerate() {
+        for sym in object.symbols.symbols.iter().filter(|sym| sym.is_external()) {
+            let label = sym.name(&object.strings);
+            let name_index = strings.get_index_or_insert(label);
+            if sym.is_defined() {
+                if let Some(&first) = definers.get(label) {
+                    return Err(LinkError::DuplicateLabel {
+                        label: label.to_owned(),
+                        first: objects[first].0.clone(),
+                        second: name.clone(),
+                    });
+                }
+                definers.insert(label, i);
+            } else if symbols.contains_name(name_index) {
+                continue; // either defined already or just as undefined
+            }
+            let value = move_value(sym.value, &object.strings, &mut strings, bases[i]);
+            symbols.insert(name_index, value);
+        }
+    }
+
+    let mut code_and_data = Vec::with_capacity(len as usize);
+    let mut relocations = Vec::new();
+    let mut extensions = 0;
+    for (i, (name, object)) in objects.iter().enumerate() {
+        // This object's symbol indices to the output's.
+        let mut symbol_indices = Vec::with_capacity(object.symbols.symbols.len());
+        for sym in &object.symbols.symbols {
+            let label = sym.name(&object.strings);
+            let index = if sym.is_external() {
+                symbols.get_index_or_insert(strings.get_index_or_insert(label), sym.value)
+            } else {
+                // Not get_index_or_insert, because other objects can have labels with this name.
+                let name_index = strings.insert(label.to_owned());
+                let value = move_value(sym.value, &object.strings, &mut strings, bases[i]);
+                symbols.insert(name_index, value)
+            };
+            symbol_indices.push(index);
+        }
+
+        code_and_data.extend_from_slice(&object.code_and_data);
+        code_and_data.resize(bases.get(i + 1).map_or(len, |&next| next) as usize, 0);
+        for reloc in &object.relocations.relocations {
+            if matches!(reloc.value, RelocationValue::UnusedEntry) {
+                continue;
+            }
+            let malformed = |msg: &str| LinkError::Relocation {
+                object: name.clone(),
+                offset: reloc.offset,
+                msg: msg.to_owned(),
+            };
+            let symbol_index = *symbol_indices.get(reloc.symbol_index as usize)
+                .ok_or_else(|| malformed("no such symbol"))?;
+            let end = reloc.offset.checked_add(reloc.value.insn_size());
+            if end.is_none_or(|end| end as usize > object.code_and_data.len()) {
+                return Err(malformed("past the end of the code and data"));
+            }
+            relocations.push((i, Relocation {
+                offset: bases[i] + reloc.offset,
+                symbol_index,
+                value: reloc.value,
+            }));
+        }
+        extensions |= object.extensions;
+    }
+
+    let load_address = objects.first().map_or(0, |(_, object)| object.load_address);
+    let mut kept = Vec::new();
+    for (i, reloc) in relocations {
+        if !reloc.symbol(&symbols).is_defined() {
+            kept.push(reloc);
+            continue;
+        }
+        if reloc.value.is_absolute() {
+            kept.push(reloc);
+        }
+        let start = reloc.offset as usize;
+        let bytes = &mut code_and_data[start..start + reloc.value.insn_size() as usize];
+        let mut insn = [0; 4];
+        insn[..bytes.len()].copy_from_slice(bytes);
+        let insn = reloc.apply(u32::from_le_bytes(insn), &symbols, load_address)
+            .map_err(|msg| LinkError::Relocation {
+                object: objects[i].0.clone(),
+                offset: reloc.offset - bases[i],
+                msg,
+            })?;
+        let len = bytes.len();
+        bytes.copy_from_slice(&insn.to_le_bytes()[..len]);
+    }
+
+    Ok(Object {
+        load_address,
+        extensions,
+        code_and_data,
+        strings,
+        symbols,
+        relocations: RelocationTable { relocations: kept },
+    })
+}
+
+/// Relocations that point at metadata, at symbols that don't exist, or past the end of the code are
+/// errors, not panics.
+#[test]
+fn test_malformed_relocations() {
+    for (symbol_index, offset, value) in [
+        (0, 0, RelocationValue::RelCodeJType), // metadata
+        (0, 0, RelocationValue::Abs32), // metadata
+        (1, 0, RelocationValue::RelCodeJType),
+        (0, 4, RelocationValue::RelCodeJType),
+        (0, 2, RelocationValue::Abs32),
+    ] {
+        let mut strings: StringTable = Default::default();
+        strings.get_index_or_insert("");
+        let mut symbols: SymbolTable = Default::default();
+        let name_index = strings.get_index_or_insert("version");
+        symbols.insert(name_index, SymbolValue::Metadata { value_index: 0 });
+        let object = Object {
+            load_address: 0x8000_0000,
+            extensions: 0,
+            code_and_data: 0x0000_006Fu32.to_le_bytes().to_vec(), // jal zero #0
+            strings,
+            symbols,
+            relocations: RelocationTable { relocations: vec![
+                Relocation { offset, symbol_index, value },
+            ] },
+        };
+        let result = link(&[("bad.sam".to_owned(), object)]);
+        assert!(
+            matches!(result, Err(LinkError::Relocation { .. })),
+            "{:?}",
+            (symbol_index, offset),
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Intel HEX and Motorola S-record files, which flashing tools take instead of raw images because
+//! they say where each byte goes. scopy writes them.
+
+use std::io::{self, Write};
+
+/// How many data bytes go on each line.
+const RECORD_LEN: usize = 16;
+
+fn write_ihex_record(mut w: impl Write, typ: u8, address: u16, data: &[u8]) -> io::Result<()> {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(typ);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(sum.wrapping_neg());
+    write!(w, ":")?;
+    for b in bytes {
+        write!(w, "{:02X}", b)?;
+    }
+    writeln!(w)
+}
+
+/// Writes data as Intel HEX, starting at address, with a start linear address record if start is
+/// given.
+pub fn write_ihex(mut w: impl Write, address: u32, data: &[u8], start: Option<u32>)
+    -> io::Result<()>
+{
+    let mut upper = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let here = address.wrapping_add(offset as u32);
+        if upper != Some(here >> 16) {
+            upper = Some(here >> 16);
+            write_ihex_record(&mut w, 0x04, 0, &((here >> 16) as u16).to_be_bytes())?;
+        }
+        // A record can't run past the end of its 64 KiB.
+        let len = RECORD_LEN.min(data.len() - offset).min(0x1_0000 - (here & 0xFFFF) as usize);
+        write_ihex_record(&mut w, 0x00, here as u16, &data[offset..offset + len])?;
+        offset += len;
+    }
+    if let Some(start) = start {
+        write_ihex_record(&mut w, 0x05, 0, &start.to_be_bytes())?;
+    }
+    write_ihex_record(&mut w, 0x01, 0, &[])
+}
+
+fn write_srec_record(mut w: impl Write, typ: u8, address: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(address);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    bytes.push(!sum);
+    write!(w, "S{}", typ)?;
+    for b in bytes {
+        write!(w, "{:02X}", b)?;
+    }
+    writeln!(w)
+}
+
+/// Writes data as Motorola S-records with 32-bit addresses (S3), starting at address. The S7
+/// record at the end says where to start, or 0 if start isn't given.
+pub fn write_srec(mut w: impl Write, address: u32, data: &[u8], start: Option<u32>)
+    -> io::Result<()>
+{
+    write_srec_record(&mut w, 0, &[0, 0], &[])?;
+    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+        let here = address.wrapping_add((i * RECORD_LEN) as u32);
+        write_srec_record(&mut w, 3, &here.to_be_bytes(), chunk)?;
+    }
+    write_srec_record(&mut w, 7, &start.unwrap_or(0).to_be_bytes(), &[])
+}
+
+#[test]
+fn test_ihex() {
+    // Crosses a 64 KiB boundary.
+    let data = [0x13, 0, 0, 0, 0x6F, 0, 0, 0, 0x73, 0, 0x50, 0x10];
+    let mut out = Vec::new();
+    write_ihex(&mut out, 0x8000_FFF8, &data, Some(0x8000_FFF8)).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+:0200000480007A
+:08FFF800130000006F0000007F
+:02000004800179
+:040000007300501029
+:040000058000FFF880
+:00000001FF
+");
+}
+
+#[test]
+fn test_srec() {
+    let mut out = Vec::new();
+    write_srec(&mut out, 0x8000_0000, &[0x13, 0, 0, 0], None).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+S0030000FC
+S309800000001300000063
+S70500000000FA
+");
+}
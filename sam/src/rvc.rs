@@ -0,0 +1,269 @@
+//! The C extension: 16-bit encodings of common instructions.
+//!
+//! A compressed instruction is passed around as a u32 with only the low 16 bits set. Its low two
+//! bits are never 0b11, which is how it's told apart from the start of a 32-bit instruction.
+
+use crate::insn::*;
+
+/// Where the bits of a compressed instruction's immediate go: entry i is the bit of the immediate
+/// stored in bit 12 - i of the instruction, or `__` if that bit isn't part of the immediate. They
+/// read left to right the same way as the tables in the spec.
+pub type Layout = [u8; 11];
+
+const __: u8 = u8::MAX;
+
+/// c.addi4spn
+pub const CIW: Layout = [5, 4, 9, 8, 7, 6, 2, 3, __, __, __];
+/// c.lw and c.sw
+pub const CL: Layout = [5, 4, 3, __, __, __, 2, 6, __, __, __];
+/// c.addi, c.li, c.lui, c.andi, and the shifts
+pub const CI: Layout = [5, __, __, __, __, __, 4, 3, 2, 1, 0];
+pub const ADDI16SP: Layout = [9, __, __, __, __, __, 4, 6, 8, 7, 5];
+pub const LWSP: Layout = [5, __, __, __, __, __, 4, 3, 2, 7, 6];
+pub const SWSP: Layout = [5, 4, 3, 2, 7, 6, __, __, __, __, __];
+/// c.j and c.jal
+pub const CJ: Layout = [11, 4, 9, 8, 10, 6, 7, 3, 2, 1, 5];
+/// c.beqz and c.bnez
+pub const CB: Layout = [8, 4, 3, __, __, __, 7, 6, 2, 1, 5];
+
+/// The immediate of a compressed instruction, zero-extended.
+pub fn imm(half: u32, layout: &Layout) -> u32 {
+    layout.iter().enumerate()
+        .filter(|&(_, &bit)| bit != __)
+        .map(|(i, &bit)| ((half >> (12 - i)) & 1) << bit)
+        .sum()
+}
+
+/// The instruction bits for an immediate. Bits of imm that the layout doesn't have are dropped,
+/// so check the range first.
+pub fn put_imm(imm: u32, layout: &Layout) -> u32 {
+    layout.iter().enumerate()
+        .filter(|&(_, &bit)| bit != __)
+        .map(|(i, &bit)| ((imm >> bit) & 1) << (12 - i))
+        .sum()
+}
+
+/// Sign-extend the low width bits of x.
+pub fn sext(x: u32, width: u32) -> u32 {
+    ((x << (32 - width)) as i32 >> (32 - width)) as u32
+}
+
+/// Whether a register fits in the 3-bit register fields, which only reach x8-x15.
+pub fn is_creg(r: u32) -> bool {
+    (8..16).contains(&r)
+}
+
+fn i_type(opcode: u32, funct3: u32, rd: u32, rs1: u32, imm: u32) -> u32 {
+    (imm << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn r_type(funct3: u32, funct7: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0x33
+}
+
+fn sw(rs2: u32, rs1: u32, imm: u32) -> u32 {
+    ((imm >> 5) << 25) | (rs2 << 20) | (rs1 << 15) | (2 << 12) | ((imm & 0x1F) << 7) | 0x23
+}
+
+fn branch(funct3: u32, rs1: u32, imm: u32) -> u32 {
+    ((imm >> 12 & 1) << 31) | ((imm >> 5 & 0x3F) << 25) | (rs1 << 15) | (funct3 << 12)
+        | ((imm >> 1 & 0xF) << 8) | ((imm >> 11 & 1) << 7) | 0x63
+}
+
+fn jal(rd: u32, imm: u32) -> u32 {
+    ((imm >> 20 & 1) << 31) | ((imm >> 1 & 0x3FF) << 21) | ((imm >> 11 & 1) << 20)
+        | (imm & 0xF_F000) | (rd << 7) | 0x6F
+}
+
+/// The 32-bit instruction a compressed one stands for, or None if it's reserved, illegal, or
+/// needs an extension sam doesn't have (like the floating-point loads and stores). HINTs expand
+/// to the instructions they're encoded as, which don't do anything.
+pub fn expand(half: u32) -> Option<u32> {
+    let rd = (half >> 7) & 0x1F; // also rs1
+    let rs2 = (half >> 2) & 0x1F;
+    let rd_ = 8 + ((half >> 2) & 0x7); // rd' or rs2'
+    let rs1_ = 8 + ((half >> 7) & 0x7); // rs1' or rd'
+    let ci = imm(half, &CI);
+    Some(match (half & 0b11, half >> 13) {
+        (0b00, 0b000) => match imm(half, &CIW) {
+            0 => return None, // including the all-zeros illegal instruction
+            imm => i_type(0x13, 0, rd_, 2, imm), // c.addi4spn
+        },
+        (0b00, 0b010) => i_type(0x03, 2, rd_, rs1_, imm(half, &CL)), // c.lw
+        (0b00, 0b110) => sw(rd_, rs1_, imm(half, &CL)), // c.sw
+        (0b01, 0b000) => i_type(0x13, 0, rd, rd, sext(ci, 6)), // c.addi, c.nop
+        (0b01, 0b001) => jal(1, sext(imm(half, &CJ), 12)), // c.jal
+        (0b01, 0b010) => i_type(0x13, 0, rd, 0, sext(ci, 6)), // c.li
+        (0b01, 0b011) if rd == 2 => match sext(imm(half, &ADDI16SP), 10) {
+            0 => return None,
+            imm => i_type(0x13, 0, 2, 2, imm), // c.addi16sp
+        },
+        (0b01, 0b011) => match sext(ci, 6) {
+            0 => return None,
+            imm => (imm << 12) | (rd << 7) | 0x37, // c.lui
+        },
+        (0b01, 0b100) => match (half >> 10) & 0b11 {
+            // shamt[5] must be 0 in RV32.
+            0b00 | 0b01 if ci & 0x20 != 0 => return None,
+            0b00 => i_type(0x13, 5, rs1_, rs1_, ci), // c.srli
+            0b01 => i_type(0x13, 5, rs1_, rs1_, ci | 0x400), // c.srai
+            0b10 => i_type(0x13, 7, rs1_, rs1_, sext(ci, 6)), // c.andi
+            _ if half & 0x1000 != 0 => return None, // RV64's c.subw and c.addw
+            _ => {
+                let ops = [(0, 0x20), (4, 0), (6, 0), (7, 0)];
+                let (funct3, funct7) = ops[(half as usize >> 5) & 0b11];
+                r_type(funct3, funct7, rs1_, rs1_, rd_) // c.sub, c.xor, c.or, c.and
+            },
+        },
+        (0b01, 0b101) => jal(0, sext(imm(half, &CJ), 12)), // c.j
+        (0b01, 0b110) => branch(0, rs1_, sext(imm(half, &CB), 9)), // c.beqz
+        (0b01, 0b111) => branch(1, rs1_, sext(imm(half, &CB), 9)), // c.bnez
+        (0b10, 0b000) if ci & 0x20 == 0 => i_type(0x13, 1, rd, rd, ci), // c.slli
+        (0b10, 0b010) if rd != 0 => i_type(0x03, 2, rd, 2, imm(half, &LWSP)), // c.lwsp
+        (0b10, 0b100) => match (half >> 12 & 1, rd, rs2) {
+            (0, 0, 0) => return None,
+            (0, _, 0) => i_type(0x67, 0, 0, rd, 0), // c.jr
+            (0, _, _) => r_type(0, 0, rd, 0, rs2), // c.mv
+            (1, 0, 0) => 0x0010_0073, // c.ebreak
+            (1, _, 0) => i_type(0x67, 0, 1, rd, 0), // c.jalr
+            (_, _, _) => r_type(0, 0, rd, rd, rs2), // c.add
+        },
+        (0b10, 0b110) => sw(rs2, 2, imm(half, &SWSP)), // c.swsp
+        _ => return None,
+    })
+}
+
+/// The compressed form of a 32-bit instruction, if it has one that expands back to exactly it.
+/// HINT encodings are never chosen.
+pub fn compress(insn: u32) -> Option<u32> {
+    let (rd, rs1, rs2) = (rd(insn), rs1(insn), rs2(insn));
+    let fits = |imm: u32, width| sext(imm, width) == imm;
+    let i = imm_i(insn);
+    match (opcode(insn), funct3(insn)) {
+        (0x13, 0) => {
+            if insn == 0x0000_0013 {
+                Some(0x0001) // c.nop
+            } else if rd == rs1 && rd != 0 && i != 0 && fits(i, 6) {
+                Some(0x0001 | (rd << 7) | put_imm(i, &CI)) // c.addi
+            } else if rd == 2 && rs1 == 2 && i != 0 && i & 0xF == 0 && fits(i, 10) {
+                Some(0x6101 | put_imm(i, &ADDI16SP))
+            } else if rs1 == 0 && rd != 0 && fits(i, 6) {
+                Some(0x4001 | (rd << 7) | put_imm(i, &CI)) // c.li
+            } else if rs1 == 2 && is_creg(rd) && i != 0 && i & 0b11 == 0 && i < 0x400 {
+                Some(((rd - 8) << 2) | put_imm(i, &CIW)) // c.addi4spn
+            } else {
+                None
+            }
+        },
+        (0x37, _) => {
+            let imm = (insn as i32 >> 12) as u32;
+            if rd != 0 && rd != 2 && imm != 0 && fits(imm, 6) {
+                Some(0x6001 | (rd << 7) | put_imm(imm, &CI)) // c.lui
+            } else {
+                None
+            }
+        },
+        (0x13, 1) if funct7(insn) == 0 && rd == rs1 && rd != 0 && rs2 != 0 => {
+            Some(0x0002 | (rd << 7) | put_imm(rs2, &CI)) // c.slli
+        },
+        (0x13, 5) if rd == rs1 && is_creg(rd) && rs2 != 0 => match funct7(insn) {
+            0x00 => Some(0x8001 | ((rd - 8) << 7) | put_imm(rs2, &CI)), // c.srli
+            0x20 => Some(0x8401 | ((rd - 8) << 7) | put_imm(rs2, &CI)), // c.srai
+            _ => None,
+        },
+        (0x13, 7) if rd == rs1 && is_creg(rd) && fits(i, 6) => {
+            Some(0x8801 | ((rd - 8) << 7) | put_imm(i, &CI)) // c.andi
+        },
+        (0x33, 0) if funct7(insn) == 0 && rd != 0 && rs2 != 0 && rs1 == rd => {
+            Some(0x9002 | (rd << 7) | (rs2 << 2)) // c.add
+        },
+        (0x33, 0) if funct7(insn) == 0 && rd != 0 && rs2 != 0 && rs1 == 0 => {
+            Some(0x8002 | (rd << 7) | (rs2 << 2)) // c.mv
+        },
+        (0x33, f3) if rd == rs1 && is_creg(rd) && is_creg(rs2) => {
+            let op = match (f3, funct7(insn)) {
+                (0, 0x20) => 0, // c.sub
+                (4, 0x00) => 1, // c.xor
+                (6, 0x00) => 2, // c.or
+                (7, 0x00) => 3, // c.and
+                _ => return None,
+            };
+            Some(0x8C01 | ((rd - 8) << 7) | (op << 5) | ((rs2 - 8) << 2))
+        },
+        (0x03, 2) if i & 0b11 == 0 => {
+            if rs1 == 2 && rd != 0 && i < 0x100 {
+                Some(0x4002 | (rd << 7) | put_imm(i, &LWSP)) // c.lwsp
+            } else if is_creg(rd) && is_creg(rs1) && i < 0x80 {
+                Some(0x4000 | ((rs1 - 8) << 7) | ((rd - 8) << 2) | put_imm(i, &CL)) // c.lw
+            } else {
+                None
+            }
+        },
+        (0x23, 2) if imm_s(insn) & 0b11 == 0 => {
+            let s = imm_s(insn);
+            if rs1 == 2 && s < 0x100 {
+                Some(0xC002 | (rs2 << 2) | put_imm(s, &SWSP)) // c.swsp
+            } else if is_creg(rs2) && is_creg(rs1) && s < 0x80 {
+                Some(0xC000 | ((rs1 - 8) << 7) | ((rs2 - 8) << 2) | put_imm(s, &CL)) // c.sw
+            } else {
+                None
+            }
+        },
+        (0x6F, _) if rd <= 1 && fits(imm_j(insn), 12) => {
+            let base = if rd == 0 { 0xA001 } else { 0x2001 }; // c.j, c.jal
+            Some(base | put_imm(imm_j(insn), &CJ))
+        },
+        (0x67, 0) if rd <= 1 && rs1 != 0 && i == 0 => {
+            let base = if rd == 0 { 0x8002 } else { 0x9002 }; // c.jr, c.jalr
+            Some(base | (rs1 << 7))
+        },
+        (0x63, f3 @ (0 | 1)) if rs2 == 0 && is_creg(rs1) && fits(imm_b(insn), 9) => {
+            let base = if f3 == 0 { 0xC001 } else { 0xE001 }; // c.beqz, c.bnez
+            Some(base | ((rs1 - 8) << 7) | put_imm(imm_b(insn), &CB))
+        },
+        (0x73, 0) if insn == 0x0010_0073 => Some(0x9002), // c.ebreak
+        _ => None,
+    }
+}
+
+#[test]
+fn test_expand() {
+    // c.addi4spn a0 #10
+    assert_eq!(expand(0x0808), Some(0x0101_0513));
+    // c.lw a0 a1 #4
+    assert_eq!(expand(0x41C8), Some(0x0045_A503));
+    // c.j -#2
+    assert_eq!(expand(0xBFFD), Some(0xFFFF_F06F));
+    // c.beqz a0 #8
+    assert_eq!(expand(0xC501), Some(0x0005_0463));
+    // c.jr ra
+    assert_eq!(expand(0x8082), Some(0x0000_8067));
+    assert_eq!(expand(0x0000), None);
+}
+
+/// Every 16-bit instruction expands to something that compresses back to an instruction that
+/// does the same thing, and compressing never changes what an instruction does.
+#[test]
+fn test_compress() {
+    for half in 0..0x1_0000 {
+        if half & 0b11 == 0b11 {
+            continue;
+        }
+        if let Some(insn) = expand(half) {
+            if let Some(again) = compress(insn) {
+                assert_eq!(
+                    expand(again),
+                    Some(insn),
+                    "{:#06x} -> {:#010x} -> {:#06x}",
+                    half,
+                    insn,
+                    again,
+                );
+            }
+        }
+    }
+    // addi sp sp -#40 doesn't fit c.addi but does fit c.addi16sp.
+    assert_eq!(compress(0xFC01_0113), Some(0x7139));
+    // Jumps too far for c.j stay 32-bit.
+    assert_eq!(compress(0x0010_006F), None);
+}
@@ -0,0 +1,280 @@
+//! Link a program against a separately assembled library with slink, directly or from an archive
+//! made with sar, and run the result with srun.
+
+use sam::Object;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const LOAD_ADDRESS: &str = "#8000'0000";
+
+const MAIN: &str = "\
+.extern putc shutdown
+$start
+            li a0 #48
+            call putc
+            li a0 #69
+            jal ra putc
+            li a0 #0A
+            call putc
+            tail shutdown
+$spin
+            inval
+";
+
+/// Compressed, so the linker has to keep main.s's code lined up after it.
+const LIB: &str = "\
+.global putc shutdown
+$putc
+            lui t0 #1000'0
+            sb a0 t0 #0
+            ret
+$shutdown
+            lui t1 #100
+            li t0 #5555
+            sw t0 t1 #0
+$spin
+            j spin
+";
+
+const UNUSED: &str = "\
+.global unused
+$unused
+            ret
+";
+
+/// Prints through a pointer that GREETING keeps in a data word, which it finds by its absolute
+/// address.
+const PRINT: &str = "\
+.extern greeting putc shutdown
+$start
+            lui t0 %hi(greeting)
+            lw s0 t0 %lo(greeting)
+$loop
+            lbu a0 s0 #0
+            beqz a0 done
+            call putc
+            addi s0 s0 #1
+            j loop
+$done
+            tail shutdown
+";
+
+const GREETING: &str = "\
+.global greeting
+$greeting
+            .word letters
+$letters
+            .byte #48 #69 #0A #00
+";
+
+fn tmp_dir(test: &str) -> PathBuf {
+    let tmp = env::temp_dir().join(format!("sam-test-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&tmp).unwrap();
+    tmp
+}
+
+fn assemble(tmp: &Path, name: &str, source: &str, compress: bool) -> PathBuf {
+    let source_path = tmp.join(format!("{}.s", name));
+    let object_path = tmp.join(format!("{}.sam", name));
+    fs::write(&source_path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_sam"))
+        .args(compress.then_some("--compress"))
+        .arg(LOAD_ADDRESS)
+        .arg(&source_path)
+        .arg(&object_path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "couldn't assemble {}:\n{}",
+        name,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    object_path
+}
+
+fn slink(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_slink")).args(args).output().unwrap()
+}
+
+#[test]
+fn link_and_run() {
+    let tmp = tmp_dir("link");
+    let main = assemble(&tmp, "main", MAIN, false);
+    let lib = assemble(&tmp, "lib", LIB, true);
+
+    // Not linked yet, so srun shouldn't try.
+    let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(&main).output().unwrap();
+    assert!(!output.status.success());
+
+    let linked = tmp.join("linked.sam");
+    let output = slink(&[&main, &lib, &linked]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(&linked).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hi\n");
+
+    let output = slink(&[Path::new("--bios"), &main, &lib, &tmp.join("linked.bin")]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
+
+/// slink --elf makes an executable that srun runs when everything is linked, and a relocatable
+/// file otherwise, which sconv turns back into an object.
+#[test]
+fn link_elf() {
+    let tmp = tmp_dir("link-elf");
+    let main = assemble(&tmp, "main", MAIN, false);
+    let lib = assemble(&tmp, "lib", LIB, true);
+    let srun = |path: &Path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(path).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Hi\n");
+    };
+
+    let exec = tmp.join("linked.elf");
+    let output = slink(&[Path::new("--elf"), &main, &lib, &exec]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let elf = fs::read(&exec).unwrap();
+    assert_eq!((&elf[..4], elf[0x10]), (&b"\x7FELF"[..], 2)); // ET_EXEC
+    srun(&exec);
+
+    let rel = tmp.join("main.elf");
+    let output = slink(&[Path::new("--elf"), &main, &rel]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(&rel).unwrap()[0x10], 1); // ET_REL
+    let converted = tmp.join("converted.sam");
+    let output = Command::new(env!("CARGO_BIN_EXE_sconv"))
+        .args([Path::new(LOAD_ADDRESS), &rel, &converted])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let linked = tmp.join("linked.sam");
+    let output = slink(&[&converted, &lib, &linked]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    srun(&linked);
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
+
+/// An absolute address in an object that doesn't come first has to follow it, including through
+/// ELF and back.
+#[test]
+fn link_data() {
+    let tmp = tmp_dir("link-data");
+    let print = assemble(&tmp, "print", PRINT, false);
+    let greeting = assemble(&tmp, "greeting", GREETING, false);
+    let lib = assemble(&tmp, "lib", LIB, true);
+    let srun = |path: &Path| {
+        let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(path).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "Hi\n");
+    };
+
+    let linked = tmp.join("linked.sam");
+    let output = slink(&[&print, &greeting, &lib, &linked]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    srun(&linked);
+
+    let rel = tmp.join("greeting.elf");
+    let output = Command::new(env!("CARGO_BIN_EXE_sam"))
+        .args([Path::new("--elf"), Path::new(LOAD_ADDRESS), &tmp.join("greeting.s"), &rel])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let converted = tmp.join("converted.sam");
+    let output = Command::new(env!("CARGO_BIN_EXE_sconv"))
+        .args([Path::new(LOAD_ADDRESS), &rel, &converted])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = slink(&[&print, &converted, &lib, &linked]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    srun(&linked);
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
+
+#[test]
+fn link_from_archive() {
+    let tmp = tmp_dir("link-archive");
+    let main = assemble(&tmp, "main", MAIN, false);
+    let lib = assemble(&tmp, "lib", LIB, false);
+    let unused = assemble(&tmp, "unused", UNUSED, false);
+    let archive = tmp.join("lib.sa");
+    let output = Command::new(env!("CARGO_BIN_EXE_sar"))
+        .args([&archive, &unused, &lib])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sar"))
+        .arg("--list")
+        .arg(&archive)
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "unused.sam: unused\nlib.sam: putc shutdown\n",
+    );
+
+    let linked = tmp.join("linked.sam");
+    let output = slink(&[&main, &archive, &linked]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let f = fs::File::open(&linked).unwrap();
+    let object = Object::deserialize(io::BufReader::new(f)).unwrap();
+    let names: Vec<_> = object.symbols.symbols.iter()
+        .map(|sym| sym.name(&object.strings))
+        .collect();
+    assert!(names.contains(&"putc") && !names.contains(&"unused"), "{:?}", names);
+    let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(&linked).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hi\n");
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
+
+#[test]
+fn link_errors() {
+    let tmp = tmp_dir("link-errors");
+    let main = assemble(&tmp, "main", MAIN, false);
+    let lib = assemble(&tmp, "lib", LIB, false);
+
+    let output = slink(&[&main, &lib, &lib, &tmp.join("twice.sam")]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\"putc\" is defined in both"));
+
+    let bios = tmp.join("main.bin");
+    let output = slink(&[Path::new("--bios"), &main, &bios]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no object defines putc shutdown"));
+    assert!(!bios.exists());
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
+
+/// srun won't run an object that needs an extension the hart doesn't have, which the header says.
+#[test]
+fn srun_checks_extensions() {
+    let tmp = tmp_dir("extensions");
+    let lib = assemble(&tmp, "lib", LIB, true);
+    let f = fs::File::open(&lib).unwrap();
+    let mut object = Object::deserialize(io::BufReader::new(f)).unwrap();
+    assert_eq!(object.extensions, sam::EXT_C);
+
+    object.extensions |= 1 << 5; // F
+    let needs_f = tmp.join("needs-f.sam");
+    object.serialize(fs::File::create(&needs_f).unwrap()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_srun")).arg(&needs_f).output().unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("extensions this hart doesn't have: F"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    fs::remove_dir_all(&tmp).unwrap();
+}
@@ -0,0 +1,226 @@
+//! Assemble each sample program, link it against an archive of the shared routines in lib, run it
+//! in the emulator, and compare what it sends to the UART and how it exits against the files in
+//! tests/golden:
+//!
+//! - NAME.in (optional) is fed to the UART before the program starts.
+//! - NAME.out is everything the program should send to the UART.
+//! - NAME.status is the exit status it should report to the test finisher, or "running" if it
+//!   should still be going when it runs out of steps.
+//!
+//! Each program also runs assembled with --compress, which shouldn't change anything it does
+//! unless it works out addresses by counting instructions.
+//!
+//! The clock only moves one tick per step, or straight to the next timer interrupt when the hart is
+//! in wfi, so every run is the same. Run with SAM_BLESS=1 to write the golden files from the
+//! current output instead of checking it.
+
+use sam::emu::{pci, virt, virtio, Clock, Halt, Hart, Step};
+use sam::Object;
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+const LOAD_ADDRESS: &str = "#8000'0000";
+
+/// Programs that jump into tables or past instructions by counting them, which only works if every
+/// instruction is 4 bytes.
+const NOT_COMPRESSIBLE: &[&str] = &["interrupt-clock", "trap-test"];
+
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn crate_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn run_tool(exe: &str, args: &[&OsStr]) {
+    let output = Command::new(exe).args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{} {:?} failed:\n{}",
+        exe,
+        args,
+        String::from_utf8_lossy(&output.stderr),
+    );
+}
+
+fn assemble_to(source: &Path, object: &Path, compress: bool) {
+    let mut args = vec![OsStr::new(LOAD_ADDRESS), source.as_os_str(), object.as_os_str()];
+    if compress {
+        args.insert(0, OsStr::new("--compress"));
+    }
+    run_tool(env!("CARGO_BIN_EXE_sam"), &args);
+}
+
+/// Assembles the program and links it against an archive of everything in lib.
+fn assemble(name: &str, compress: bool) -> Object {
+    let suffix = if compress { ".c" } else { "" };
+    let tmp = env::temp_dir()
+        .join(format!("sam-test-{}-{}{}", std::process::id(), name, suffix));
+    fs::create_dir_all(&tmp).unwrap();
+
+    let program = tmp.join("program.sam");
+    assemble_to(&crate_dir().join(format!("{}.s", name)), &program, compress);
+    let archive = tmp.join("lib.sa");
+    let mut sar_args = vec![archive.clone().into_os_string()];
+    for entry in fs::read_dir(crate_dir().join("lib")).unwrap() {
+        let source = entry.unwrap().path();
+        let object = tmp.join(source.with_extension("sam").file_name().unwrap());
+        assemble_to(&source, &object, compress);
+        sar_args.push(object.into_os_string());
+    }
+    let sar_args: Vec<_> = sar_args.iter().map(|arg| arg.as_os_str()).collect();
+    run_tool(env!("CARGO_BIN_EXE_sar"), &sar_args);
+    let linked = tmp.join("linked.sam");
+    run_tool(
+        env!("CARGO_BIN_EXE_slink"),
+        &[program.as_os_str(), archive.as_os_str(), linked.as_os_str()],
+    );
+
+    let f = fs::File::open(&linked).unwrap();
+    let object_data = Object::deserialize(io::BufReader::new(f)).unwrap();
+    fs::remove_dir_all(&tmp).unwrap();
+    object_data
+}
+
+/// Returns the UART output and the exit status.
+fn run(object: &Object, input: &[u8], max_steps: u64, mut config: virt::Config)
+    -> (Vec<u8>, String)
+{
+    let (uart_tx, uart_rx) = mpsc::channel();
+    for &b in input {
+        uart_tx.send(b).unwrap();
+    }
+    let out = SharedBuf::default();
+    let clock = Clock::manual();
+    config.clock = clock.clone();
+    let mut bus = virt::bus(uart_rx, Box::new(out.clone()), config);
+    bus.load_image(object.load_address, &object.code_and_data).unwrap();
+    let mut hart = Hart::new(object.load_address);
+
+    let mut status = "running".to_owned();
+    for _ in 0..max_steps {
+        match hart.step(&mut bus) {
+            Ok(Step::Waiting) => match bus.deadline() {
+                Some(deadline) => clock.advance(deadline.saturating_sub(clock.mtime()).max(1)),
+                None => break, // nothing will ever wake it up
+            },
+            Ok(_) => clock.advance(1),
+            Err(e) => panic!("exception in trap handler at {:#x}: {}", hart.pc, e),
+        }
+        match bus.halt.take() {
+            None => (),
+            Some(Halt::Pass) => {
+                status = "0".to_owned();
+                break;
+            },
+            Some(Halt::Fail(code)) => {
+                status = code.to_string();
+                break;
+            },
+            Some(Halt::Reset) => {
+                bus.load_image(object.load_address, &object.code_and_data).unwrap();
+                hart = Hart::new(object.load_address);
+            },
+        }
+    }
+    let uart = out.0.borrow().clone();
+    (uart, status)
+}
+
+fn check(name: &str, max_steps: u64, config: impl Fn() -> virt::Config) {
+    check_one(name, max_steps, config(), false);
+    if !NOT_COMPRESSIBLE.contains(&name) {
+        check_one(name, max_steps, config(), true);
+    }
+}
+
+fn check_one(name: &str, max_steps: u64, config: virt::Config, compress: bool) {
+    let golden = crate_dir().join("tests").join("golden");
+    let path = |ext: &str| golden.join(format!("{}.{}", name, ext));
+    let input = fs::read(path("in")).unwrap_or_default();
+
+    let object = assemble(name, compress);
+    let (uart, status) = run(&object, &input, max_steps, config);
+    let name = if compress { format!("{} (compressed)", name) } else { name.to_owned() };
+
+    if env::var_os("SAM_BLESS").is_some() {
+        if compress {
+            return;
+        }
+        fs::create_dir_all(&golden).unwrap();
+        fs::write(path("out"), &uart).unwrap();
+        fs::write(path("status"), format!("{}\n", status)).unwrap();
+        return;
+    }
+    let read = |p: &Path| fs::read(p).unwrap_or_else(|e| panic!("{}: {}", p.display(), e));
+    let expected_uart = read(&path("out"));
+    let expected_status = String::from_utf8(read(&path("status"))).unwrap();
+    assert!(
+        uart == expected_uart,
+        "UART output of {} differs:\n--- expected\n{}\n--- actual\n{}",
+        name,
+        String::from_utf8_lossy(&expected_uart),
+        String::from_utf8_lossy(&uart),
+    );
+    assert_eq!(status, expected_status.trim(), "exit status of {}", name);
+}
+
+macro_rules! programs {
+    ($($(#[$attr:meta])* $name:ident, $max_steps:expr, $config:expr;)*) => {
+        $(
+            #[test]
+            $(#[$attr])*
+            fn $name() {
+                check(&stringify!($name).replace('_', "-"), $max_steps, || $config);
+            }
+        )*
+
+        const PROGRAMS: &[&str] = &[$(stringify!($name)),*];
+    };
+}
+
+programs! {
+    #[ignore = "uses %label, which the assembler doesn't support"]
+    banner, 0x10_0000, Default::default();
+    clock, 0xA0_0000, Default::default();
+    echo, 0x1_0000, Default::default();
+    interrupt_clock, 0x2000, Default::default();
+    list_pci, 0x10_0000, virt::Config {
+        pci_functions: vec![pci::Function::new(0x1B36, 0x000D, 0x0C_03_30, 0)],
+        ..Default::default()
+    };
+    list_virtio, 0x10_0000, virt::Config {
+        virtio_devices: vec![Box::new(virtio::Console::new(Box::new(io::sink())))],
+        ..Default::default()
+    };
+    trap_test, 0x10_0000, Default::default();
+}
+
+#[test]
+fn every_program_is_tested() {
+    for entry in fs::read_dir(crate_dir()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "s") {
+            let name = path.file_stem().unwrap().to_str().unwrap().replace('-', "_");
+            assert!(PROGRAMS.contains(&&*name), "{} isn't in programs!", path.display());
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! `sdis --reassemblable` on each sample program and each file in lib should give back source that
+//! assembles into the same code and data, whether or not it was assembled with --compress.
+
+use sam::Object;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const LOAD_ADDRESS: &str = "#8000'0000";
+
+/// Returns None if the assembler fails.
+fn assemble(source: &Path, object: &Path, compress: bool) -> Option<Object> {
+    let status = Command::new(env!("CARGO_BIN_EXE_sam"))
+        .args(compress.then_some("--compress"))
+        .arg(LOAD_ADDRESS)
+        .arg(source)
+        .arg(object)
+        .output()
+        .unwrap()
+        .status;
+    if !status.success() {
+        return None;
+    }
+    let f = fs::File::open(object).unwrap();
+    Some(Object::deserialize(io::BufReader::new(f)).unwrap())
+}
+
+#[test]
+fn reassemble_programs() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let tmp = env::temp_dir().join(format!("sam-test-reassemble-{}", std::process::id()));
+    fs::create_dir_all(&tmp).unwrap();
+
+    let mut count = 0;
+    let lib_entries = fs::read_dir(crate_dir.join("lib")).unwrap();
+    for entry in fs::read_dir(&crate_dir).unwrap().chain(lib_entries) {
+        let source = entry.unwrap().path();
+        if source.extension().is_none_or(|ext| ext != "s") {
+            continue;
+        }
+        for compress in [false, true] {
+            let stem = source.file_stem().unwrap().to_str().unwrap();
+            let name = if compress { format!("{}.c", stem) } else { stem.to_owned() };
+            let object_path = tmp.join(format!("{}.sam", name));
+            // The programs test complains about programs that don't assemble.
+            let object = match assemble(&source, &object_path, compress) {
+                Some(object) => object,
+                None => continue,
+            };
+
+            let output = Command::new(env!("CARGO_BIN_EXE_sdis"))
+                .arg("--reassemblable")
+                .arg(&object_path)
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "couldn't disassemble {}:\n{}",
+                name,
+                String::from_utf8_lossy(&output.stderr),
+            );
+            let disassembly_path = tmp.join(format!("{}.s", name));
+            fs::write(&disassembly_path, &output.stdout).unwrap();
+            // The disassembly says which instructions are compressed, so no --compress this time.
+            let again_path = tmp.join(format!("{}.again.sam", name));
+            let again = assemble(&disassembly_path, &again_path, false)
+                .unwrap_or_else(|| panic!("couldn't reassemble {}", name));
+            assert!(again.code_and_data == object.code_and_data, "{} changed", name);
+            count += 1;
+        }
+    }
+    fs::remove_dir_all(&tmp).unwrap();
+    assert!(count > 0);
+}